@@ -0,0 +1,93 @@
+//! Connects a number of headless bots that wander around randomly, useful for load testing
+//!
+//! `cargo run --example bot -- [address] [--bots=N] [--token=TOKEN]`
+
+use anyhow::Context as _;
+use multiplayer_game::{
+    client::{
+        Circle, Client, ClientConfig, ClientToServerMessage, ConnectionState, Shape,
+        SPAWN_CIRCLE_ID,
+    },
+    logging,
+};
+use nanorand::{Rng as _, WyRand};
+use std::{net::SocketAddr, ops::ControlFlow, time::Duration};
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:1234";
+const DEFAULT_BOTS: usize = 10;
+const UPDATE_INTERVAL: Duration = Duration::from_millis(50);
+const MAX_STEP: f32 = 0.1;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    logging::init();
+    let address = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+    let addr: SocketAddr = address
+        .parse()
+        .with_context(|| format!("`{address}` is not a valid socket address"))?;
+    let bots = match std::env::args().find_map(|arg| arg.strip_prefix("--bots=").map(str::to_owned))
+    {
+        Some(bots) => bots
+            .parse()
+            .with_context(|| format!("`{bots}` is not a valid number of bots"))?,
+        None => DEFAULT_BOTS,
+    };
+    let config = ClientConfig {
+        auth_token: std::env::args()
+            .find_map(|arg| arg.strip_prefix("--token=").map(str::to_owned)),
+        ..Default::default()
+    };
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for index in 0..bots {
+        let config = config.clone();
+        tasks.spawn(async move { (index, run_bot(index, addr, config).await) });
+    }
+    while let Some(result) = tasks.join_next().await {
+        match result? {
+            (index, Ok(state)) => tracing::info!(bot = index, ?state, "stopped"),
+            (index, Err(error)) => tracing::error!(bot = index, %error, "failed"),
+        }
+    }
+    Ok(())
+}
+
+async fn run_bot(
+    index: usize,
+    addr: SocketAddr,
+    config: ClientConfig,
+) -> anyhow::Result<ConnectionState> {
+    let mut client = Client::connect_with_config(addr, config).await?;
+    client.send_message(ClientToServerMessage::SetName(format!("Bot {index}")))?;
+
+    let mut rng = WyRand::new();
+    let mut random = move || rng.generate::<f32>();
+    let mut circle = Circle {
+        position: client.spawn_position(),
+        color: cgmath::vec4(random(), random(), random(), 1.0),
+        shape: Shape::Circle { radius: 0.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.0,
+    };
+
+    client.send_message(ClientToServerMessage::PlayerChanged(
+        SPAWN_CIRCLE_ID,
+        circle,
+    ))?;
+
+    let state = client
+        .run_until_disconnected(UPDATE_INTERVAL, |client, _| {
+            let step = cgmath::vec2(random() * 2.0 - 1.0, random() * 2.0 - 1.0) * MAX_STEP;
+            circle.position = client.world_bounds().clamp(circle.position + step);
+            _ = client.send_message(ClientToServerMessage::MovedTo(
+                SPAWN_CIRCLE_ID,
+                circle.position,
+            ));
+            ControlFlow::Continue(())
+        })
+        .await;
+    Ok(state)
+}
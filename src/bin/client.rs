@@ -1,21 +1,64 @@
-use eframe::wgpu;
-use multiplayer_game::App;
+use anyhow::Context as _;
+#[cfg(feature = "tls")]
+use multiplayer_game::tls::TlsClientConfig;
+use multiplayer_game::{
+    client::{ClientConfig, RetryConfig},
+    config::Config,
+    logging, run_app, App,
+};
+use std::path::PathBuf;
 
 fn main() -> anyhow::Result<()> {
-    eframe::run_native(
-        "Multiplayer",
-        eframe::NativeOptions {
-            renderer: eframe::Renderer::Wgpu,
-            vsync: false,
-            hardware_acceleration: eframe::HardwareAcceleration::Preferred,
-            wgpu_options: eframe::egui_wgpu::WgpuConfiguration {
-                present_mode: wgpu::PresentMode::AutoNoVsync,
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                ..Default::default()
-            },
-            ..Default::default()
-        },
-        Box::new(|cc| Box::new(App::new(cc, false))),
-    )?;
-    Ok(())
+    logging::init();
+    let config_path =
+        std::env::args().find_map(|arg| arg.strip_prefix("--config=").map(PathBuf::from));
+    let mut config = match &config_path {
+        Some(path) => Config::load_from(path)?,
+        None => Config::load()?,
+    };
+    if std::env::args().any(|arg| arg == "--host") {
+        config.host = true;
+    }
+    if let Some(address) = std::env::args().skip(1).find(|arg| !arg.starts_with("--")) {
+        config.addr = address;
+    }
+    let spectator = std::env::args().any(|arg| arg == "--spectate");
+    let record = std::env::args().find_map(|arg| arg.strip_prefix("--record=").map(PathBuf::from));
+
+    #[cfg(feature = "tls")]
+    let tls =
+        match std::env::args().find_map(|arg| arg.strip_prefix("--tls-root=").map(PathBuf::from)) {
+            Some(path) => Some(TlsClientConfig {
+                server_name: std::env::args()
+                    .find_map(|arg| arg.strip_prefix("--tls-name=").map(str::to_owned)),
+                ..TlsClientConfig::trusting(&path)?
+            }),
+            None => None,
+        };
+    let auth_token =
+        std::env::args().find_map(|arg| arg.strip_prefix("--token=").map(str::to_owned));
+    let codec = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--codec=").map(str::parse))
+        .transpose()?
+        .unwrap_or_default();
+    let client_config = ClientConfig {
+        retry: Some(RetryConfig::default()),
+        player_update_interval: config.player_update_interval(),
+        spectator,
+        auth_token,
+        codec,
+        #[cfg(feature = "tls")]
+        tls,
+        ..Default::default()
+    };
+
+    run_app("Multiplayer", move |cc| {
+        let mut app = App::new(cc, &config.addr, &config, client_config)?;
+        app.save_tuning_to(config_path.unwrap_or_else(Config::path));
+        if let Some(path) = &record {
+            app.record(path)
+                .with_context(|| format!("failed to create `{}`", path.display()))?;
+        }
+        Ok(app)
+    })
 }
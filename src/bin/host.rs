@@ -0,0 +1,34 @@
+use anyhow::Context as _;
+use multiplayer_game::{client::ClientConfig, config::Config, logging, run_app, App};
+use std::path::PathBuf;
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0:1234";
+
+fn main() -> anyhow::Result<()> {
+    logging::init();
+    let config = Config {
+        host: true,
+        ..Config::load()?
+    };
+    let spectator = std::env::args().any(|arg| arg == "--spectate");
+    let record = std::env::args().find_map(|arg| arg.strip_prefix("--record=").map(PathBuf::from));
+    let address = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+
+    run_app("Multiplayer", move |cc| {
+        let client_config = ClientConfig {
+            player_update_interval: config.player_update_interval(),
+            spectator,
+            ..Default::default()
+        };
+        let mut app = App::new(cc, &address, &config, client_config)?;
+        app.save_tuning_to(Config::path());
+        if let Some(path) = &record {
+            app.record(path)
+                .with_context(|| format!("failed to create `{}`", path.display()))?;
+        }
+        Ok(app)
+    })
+}
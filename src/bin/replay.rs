@@ -0,0 +1,14 @@
+use anyhow::Context as _;
+use multiplayer_game::{logging, run_app, App};
+use std::path::PathBuf;
+
+fn main() -> anyhow::Result<()> {
+    logging::init();
+    let path: PathBuf = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .context("usage: replay <recording>")?
+        .into();
+
+    run_app("Multiplayer Replay", move |cc| App::replay(cc, &path))
+}
@@ -1,21 +1,114 @@
-use eframe::wgpu;
-use multiplayer_game::App;
+use anyhow::Context as _;
+#[cfg(feature = "tls")]
+use multiplayer_game::tls::load_server_config;
+use multiplayer_game::{
+    logging,
+    server::{run_server, GameMode, ServerConfig},
+};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
 
-fn main() -> anyhow::Result<()> {
-    eframe::run_native(
-        "Multiplayer",
-        eframe::NativeOptions {
-            renderer: eframe::Renderer::Wgpu,
-            vsync: false,
-            hardware_acceleration: eframe::HardwareAcceleration::Preferred,
-            wgpu_options: eframe::egui_wgpu::WgpuConfiguration {
-                present_mode: wgpu::PresentMode::AutoNoVsync,
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                ..Default::default()
-            },
+const DEFAULT_ADDRESS: &str = "127.0.0.1:1234";
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    logging::init();
+    let mut args = std::env::args()
+        .skip(1)
+        .filter(|arg| !arg.starts_with("--"));
+    let address = args.next().unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+    let addr: SocketAddr = address
+        .parse()
+        .with_context(|| format!("`{address}` is not a valid socket address"))?;
+
+    let save_path = args.next().map(PathBuf::from);
+    let auth_token =
+        std::env::args().find_map(|arg| arg.strip_prefix("--token=").map(str::to_owned));
+    let tick_rate = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--tick-rate=").map(str::to_owned))
+        .map(|tick_rate| {
+            tick_rate
+                .parse()
+                .with_context(|| format!("`{tick_rate}` is not a valid tick rate"))
+        })
+        .transpose()?;
+    let game_mode = if std::env::args().any(|arg| arg == "--tag") {
+        GameMode::Tag
+    } else {
+        GameMode::FreeRoam
+    };
+    let delta_encoding = std::env::args().any(|arg| arg == "--delta");
+    let codec = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--codec=").map(str::parse))
+        .transpose()?
+        .unwrap_or_default();
+    let bandwidth_report_interval = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--bandwidth-report=").map(str::to_owned))
+        .map(|seconds| {
+            seconds
+                .parse()
+                .map(Duration::from_secs)
+                .with_context(|| format!("`{seconds}` is not a valid number of seconds"))
+        })
+        .transpose()?;
+    let reconnect_grace = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--reconnect-grace=").map(str::to_owned))
+        .map(|seconds| {
+            seconds
+                .parse()
+                .map(Duration::from_secs)
+                .with_context(|| format!("`{seconds}` is not a valid number of seconds"))
+        })
+        .transpose()?;
+    #[cfg(feature = "websocket")]
+    let websocket = std::env::args()
+        .find_map(|arg| arg.strip_prefix("--websocket=").map(str::to_owned))
+        .map(|address| {
+            let addr = address
+                .parse()
+                .with_context(|| format!("`{address}` is not a valid socket address"))?;
+            let path = std::env::args()
+                .find_map(|arg| arg.strip_prefix("--websocket-path=").map(str::to_owned))
+                .unwrap_or_else(|| "/".to_string());
+            anyhow::Ok(multiplayer_game::server::WebSocketListener { addr, path })
+        })
+        .transpose()?;
+
+    #[cfg(feature = "tls")]
+    let tls = {
+        let flag = |name: &str| {
+            let prefix = format!("--{name}=");
+            std::env::args().find_map(|arg| arg.strip_prefix(&prefix).map(PathBuf::from))
+        };
+        match (flag("tls-cert"), flag("tls-key")) {
+            (Some(certificate), Some(key)) => Some(load_server_config(certificate, key)?),
+            (None, None) => None,
+            _ => anyhow::bail!("`--tls-cert` and `--tls-key` have to be passed together"),
+        }
+    };
+
+    let (server, _) = run_server(
+        addr,
+        ServerConfig {
+            save_path,
+            tick_rate,
+            auth_token,
+            game_mode,
+            delta_encoding,
+            bandwidth_report_interval,
+            reconnect_grace,
+            codec,
+            #[cfg(feature = "websocket")]
+            websocket,
+            #[cfg(feature = "tls")]
+            tls,
             ..Default::default()
         },
-        Box::new(|cc| Box::new(App::new(cc, true))),
-    )?;
+    )
+    .await?;
+    tracing::info!(%addr, "listening");
+
+    tokio::signal::ctrl_c().await?;
+    tracing::info!("shutting down");
+    server.shutdown().await;
     Ok(())
 }
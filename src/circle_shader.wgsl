@@ -13,22 +13,58 @@ struct Camera {
     position: vec2<f32>,
     aspect: f32,
     zoom: f32,
+    rotation: f32,
+    viewport_height: f32,
+    min_circle_pixels: f32,
 };
 
 @group(0)
 @binding(0)
 var<uniform> camera: Camera;
 
+// rotates offsets from the camera position into view space
+fn view_matrix() -> mat2x2<f32> {
+    let c = cos(camera.rotation);
+    let s = sin(camera.rotation);
+    return mat2x2<f32>(c, -s, s, c);
+}
+
+fn view_to_clip(view_position: vec2<f32>) -> vec4<f32> {
+    return vec4<f32>(view_position * camera.zoom / vec2<f32>(camera.aspect, 1.0), 0.0, 1.0);
+}
+
+const SHAPE_CIRCLE: u32 = 0u;
+const SHAPE_SQUARE: u32 = 1u;
+
 struct Circle {
     position: vec2<f32>,
-    color: vec3<f32>,
+    color: vec4<f32>,
     radius: f32,
+    shape: u32,
+    outline_color: vec3<f32>,
+    outline_thickness: f32,
+    glow: f32,
+    sprite: u32,
 };
 
+const NO_SPRITE: u32 = 0xffffffffu;
+
+// how opaque the glow is right at the edge of the circle, relative to the circle itself
+const GLOW_INTENSITY: f32 = 0.6;
+
 @group(1)
 @binding(0)
 var<storage, read> circles: array<Circle>;
 
+// a row of square sprites
+@group(2)
+@binding(0)
+var sprite_atlas: texture_2d<f32>;
+
+@group(2)
+@binding(1)
+var sprite_sampler: sampler;
+
 @vertex
 fn vertex(input: VertexInput) -> VertexOutput {
     var output: VertexOutput;
@@ -39,18 +75,56 @@ fn vertex(input: VertexInput) -> VertexOutput {
         f32((input.vertex_index >> 1u) & 1u) * 2.0 - 1.0,
     );
 
-    let world_position = output.uv * circles[input.circle_index].radius + circles[input.circle_index].position;
+    let circle = circles[input.circle_index];
+    // the whole circle is scaled up when it would be too small to see, one world unit is
+    // zoom * viewport_height / 2 pixels
+    let min_radius = camera.min_circle_pixels / (camera.zoom * camera.viewport_height);
+    let scale = max(1.0, min_radius / circle.radius);
+    // the quad is grown to fit the glow, but uv stays in units of the radius
+    let extent = circle.radius + circle.glow;
+    let world_position = output.uv * extent * scale + circle.position;
+    output.uv *= extent / circle.radius;
 
-    output.clip_position = vec4<f32>((world_position - camera.position) * camera.zoom / vec2<f32>(camera.aspect, 1.0), 0.0, 1.0);
+    output.clip_position = view_to_clip(view_matrix() * (world_position - camera.position));
 
     return output;
 }
 
 @fragment
 fn pixel(input: VertexOutput) -> @location(0) vec4<f32> {
-    if dot(input.uv, input.uv) > 1.0 {
-        discard;
+    let circle = circles[input.circle_index];
+
+    var edge_distance: f32;
+    if circle.shape == SHAPE_CIRCLE {
+        edge_distance = length(input.uv);
+    } else {
+        edge_distance = max(abs(input.uv.x), abs(input.uv.y));
     }
 
-    return vec4<f32>(circles[input.circle_index].color, 1.0);
+    if edge_distance > 1.0 {
+        let glow_distance = (edge_distance - 1.0) * circle.radius / circle.glow;
+        if circle.glow <= 0.0 || glow_distance >= 1.0 {
+            discard;
+        }
+        let falloff = 1.0 - glow_distance;
+        return vec4<f32>(circle.color.rgb, circle.color.a * GLOW_INTENSITY * falloff * falloff);
+    }
+
+    if edge_distance > 1.0 - circle.outline_thickness / circle.radius {
+        return vec4<f32>(circle.outline_color, circle.color.a);
+    }
+
+    if circle.sprite == NO_SPRITE {
+        return circle.color;
+    }
+
+    let atlas_size = textureDimensions(sprite_atlas);
+    let sprite_count = f32(atlas_size.x / atlas_size.y);
+    let sprite_uv = vec2<f32>(
+        (f32(circle.sprite) + input.uv.x * 0.5 + 0.5) / sprite_count,
+        0.5 - input.uv.y * 0.5,
+    );
+    // the explicit level is needed because this is not in uniform control flow
+    let texel = textureSampleLevel(sprite_atlas, sprite_sampler, sprite_uv, 0.0);
+    return vec4<f32>(mix(circle.color.rgb, texel.rgb, texel.a), circle.color.a);
 }
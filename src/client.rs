@@ -1,235 +1,1116 @@
-use anyhow::bail;
-use cgmath::{Vector2, Vector3};
+#[cfg(feature = "tls")]
+use crate::tls::TlsClientConfig;
+use crate::{
+    codec::{Codec, CodecKind},
+    recording::{replay_log, Recorder},
+    server::{default_color, run_server, spawn_position, ServerConfig, ServerHandle},
+    transport::{TcpTransport, Transport, TransportKind, UdpTransport},
+};
+#[cfg(feature = "test-transport")]
+use crate::{
+    laggy::{LagConfig, LaggyTransport},
+    transport::MemoryConnector,
+};
+use anyhow::{bail, Context as _};
+use cgmath::{Vector2, Vector3, Vector4};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    ops::ControlFlow,
+    path::Path,
+    sync::{
+        atomic::{AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 use tokio::{
     io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _},
-    net::{TcpListener, TcpStream},
     select,
-    sync::mpsc::{error::TryRecvError, unbounded_channel, UnboundedReceiver, UnboundedSender},
-    time::MissedTickBehavior,
+    sync::{
+        mpsc::{error::TryRecvError, unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot, watch,
+    },
+    task::JoinHandle,
+    time::{Interval, MissedTickBehavior},
 };
+use tracing::{error, info_span, warn, Instrument as _};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub const PROTOCOL_VERSION: u32 = 31;
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+pub const COMPRESSION_THRESHOLD: usize = 256;
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(5);
+/// The connection counts as overdue once no ping has arrived for this long, which is a few of the
+/// server's default ping intervals, see [`crate::server::ServerConfig::ping_interval`]
+pub const PING_OVERDUE_AFTER: Duration = Duration::from_secs(3);
+pub const MIN_RADIUS: f32 = 0.05;
+pub const MAX_RADIUS: f32 = 5.0;
+pub const MAX_OUTLINE_THICKNESS: f32 = 0.5;
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 256;
+pub const MAX_NAME_LENGTH: usize = 32;
+/// How many sprites there are to pick from with [`ClientToServerMessage::SetSprite`]
+pub const SPRITE_COUNT: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WorldBounds {
+    pub min: Vector2<f32>,
+    pub max: Vector2<f32>,
+}
+
+impl WorldBounds {
+    pub fn contains(self, position: Vector2<f32>) -> bool {
+        (self.min.x..=self.max.x).contains(&position.x)
+            && (self.min.y..=self.max.y).contains(&position.y)
+    }
+
+    pub fn clamp(self, position: Vector2<f32>) -> Vector2<f32> {
+        Vector2 {
+            x: position.x.clamp(self.min.x, self.max.x),
+            y: position.y.clamp(self.min.y, self.max.y),
+        }
+    }
+}
+
+impl Default for WorldBounds {
+    fn default() -> Self {
+        Self {
+            min: Vector2 { x: -50.0, y: -50.0 },
+            max: Vector2 { x: 50.0, y: 50.0 },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Shape {
+    Circle { radius: f32 },
+    Square { size: f32 },
+}
+
+impl Shape {
+    pub fn extent(self) -> f32 {
+        match self {
+            Shape::Circle { radius } => radius,
+            Shape::Square { size } => size / 2.0,
+        }
+    }
+
+    pub fn with_extent(self, extent: f32) -> Self {
+        match self {
+            Shape::Circle { .. } => Shape::Circle { radius: extent },
+            Shape::Square { .. } => Shape::Square { size: extent * 2.0 },
+        }
+    }
+
+    pub fn contains(self, center: Vector2<f32>, point: Vector2<f32>) -> bool {
+        let offset = point - center;
+        match self {
+            Shape::Circle { radius } => {
+                offset.x * offset.x + offset.y * offset.y <= radius * radius
+            }
+            Shape::Square { size } => offset.x.abs() <= size / 2.0 && offset.y.abs() <= size / 2.0,
+        }
+    }
+}
+
+/// Picked by the player that owns the circle, so it is only unique together with their uuid
+pub type CircleId = u32;
+pub type CircleKey = (Uuid, CircleId);
+
+/// The circle the server gives every player when they join, at their spawn position
+pub const SPAWN_CIRCLE_ID: CircleId = 0;
+pub const SPAWN_CIRCLE_RADIUS: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Circle {
     pub position: Vector2<f32>,
-    pub color: Vector3<f32>,
-    pub radius: f32,
+    pub color: Vector4<f32>,
+    pub shape: Shape,
+    pub outline_color: Vector3<f32>,
+    pub outline_thickness: f32,
+}
+
+impl Circle {
+    /// How every player starts out, before they change anything
+    pub fn spawn(position: Vector2<f32>, color: Vector4<f32>) -> Self {
+        Self {
+            position,
+            color,
+            shape: Shape::Circle {
+                radius: SPAWN_CIRCLE_RADIUS,
+            },
+            outline_color: Vector3::new(1.0, 1.0, 1.0),
+            outline_thickness: 0.0,
+        }
+    }
+}
+
+/// The fields of a [`Circle`] that changed, see [`crate::server::ServerConfig::delta_encoding`]
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct CircleDelta {
+    pub position: Option<Vector2<f32>>,
+    pub color: Option<Vector4<f32>>,
+    pub shape: Option<Shape>,
+    pub outline_color: Option<Vector3<f32>>,
+    pub outline_thickness: Option<f32>,
+}
+
+impl CircleDelta {
+    pub fn between(old: Circle, new: Circle) -> Self {
+        fn changed<T: PartialEq>(old: T, new: T) -> Option<T> {
+            (old != new).then_some(new)
+        }
+        Self {
+            position: changed(old.position, new.position),
+            color: changed(old.color, new.color),
+            shape: changed(old.shape, new.shape),
+            outline_color: changed(old.outline_color, new.outline_color),
+            outline_thickness: changed(old.outline_thickness, new.outline_thickness),
+        }
+    }
+
+    pub fn apply(self, circle: Circle) -> Circle {
+        Circle {
+            position: self.position.unwrap_or(circle.position),
+            color: self.color.unwrap_or(circle.color),
+            shape: self.shape.unwrap_or(circle.shape),
+            outline_color: self.outline_color.unwrap_or(circle.outline_color),
+            outline_thickness: self.outline_thickness.unwrap_or(circle.outline_thickness),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum ClientToServerMessage {
     Disconnect,
-    Ping,
-    PlayerChanged(Circle),
+    Ping(u64),
+    /// Ignored if the player has no circle with this id, see [`ClientToServerMessage::AddCircle`]
+    PlayerChanged(CircleId, Circle),
+    /// Like [`ClientToServerMessage::PlayerChanged`] but only for the position, which is all that
+    /// changes while moving around
+    MovedTo(CircleId, Vector2<f32>),
+    /// Replaces the circle if the player already has one with this id, see
+    /// [`crate::server::ServerConfig::max_circles_per_player`]
+    AddCircle(CircleId, Circle),
+    RemoveCircle(CircleId),
+    SetName(String),
+    /// `None` goes back to a flat colored circle
+    SetSprite(Option<u32>),
+    Chat(String),
+    SetSpectator(bool),
+    Reliable(u64, Box<ClientToServerMessage>),
+    /// Only accepted from the host
+    Kick(Uuid),
+    /// Only accepted from the host, nobody can move while the world is paused
+    SetPaused(bool),
+    /// The center and half the diagonal of what the player can see, see
+    /// [`crate::server::ServerConfig::interest_radius`]
+    SetViewport(Vector2<f32>, f32),
+    /// Sent before anything else, the auth token is only needed when the server requires one
+    Join {
+        auth_token: Option<String>,
+        /// From the last handshake, to get the same uuid and circle back after losing the connection
+        reconnect_token: Option<Uuid>,
+        /// Has to match [`crate::server::ServerConfig::codec`], the join and the handshake are
+        /// always cbor and everything after them uses this
+        codec: CodecKind,
+    },
+    /// Sent instead of [`ClientToServerMessage::Join`] to get a [`ServerToClientMessage::Info`]
+    /// without joining, see [`query_server`]
+    QueryInfo,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// A joining client is sent its [`ServerToClientMessage::Handshake`] first, then everything that
+/// already exists as one [`ServerToClientMessage::Batch`] ending in a
+/// [`ServerToClientMessage::InitialState`], and only then changes as they happen.
+/// Every uuid is announced with a [`ServerToClientMessage::ClientConnected`] before anything else
+/// is said about it, the only exception are circles loaded from a save whose owner hasnt joined
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum ServerToClientMessage {
-    Handshake(Uuid),
+    Handshake {
+        uuid: Uuid,
+        version: u32,
+        world_bounds: WorldBounds,
+        /// Derived from the uuid so players look different before they pick a color
+        default_color: Vector4<f32>,
+        /// Where this client should put its circle, so new players dont pile up in one place
+        spawn_position: Vector2<f32>,
+        /// A secret for [`ClientToServerMessage::Join`] when reconnecting, see
+        /// [`crate::server::ServerConfig::reconnect_grace`]
+        reconnect_token: Uuid,
+        /// A secret that has to be sent along with every datagram, so nobody else can send
+        /// datagrams for this client
+        udp_token: Uuid,
+    },
+    Rejected(String),
+    Unauthorized,
+    /// The answer to [`ClientToServerMessage::QueryInfo`], the connection is closed afterwards
+    Info {
+        player_count: usize,
+        max_players: Option<usize>,
+        name: String,
+    },
+    Ack(u64),
+    Batch(Vec<ServerToClientMessage>),
     ClientConnected(Uuid),
-    ClientDisconnected(Uuid),
-    Ping,
-    PlayerChanged(Uuid, Circle),
+    ClientDisconnected(Uuid, DisconnectReason),
+    /// A client that lost its connection came back in time to keep its uuid and circle
+    ClientReconnected(Uuid),
+    Ping(u64),
+    Latency(Duration),
+    PlayerChanged(CircleKey, Circle),
+    MovedTo(CircleKey, Vector2<f32>),
+    /// Only what changed since the last time this client was sent the circle
+    PlayerDelta(CircleKey, CircleDelta),
+    /// Every circle that changed since the last tick, see [`crate::server::ServerConfig::tick_rate`],
+    /// or every circle there is as a keyframe, see [`crate::server::ServerConfig::delta_encoding`]
+    Snapshot(Vec<(CircleKey, Circle)>),
+    /// Every circle in the world, sent once when joining instead of a message per circle
+    InitialState(Vec<(CircleKey, Circle)>),
+    PositionCorrected(CircleId, Vector2<f32>),
+    CircleRemoved(CircleKey),
+    /// The circle is too far away to be interesting, it is sent in full again once it comes back
+    /// into view, see [`ClientToServerMessage::SetViewport`]
+    LeftView(CircleKey),
+    NameChanged(Uuid, String),
+    SpriteChanged(Uuid, Option<u32>),
+    Chat(Uuid, String),
+    SpectatorChanged(Uuid, bool),
+    /// The player who is it now, see [`crate::server::GameMode::Tag`]
+    TagChanged(Uuid),
+    /// How long each connected player has been it so far
+    TagScores(Vec<(Uuid, Duration)>),
+    /// See [`ClientToServerMessage::SetPaused`]
+    Paused(bool),
+    ServerShutdown,
+    Kicked,
+    Reconnecting,
+    Reconnected(Uuid),
+    /// Sent locally when the server sent something that couldnt be decoded, the connection is
+    /// closed afterwards because reconnecting would most likely run into the same problem
+    ProtocolError(String),
+}
+
+impl ServerToClientMessage {
+    /// Everything in a [`ServerToClientMessage::Batch`], including batches inside it, or just the
+    /// message itself
+    pub fn unbatch(self) -> Vec<Self> {
+        match self {
+            Self::Batch(messages) => messages.into_iter().flat_map(Self::unbatch).collect(),
+            message => vec![message],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DisconnectReason {
+    Quit,
+    Timeout,
+    Kicked,
+    /// Kicked by the server for not doing anything, see [`crate::server::ServerConfig::idle_timeout`]
+    Idle,
+    #[default]
+    Error,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryConfig {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(10),
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub connect_timeout: Duration,
+    pub retry: Option<RetryConfig>,
+    pub player_update_interval: Duration,
+    pub transport: TransportKind,
+    /// Disables Nagle's algorithm so small messages are sent immediately
+    pub tcp_nodelay: bool,
+    pub spectator: bool,
+    /// Sent to servers that require authentication, see [`crate::server::ServerConfig::auth_token`]
+    pub auth_token: Option<String>,
+    /// Has to be the one the server uses, udp datagrams are always cbor
+    pub codec: CodecKind,
+    /// Encrypts the connection, the server has to be configured with a matching certificate
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsClientConfig>,
+    /// Simulates a bad network once connected, see [`LaggyTransport`]
+    #[cfg(feature = "test-transport")]
+    pub lag: Option<LagConfig>,
+    /// Connects without the network, `addr` is still used for udp
+    #[cfg(feature = "test-transport")]
+    pub memory: Option<MemoryConnector>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            retry: None,
+            player_update_interval: Duration::from_secs(1) / 30,
+            transport: TransportKind::Tcp,
+            tcp_nodelay: true,
+            spectator: false,
+            auth_token: None,
+            codec: CodecKind::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "test-transport")]
+            lag: None,
+            #[cfg(feature = "test-transport")]
+            memory: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    Reconnecting,
+    Disconnected,
+    ServerShutdown,
+    Kicked,
+    ProtocolError,
+}
+
+/// How the connection is doing at a glance, see [`Client::health`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionHealth {
+    Healthy,
+    /// Connected, but no ping has made it back within [`PING_OVERDUE_AFTER`]
+    Overdue,
+    /// Counting from 1, see [`ClientConfig::retry`]
+    Reconnecting(u32),
+    Disconnected,
+}
+
+type PendingAcks = Arc<Mutex<HashMap<u64, oneshot::Sender<()>>>>;
+
+/// What [`query_server`] found out about a server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerInfo {
+    pub player_count: usize,
+    pub max_players: Option<usize>,
+    pub name: String,
+}
+
+/// Asks the server about itself without joining it
+pub async fn query_server(addr: SocketAddr) -> anyhow::Result<ServerInfo> {
+    tokio::time::timeout(DEFAULT_CONNECT_TIMEOUT, async {
+        let mut transport = TcpTransport::connect(addr, true).await?;
+        transport.send(ClientToServerMessage::QueryInfo).await?;
+        match transport.receive().await? {
+            ServerToClientMessage::Info {
+                player_count,
+                max_players,
+                name,
+            } => Ok(ServerInfo {
+                player_count,
+                max_players,
+                name,
+            }),
+            message => bail!("expected the server info but got {message:?}"),
+        }
+    })
+    .await
+    .context("timed out while querying the server")?
+}
+
+fn acknowledge(pending_acks: &PendingAcks, id: u64) {
+    if let Some(ack) = pending_acks.lock().unwrap().remove(&id) {
+        _ = ack.send(());
+    }
 }
 
 pub struct Client {
     uuid: Uuid,
+    world_bounds: WorldBounds,
+    default_color: Vector4<f32>,
+    spawn_position: Vector2<f32>,
+    spectator: bool,
+    latency: Option<Duration>,
+    /// When the last ping made the round trip, or when the connection was made
+    last_round_trip: Instant,
+    peers: HashSet<Uuid>,
+    connection_state: watch::Receiver<ConnectionState>,
+    reconnect_attempt: Arc<AtomicU32>,
+    player_update_interval: watch::Sender<Duration>,
     to_server_messages: UnboundedSender<(ClientToServerMessage, Uuid)>,
     from_server_messages: UnboundedReceiver<ServerToClientMessage>,
+    next_ack_id: AtomicU64,
+    pending_acks: PendingAcks,
+    task: Option<JoinHandle<()>>,
+    server: Option<ServerHandle>,
+    recorder: Option<Recorder>,
 }
 
 #[derive(Debug, Error)]
 #[error("the server has disconnected")]
 pub struct Disconnected;
 
+#[derive(Debug, Error)]
+pub enum ReliableSendError {
+    #[error("{0}")]
+    Disconnected(#[from] Disconnected),
+    #[error("timed out while waiting for the server to acknowledge the message")]
+    Timeout,
+}
+
+#[derive(Debug, Error)]
+pub enum ConnectError {
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("the first message sent wasnt a handshake")]
+    HandshakeMissing,
+    #[error("the server is using protocol version {server} but this client uses version {client}")]
+    VersionMismatch { server: u32, client: u32 },
+    #[error("timed out while connecting to the server")]
+    Timeout,
+    #[error("the server rejected the connection: {0}")]
+    Rejected(String),
+    #[error("the server rejected the authentication token")]
+    Unauthorized,
+    #[error("{0}")]
+    Message(anyhow::Error),
+}
+
 impl Client {
     pub async fn create_local(addr: SocketAddr) -> anyhow::Result<Self> {
-        let (to_server_messages, mut from_clients_messages) = unbounded_channel();
-        let (to_client_messages, from_server_messages) = unbounded_channel();
-
-        let listener = TcpListener::bind(addr).await?;
+        Self::create_local_with_config(addr, ServerConfig::default()).await
+    }
 
-        let uuid = Uuid::new_v4();
-        to_client_messages
-            .send(ServerToClientMessage::Handshake(uuid))
-            .unwrap();
-        to_client_messages
-            .send(ServerToClientMessage::ClientConnected(uuid))
-            .unwrap();
+    pub async fn create_local_with_config(
+        addr: SocketAddr,
+        config: ServerConfig,
+    ) -> anyhow::Result<Self> {
+        let (spawn_radius, world_bounds) = (config.spawn_radius, config.world_bounds);
+        let (server, _) = run_server(addr, config).await?;
+        let (uuid, to_server_messages, mut from_local_server_messages, connection_state) =
+            server.connect_local().await;
 
-        tokio::spawn({
-            let to_server_messages = to_server_messages.clone();
+        let (to_client_messages, from_server_messages) = unbounded_channel();
+        let pending_acks = PendingAcks::default();
+        let task = tokio::spawn({
+            let pending_acks = pending_acks.clone();
             async move {
-                let mut clients = HashMap::from([(uuid, to_client_messages)]);
-
-                async fn handle_client(
-                    mut stream: TcpStream,
-                    uuid: Uuid,
-                    to_server_messages: UnboundedSender<(ClientToServerMessage, Uuid)>,
-                    mut from_server_messages: UnboundedReceiver<ServerToClientMessage>,
-                ) -> anyhow::Result<()> {
-                    let (mut reader, mut writer) = stream.split();
-
-                    'outer: loop {
-                        tokio::pin! {
-                            let read_message = read_message(&mut reader);
+                while let Some(message) = from_local_server_messages.recv().await {
+                    for message in message.unbatch() {
+                        if let ServerToClientMessage::Ack(id) = message {
+                            acknowledge(&pending_acks, id);
+                            continue;
                         }
-
-                        loop {
-                            select! {
-                                message = from_server_messages.recv() => {
-                                    let Some(message) = message else {
-                                        break 'outer;
-                                    };
-                                    write_message(&mut writer, message).await?;
-                                }
-
-                                result = &mut read_message => {
-                                    let message = result?;
-                                    let Ok(()) = to_server_messages.send((message, uuid)) else {
-                                        break 'outer;
-                                    };
-                                    continue 'outer;
-                                }
-                            }
+                        if to_client_messages.send(message).is_err() {
+                            return;
                         }
                     }
-
-                    stream.shutdown().await?;
-                    Ok(())
                 }
+            }
+        });
 
-                async fn handle_message(
-                    message: ClientToServerMessage,
-                    uuid: Uuid,
-                    clients: &mut HashMap<Uuid, UnboundedSender<ServerToClientMessage>>,
-                ) {
-                    match message {
-                        ClientToServerMessage::Disconnect => {
-                            clients.remove(&uuid);
-                            for client in clients.values() {
-                                _ = client.send(ServerToClientMessage::ClientDisconnected(uuid));
-                            }
-                        }
-                        ClientToServerMessage::Ping => {}
-                        ClientToServerMessage::PlayerChanged(circle) => {
-                            for client in clients.values() {
-                                _ = client.send(ServerToClientMessage::PlayerChanged(uuid, circle));
-                            }
-                        }
-                    }
-                }
+        Ok(Self {
+            uuid,
+            world_bounds,
+            default_color: default_color(uuid),
+            spawn_position: spawn_position(uuid, spawn_radius, world_bounds),
+            spectator: false,
+            latency: None,
+            last_round_trip: Instant::now(),
+            peers: HashSet::new(),
+            connection_state,
+            reconnect_attempt: Arc::default(),
+            // local messages go straight to the server without being queued
+            player_update_interval: watch::Sender::new(
+                ClientConfig::default().player_update_interval,
+            ),
+            to_server_messages,
+            from_server_messages,
+            next_ack_id: AtomicU64::new(0),
+            pending_acks,
+            task: Some(task),
+            server: Some(server),
+            recorder: None,
+        })
+    }
 
-                let mut ping_interval = tokio::time::interval(Duration::from_millis(1000));
-                ping_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    /// Creates a client that plays back a log written by [`Client::record`] instead of connecting to a server
+    pub async fn replay(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let entries = replay_log(path)?.collect::<Vec<_>>();
+        let (to_server_messages, mut from_client_messages) = unbounded_channel();
+        let (to_client_messages, from_server_messages) = unbounded_channel();
+        let (connection_state_sender, connection_state) =
+            watch::channel(ConnectionState::Connected);
+        let task = tokio::spawn(async move {
+            let _connection_state_sender = connection_state_sender;
+            let start = tokio::time::Instant::now();
+            for (time, message) in entries {
+                let sleep = tokio::time::sleep_until(start + time);
+                tokio::pin!(sleep);
                 loop {
                     select! {
-                        Some((message, uuid)) = from_clients_messages.recv(), if clients.contains_key(&uuid) => {
-                            handle_message(message, uuid, &mut clients).await;
-                        }
-
-                        Ok((stream, _addr)) = listener.accept() => {
-                            let (to_client_messages, from_server_messages) = unbounded_channel();
-                            let uuid = Uuid::new_v4();
-                            to_client_messages
-                                .send(ServerToClientMessage::Handshake(uuid))
-                                .unwrap();
-                            clients.insert(uuid, to_client_messages);
-                            for client in clients.values() {
-                                _ = client.send(ServerToClientMessage::ClientConnected(uuid));
-                            }
-                            tokio::spawn({
-                                let to_server_messages = to_server_messages.clone();
-                                async move {
-                                    match handle_client(stream, uuid, to_server_messages.clone(), from_server_messages).await {
-                                        Ok(()) => {}
-                                        Err(error) => {
-                                            eprintln!("{uuid}: {error}");
-                                            _ = to_server_messages.send((ClientToServerMessage::Disconnect, uuid));
-                                        }
-                                    }
-                                }
-                            });
-                        }
-
-                        _ = ping_interval.tick() => {
-                            for client in clients.values() {
-                                _ = client.send(ServerToClientMessage::Ping);
-                            }
-                        }
+                        _ = &mut sleep => break,
+                        message = from_client_messages.recv() => match message {
+                            None | Some((ClientToServerMessage::Disconnect, _)) => return,
+                            Some(_) => {}
+                        },
+                    }
+                }
+                for message in message.unbatch() {
+                    if to_client_messages.send(message).is_err() {
+                        return;
                     }
                 }
             }
         });
 
         Ok(Self {
-            uuid,
+            uuid: Uuid::nil(),
+            world_bounds: WorldBounds::default(),
+            default_color: default_color(Uuid::nil()),
+            spawn_position: Vector2::new(0.0, 0.0),
+            spectator: false,
+            latency: None,
+            last_round_trip: Instant::now(),
+            peers: HashSet::new(),
+            connection_state,
+            reconnect_attempt: Arc::default(),
+            player_update_interval: watch::Sender::new(
+                ClientConfig::default().player_update_interval,
+            ),
             to_server_messages,
             from_server_messages,
+            next_ack_id: AtomicU64::new(0),
+            pending_acks: PendingAcks::default(),
+            task: Some(task),
+            server: None,
+            recorder: None,
         })
     }
 
-    pub async fn connect(addr: SocketAddr) -> anyhow::Result<Self> {
-        let (to_server_messages, from_client_messages) = unbounded_channel();
+    pub async fn connect(addr: SocketAddr) -> Result<Self, ConnectError> {
+        Self::connect_with_timeout(addr, DEFAULT_CONNECT_TIMEOUT).await
+    }
+
+    pub async fn connect_with_timeout(
+        addr: SocketAddr,
+        timeout: Duration,
+    ) -> Result<Self, ConnectError> {
+        Self::connect_with_config(
+            addr,
+            ClientConfig {
+                connect_timeout: timeout,
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    pub async fn connect_with_token(
+        addr: SocketAddr,
+        auth_token: impl Into<String>,
+    ) -> Result<Self, ConnectError> {
+        Self::connect_with_config(
+            addr,
+            ClientConfig {
+                auth_token: Some(auth_token.into()),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    pub async fn connect_with_retry(
+        addr: SocketAddr,
+        retry: RetryConfig,
+    ) -> Result<Self, ConnectError> {
+        Self::connect_with_config(
+            addr,
+            ClientConfig {
+                retry: Some(retry),
+                ..Default::default()
+            },
+        )
+        .await
+    }
+
+    /// Resolves `host` like `"example.com:1234"` and joins the first address that works
+    pub async fn connect_host(host: &str) -> anyhow::Result<Self> {
+        Self::connect_host_with_config(host, ClientConfig::default()).await
+    }
+
+    pub async fn connect_host_with_config(
+        host: &str,
+        config: ClientConfig,
+    ) -> anyhow::Result<Self> {
+        let addrs = tokio::net::lookup_host(host)
+            .await
+            .with_context(|| format!("failed to look up `{host}`"))?;
+        let mut last_error = None;
+        for addr in addrs {
+            match Self::connect_with_config(addr, config.clone()).await {
+                Ok(client) => return Ok(client),
+                Err(error) => {
+                    warn!(%addr, %error, "failed to connect");
+                    last_error = Some(error);
+                }
+            }
+        }
+        match last_error {
+            Some(error) => Err(anyhow::Error::new(error))
+                .with_context(|| format!("failed to connect to any address of `{host}`")),
+            None => bail!("`{host}` did not resolve to any addresses"),
+        }
+    }
+
+    pub async fn connect_with_config(
+        addr: SocketAddr,
+        config: ClientConfig,
+    ) -> Result<Self, ConnectError> {
+        let (to_server_messages, mut from_client_messages) = unbounded_channel();
         let (to_client_messages, from_server_messages) = unbounded_channel();
 
+        /// The transport and everything from the handshake
+        type Joined = (
+            TcpTransport,
+            Uuid,
+            WorldBounds,
+            Vector4<f32>,
+            Vector2<f32>,
+            Uuid,
+            Uuid,
+        );
+
+        async fn handshake(
+            addr: SocketAddr,
+            config: &ClientConfig,
+            reconnect_token: Option<Uuid>,
+        ) -> Result<Joined, ConnectError> {
+            tokio::time::timeout(
+                config.connect_timeout,
+                handshake_without_timeout(addr, config, reconnect_token),
+            )
+            .await
+            .map_err(|_| ConnectError::Timeout)?
+        }
+
+        async fn connect_transport(
+            addr: SocketAddr,
+            config: &ClientConfig,
+        ) -> std::io::Result<TcpTransport> {
+            #[cfg(feature = "test-transport")]
+            if let Some(memory) = &config.memory {
+                return memory.connect();
+            }
+            #[cfg(feature = "tls")]
+            if let Some(tls) = &config.tls {
+                return TcpTransport::connect_tls(addr, config.tcp_nodelay, tls).await;
+            }
+            TcpTransport::connect(addr, config.tcp_nodelay).await
+        }
+
+        async fn handshake_without_timeout(
+            addr: SocketAddr,
+            config: &ClientConfig,
+            reconnect_token: Option<Uuid>,
+        ) -> Result<Joined, ConnectError> {
+            let mut transport = connect_transport(addr, config).await?;
+            transport
+                .send(ClientToServerMessage::Join {
+                    auth_token: config.auth_token.clone(),
+                    reconnect_token,
+                    codec: config.codec,
+                })
+                .await
+                .map_err(ConnectError::Message)?;
+            let (
+                uuid,
+                version,
+                world_bounds,
+                default_color,
+                spawn_position,
+                reconnect_token,
+                udp_token,
+            ) = match transport.receive().await.map_err(|error| {
+                match error.downcast::<std::io::Error>() {
+                    Ok(error) => ConnectError::Io(error),
+                    Err(error) => ConnectError::Message(error),
+                }
+            })? {
+                ServerToClientMessage::Handshake {
+                    uuid,
+                    version,
+                    world_bounds,
+                    default_color,
+                    spawn_position,
+                    reconnect_token,
+                    udp_token,
+                } => (
+                    uuid,
+                    version,
+                    world_bounds,
+                    default_color,
+                    spawn_position,
+                    reconnect_token,
+                    udp_token,
+                ),
+                ServerToClientMessage::Rejected(reason) => {
+                    return Err(ConnectError::Rejected(reason))
+                }
+                ServerToClientMessage::Unauthorized => return Err(ConnectError::Unauthorized),
+                _ => return Err(ConnectError::HandshakeMissing),
+            };
+            if version != PROTOCOL_VERSION {
+                return Err(ConnectError::VersionMismatch {
+                    server: version,
+                    client: PROTOCOL_VERSION,
+                });
+            }
+            transport.set_codec(config.codec);
+            Ok((
+                transport,
+                uuid,
+                world_bounds,
+                default_color,
+                spawn_position,
+                reconnect_token,
+                udp_token,
+            ))
+        }
+
+        async fn reconnect(
+            addr: SocketAddr,
+            config: &ClientConfig,
+            retry: RetryConfig,
+            (reconnect_token, reconnect_attempt): (Uuid, &AtomicU32),
+        ) -> Option<Joined> {
+            let mut delay = retry.initial_delay;
+            let mut attempts = 0;
+            loop {
+                reconnect_attempt.store(attempts + 1, Ordering::Relaxed);
+                tokio::time::sleep(delay).await;
+                match handshake(addr, config, Some(reconnect_token)).await {
+                    Ok(result) => return Some(result),
+                    Err(error) => warn!(%error, "failed to reconnect"),
+                }
+
+                attempts += 1;
+                if retry
+                    .max_attempts
+                    .is_some_and(|max_attempts| attempts >= max_attempts)
+                {
+                    return None;
+                }
+                delay = (delay * 2).min(retry.max_delay);
+            }
+        }
+
         async fn handle_client(
-            mut stream: TcpStream,
-            mut from_client_messages: UnboundedReceiver<(ClientToServerMessage, Uuid)>,
-            to_client_messages: UnboundedSender<ServerToClientMessage>,
+            (mut transport, (uuid, udp_token), mut udp): (
+                impl Transport,
+                (Uuid, Uuid),
+                Option<UdpTransport>,
+            ),
+            from_client_messages: &mut UnboundedReceiver<(ClientToServerMessage, Uuid)>,
+            to_client_messages: &UnboundedSender<ServerToClientMessage>,
+            connection_state: &watch::Sender<ConnectionState>,
+            pending_acks: &PendingAcks,
+            player_update_interval: &mut watch::Receiver<Duration>,
         ) -> anyhow::Result<()> {
-            let (mut reader, mut writer) = stream.split();
+            fn update_ticks(interval: Duration) -> Interval {
+                // tokio panics on an empty period
+                let mut ticks = tokio::time::interval(interval.max(Duration::from_millis(1)));
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                ticks
+            }
+
+            // the latest update for each circle that is waiting for the next interval
+            let mut pending_players = HashMap::new();
+            let mut player_update_ticks = update_ticks(*player_update_interval.borrow_and_update());
+            // set when the client disconnects or is dropped, rather than the server going away
+            let mut closing = false;
 
             'outer: loop {
-                tokio::pin! {
-                    let read_message = read_message(&mut reader);
-                }
+                select! {
+                    message = from_client_messages.recv() => {
+                        let Some((message, _)) = message else {
+                            closing = true;
+                            break;
+                        };
+                        // only the latest state is worth sending, but a move shouldnt lose a full
+                        // update that is still waiting
+                        match message {
+                            ClientToServerMessage::PlayerChanged(id, _) => {
+                                pending_players.insert(id, message);
+                                continue;
+                            }
+                            ClientToServerMessage::MovedTo(id, position) => {
+                                match pending_players.get_mut(&id) {
+                                    Some(ClientToServerMessage::PlayerChanged(_, circle)) => circle.position = position,
+                                    _ => _ = pending_players.insert(id, message),
+                                }
+                                continue;
+                            }
+                            // an update that is still waiting would undo these if it was sent afterwards
+                            ClientToServerMessage::AddCircle(id, _) | ClientToServerMessage::RemoveCircle(id) => {
+                                pending_players.remove(&id);
+                            }
+                            _ => {}
+                        }
+                        if let ClientToServerMessage::Disconnect = message {
+                            closing = true;
+                            break;
+                        }
+                        transport.send(message).await?;
+                    }
 
-                loop {
-                    select! {
-                        message = from_client_messages.recv() => {
-                            let Some((message, _)) = message else {
-                                break 'outer;
-                            };
-                            write_message(&mut writer, message).await?;
+                    Ok(()) = player_update_interval.changed() => {
+                        player_update_ticks = update_ticks(*player_update_interval.borrow_and_update());
+                    }
+
+                    _ = player_update_ticks.tick(), if !pending_players.is_empty() => {
+                        for (_, message) in pending_players.drain() {
+                            match &mut udp {
+                                Some(udp) => {
+                                    if let Err(error) = udp.send((uuid, udp_token, message)).await {
+                                        warn!(%uuid, %error, "failed to send a datagram");
+                                    }
+                                }
+                                None => transport.send(message).await?,
+                            }
                         }
+                    }
 
-                        result = &mut read_message => {
-                            let message = result?;
+                    result = transport.receive() => {
+                        let message: ServerToClientMessage = result?;
+                        for message in message.unbatch() {
+                            if let ServerToClientMessage::Ack(id) = message {
+                                acknowledge(pending_acks, id);
+                                continue;
+                            }
+                            let final_state = match message {
+                                ServerToClientMessage::ServerShutdown => Some(ConnectionState::ServerShutdown),
+                                ServerToClientMessage::Kicked => Some(ConnectionState::Kicked),
+                                _ => None,
+                            };
                             let Ok(()) = to_client_messages.send(message) else {
                                 break 'outer;
                             };
-                            continue 'outer;
+                            if let Some(final_state) = final_state {
+                                connection_state.send_replace(final_state);
+                                break 'outer;
+                            }
+                        }
+                    }
+
+                    // the expression is evaluated even when the branch is disabled, so the unwrap has to be deferred
+                    result = async { udp.as_mut().unwrap().receive().await }, if udp.is_some() => {
+                        match result {
+                            Ok(message @ (ServerToClientMessage::PlayerChanged(..) | ServerToClientMessage::MovedTo(..))) => {
+                                let Ok(()) = to_client_messages.send(message) else {
+                                    break;
+                                };
+                            }
+                            Ok(_) => {}
+                            Err(error) => warn!(%uuid, %error, "failed to receive a datagram"),
                         }
                     }
                 }
             }
 
-            stream.shutdown().await?;
+            if closing {
+                // the last updates go over tcp so they cant arrive after the disconnect, and errors
+                // are ignored because there is nothing left to reconnect for
+                for (_, message) in pending_players.drain() {
+                    _ = transport.send(message).await;
+                }
+                _ = transport.send(ClientToServerMessage::Disconnect).await;
+                _ = transport.shutdown().await;
+                return Ok(());
+            }
+            transport.shutdown().await?;
             Ok(())
         }
 
-        let mut stream = TcpStream::connect(addr).await?;
-        let ServerToClientMessage::Handshake(uuid) = read_message(&mut stream).await? else {
-            bail!("the first message send wasnt a handshake");
-        };
-        tokio::spawn(async move {
-            match handle_client(stream, from_client_messages, to_client_messages).await {
-                Ok(()) => {}
-                Err(error) => {
-                    println!("{uuid}: {error}");
+        async fn connect_udp(
+            addr: SocketAddr,
+            transport: TransportKind,
+        ) -> std::io::Result<Option<UdpTransport>> {
+            match transport {
+                TransportKind::Tcp => Ok(None),
+                TransportKind::Udp => UdpTransport::connect(addr).await.map(Some),
+            }
+        }
+
+        let (
+            mut transport,
+            mut uuid,
+            world_bounds,
+            default_color,
+            spawn_position,
+            mut reconnect_token,
+            mut udp_token,
+        ) = handshake(addr, &config, None).await?;
+        let mut udp = connect_udp(addr, config.transport).await?;
+        let (connection_state, connection_state_receiver) =
+            watch::channel(ConnectionState::Connected);
+        let pending_acks = PendingAcks::default();
+        let reconnect_attempt = Arc::<AtomicU32>::default();
+        let spectator = config.spectator;
+        let (player_update_interval_sender, mut player_update_interval) =
+            watch::channel(config.player_update_interval);
+        let task = tokio::spawn({
+            let pending_acks = pending_acks.clone();
+            let reconnect_attempt = reconnect_attempt.clone();
+            async move {
+                loop {
+                    #[cfg(feature = "test-transport")]
+                    let result = match config.lag {
+                        Some(lag) => {
+                            handle_client(
+                                (LaggyTransport::new(transport, lag), (uuid, udp_token), udp),
+                                &mut from_client_messages,
+                                &to_client_messages,
+                                &connection_state,
+                                &pending_acks,
+                                &mut player_update_interval,
+                            )
+                            .await
+                        }
+                        None => {
+                            handle_client(
+                                (transport, (uuid, udp_token), udp),
+                                &mut from_client_messages,
+                                &to_client_messages,
+                                &connection_state,
+                                &pending_acks,
+                                &mut player_update_interval,
+                            )
+                            .await
+                        }
+                    };
+                    #[cfg(not(feature = "test-transport"))]
+                    let result = handle_client(
+                        (transport, (uuid, udp_token), udp),
+                        &mut from_client_messages,
+                        &to_client_messages,
+                        &connection_state,
+                        &pending_acks,
+                        &mut player_update_interval,
+                    )
+                    .await;
+                    match result {
+                        Ok(()) => break,
+                        // anything that isnt an io error means the bytes themselves were wrong
+                        Err(error) if error.downcast_ref::<std::io::Error>().is_none() => {
+                            error!(%uuid, %error, "protocol error");
+                            connection_state.send_replace(ConnectionState::ProtocolError);
+                            _ = to_client_messages
+                                .send(ServerToClientMessage::ProtocolError(error.to_string()));
+                            break;
+                        }
+                        Err(error) => {
+                            warn!(%uuid, %error, "connection lost");
+                            let Some(retry) = config.retry else {
+                                break;
+                            };
+                            connection_state.send_replace(ConnectionState::Reconnecting);
+                            let Ok(()) =
+                                to_client_messages.send(ServerToClientMessage::Reconnecting)
+                            else {
+                                break;
+                            };
+                            let Some((
+                                new_transport,
+                                new_uuid,
+                                world_bounds,
+                                default_color,
+                                spawn_position,
+                                new_reconnect_token,
+                                new_udp_token,
+                            )) = reconnect(
+                                addr,
+                                &config,
+                                retry,
+                                (reconnect_token, &reconnect_attempt),
+                            )
+                            .await
+                            else {
+                                break;
+                            };
+                            (transport, uuid, reconnect_token, udp_token) =
+                                (new_transport, new_uuid, new_reconnect_token, new_udp_token);
+                            // the handshake was consumed while reconnecting, so pass it on for the new world bounds
+                            let Ok(()) =
+                                to_client_messages.send(ServerToClientMessage::Handshake {
+                                    uuid,
+                                    version: PROTOCOL_VERSION,
+                                    world_bounds,
+                                    default_color,
+                                    spawn_position,
+                                    reconnect_token,
+                                    udp_token,
+                                })
+                            else {
+                                break;
+                            };
+                            udp = match connect_udp(addr, config.transport).await {
+                                Ok(udp) => udp,
+                                Err(error) => {
+                                    warn!(%uuid, %error, "failed to connect over udp");
+                                    None
+                                }
+                            };
+                            connection_state.send_replace(ConnectionState::Connected);
+                            let Ok(()) =
+                                to_client_messages.send(ServerToClientMessage::Reconnected(uuid))
+                            else {
+                                break;
+                            };
+                        }
+                    }
                 }
             }
+            .instrument(info_span!("client", %addr))
         });
 
-        Ok(Self {
+        let mut client = Self {
             uuid,
+            world_bounds,
+            default_color,
+            spawn_position,
+            spectator: false,
+            latency: None,
+            last_round_trip: Instant::now(),
+            peers: HashSet::new(),
+            connection_state: connection_state_receiver,
+            reconnect_attempt,
+            player_update_interval: player_update_interval_sender,
             to_server_messages,
             from_server_messages,
-        })
+            next_ack_id: AtomicU64::new(0),
+            pending_acks,
+            task: Some(task),
+            server: None,
+            recorder: None,
+        };
+        if spectator {
+            _ = client.set_spectator(true);
+        }
+        Ok(client)
     }
 
     pub fn send_message(&self, message: ClientToServerMessage) -> Result<(), Disconnected> {
@@ -238,24 +1119,302 @@ impl Client {
             .map_err(|_| Disconnected)
     }
 
+    pub async fn send_reliable(
+        &self,
+        message: ClientToServerMessage,
+    ) -> Result<(), ReliableSendError> {
+        self.send_reliable_with_timeout(message, DEFAULT_ACK_TIMEOUT)
+            .await
+    }
+
+    pub async fn send_reliable_with_timeout(
+        &self,
+        message: ClientToServerMessage,
+        timeout: Duration,
+    ) -> Result<(), ReliableSendError> {
+        let id = self.next_ack_id.fetch_add(1, Ordering::Relaxed);
+        let (ack, wait_for_ack) = oneshot::channel();
+        self.pending_acks.lock().unwrap().insert(id, ack);
+        if let Err(error) =
+            self.send_message(ClientToServerMessage::Reliable(id, Box::new(message)))
+        {
+            self.pending_acks.lock().unwrap().remove(&id);
+            return Err(error.into());
+        }
+
+        let result = tokio::time::timeout(timeout, wait_for_ack).await;
+        self.pending_acks.lock().unwrap().remove(&id);
+        match result {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(Disconnected.into()),
+            Err(_) => Err(ReliableSendError::Timeout),
+        }
+    }
+
+    /// Sends anything still queued followed by a disconnect and waits for the connection to close
+    ///
+    /// Dropping the client does the same in the background, but nothing waits for it to finish, so
+    /// it can be cut short if the runtime shuts down first
+    pub async fn disconnect(&mut self) {
+        _ = self.send_message(ClientToServerMessage::Disconnect);
+        if let Some(task) = self.task.take() {
+            _ = task.await;
+        }
+        if let Some(server) = self.server.take() {
+            server.shutdown().await;
+        }
+        if let Some(recorder) = self.recorder.take() {
+            if let Err(error) = recorder.finish() {
+                warn!(%error, "failed to finish the recording");
+            }
+        }
+    }
+
+    /// Starts writing every received message to `path`, see [`Client::replay`]
+    pub fn record(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.recorder = Some(Recorder::create(path)?);
+        Ok(())
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
+
+    /// The other clients that are connected, as of the last message taken from [`Self::get_message`]
+    pub fn peers(&self) -> Vec<Uuid> {
+        self.peers.iter().copied().collect()
+    }
+
+    pub fn server_addr(&self) -> Option<SocketAddr> {
+        self.server.as_ref().map(ServerHandle::local_addr)
+    }
+
+    /// See [`ServerHandle::memory_connector`], this is `None` when not hosting
+    #[cfg(feature = "test-transport")]
+    pub fn memory_connector(&self) -> Option<MemoryConnector> {
+        self.server.as_ref().map(ServerHandle::memory_connector)
+    }
+
+    /// See [`ServerHandle::share_addresses`], this is empty when not hosting
+    pub fn share_addresses(&self) -> Vec<SocketAddr> {
+        self.server
+            .as_ref()
+            .map_or_else(Vec::new, ServerHandle::share_addresses)
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        match self.connection_state.has_changed() {
+            Ok(_) => *self.connection_state.borrow(),
+            Err(_) => match *self.connection_state.borrow() {
+                state @ (ConnectionState::ServerShutdown
+                | ConnectionState::Kicked
+                | ConnectionState::ProtocolError) => state,
+                _ => ConnectionState::Disconnected,
+            },
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connection_state() == ConnectionState::Connected
+    }
+
+    pub fn latency(&self) -> Option<Duration> {
+        self.latency
+    }
+
+    /// Only as up to date as the messages taken out with [`Self::get_message`]
+    pub fn health(&self) -> ConnectionHealth {
+        match self.connection_state() {
+            ConnectionState::Connected if self.last_round_trip.elapsed() > PING_OVERDUE_AFTER => {
+                ConnectionHealth::Overdue
+            }
+            ConnectionState::Connected => ConnectionHealth::Healthy,
+            ConnectionState::Reconnecting => {
+                ConnectionHealth::Reconnecting(self.reconnect_attempt.load(Ordering::Relaxed))
+            }
+            ConnectionState::Disconnected
+            | ConnectionState::ServerShutdown
+            | ConnectionState::Kicked
+            | ConnectionState::ProtocolError => ConnectionHealth::Disconnected,
+        }
+    }
+
+    /// How often queued circle updates are sent, starts as [`ClientConfig::player_update_interval`]
+    pub fn player_update_interval(&self) -> Duration {
+        *self.player_update_interval.borrow()
+    }
+
+    /// Takes effect immediately, and is kept across reconnects
+    pub fn set_player_update_interval(&self, interval: Duration) {
+        self.player_update_interval.send_replace(interval);
+    }
+
+    pub fn world_bounds(&self) -> WorldBounds {
+        self.world_bounds
+    }
+
+    /// The color the server picked for this client, see [`default_color`]
+    pub fn default_color(&self) -> Vector4<f32> {
+        self.default_color
+    }
+
+    /// Where the server wants this client to start, see [`spawn_position`]
+    pub fn spawn_position(&self) -> Vector2<f32> {
+        self.spawn_position
+    }
+
+    pub fn is_spectator(&self) -> bool {
+        self.spectator
+    }
+
+    pub fn set_spectator(&mut self, spectator: bool) -> Result<(), Disconnected> {
+        self.spectator = spectator;
+        self.send_message(ClientToServerMessage::SetSpectator(spectator))
+    }
+
     pub fn get_message(&mut self) -> Option<Result<ServerToClientMessage, Disconnected>> {
         match self.from_server_messages.try_recv() {
-            Ok(message) => Some(Ok(message)),
+            Ok(message) => {
+                match message {
+                    ServerToClientMessage::Latency(latency) => {
+                        self.latency = Some(latency);
+                        self.last_round_trip = Instant::now();
+                    }
+                    ServerToClientMessage::Handshake {
+                        world_bounds,
+                        default_color,
+                        spawn_position,
+                        ..
+                    } => {
+                        self.world_bounds = world_bounds;
+                        self.default_color = default_color;
+                        self.spawn_position = spawn_position;
+                    }
+                    ServerToClientMessage::ClientConnected(uuid)
+                    | ServerToClientMessage::ClientReconnected(uuid)
+                        if uuid != self.uuid =>
+                    {
+                        self.peers.insert(uuid);
+                    }
+                    ServerToClientMessage::ClientDisconnected(uuid, _) => {
+                        self.peers.remove(&uuid);
+                    }
+                    ServerToClientMessage::Reconnecting => self.peers.clear(),
+                    ServerToClientMessage::Reconnected(uuid) => {
+                        self.uuid = uuid;
+                        self.last_round_trip = Instant::now();
+                        if self.spectator {
+                            _ = self.send_message(ClientToServerMessage::SetSpectator(true));
+                        }
+                    }
+                    _ => {}
+                }
+                if let Some(recorder) = &mut self.recorder {
+                    if let Err(error) = recorder.record(&message) {
+                        error!(%error, "stopped recording");
+                        self.recorder = None;
+                    }
+                }
+                Some(Ok(message))
+            }
             Err(TryRecvError::Disconnected) => Some(Err(Disconnected)),
             Err(TryRecvError::Empty) => None,
         }
     }
+
+    pub fn drain(
+        &mut self,
+    ) -> impl Iterator<Item = Result<ServerToClientMessage, Disconnected>> + '_ {
+        let mut disconnected = false;
+        std::iter::from_fn(move || {
+            if disconnected {
+                return None;
+            }
+            let message = self.get_message()?;
+            disconnected = message.is_err();
+            Some(message)
+        })
+    }
+
+    /// Calls `handler` on the runtime with every message as soon as it arrives, until the connection
+    /// is lost, instead of taking them with [`Self::get_message`]
+    ///
+    /// Afterwards [`Self::get_message`] only reports [`Disconnected`], and the peers, latency and
+    /// recording it keeps track of are no longer updated. This has to be called from within the
+    /// runtime
+    pub fn on_message(
+        &mut self,
+        mut handler: impl FnMut(ServerToClientMessage) + Send + 'static,
+    ) -> JoinHandle<()> {
+        let (_, closed) = unbounded_channel();
+        let mut messages = std::mem::replace(&mut self.from_server_messages, closed);
+        tokio::spawn(
+            async move {
+                while let Some(message) = messages.recv().await {
+                    handler(message);
+                }
+            }
+            .instrument(info_span!("on_message", uuid = %self.uuid)),
+        )
+    }
+
+    /// Calls `tick` every `interval` with the messages received since the previous tick, until
+    /// the connection is lost or `tick` breaks, this is enough to drive a client without a window
+    pub async fn run_until_disconnected(
+        &mut self,
+        interval: Duration,
+        mut tick: impl FnMut(&mut Self, Vec<ServerToClientMessage>) -> ControlFlow<()>,
+    ) -> ConnectionState {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            let mut disconnected = false;
+            let messages = self
+                .drain()
+                .filter_map(|message| {
+                    disconnected |= message.is_err();
+                    message.ok()
+                })
+                .collect();
+            if tick(self, messages).is_break() || disconnected {
+                return self.connection_state();
+            }
+        }
+    }
 }
 
-async fn write_message<T>(writer: impl AsyncWrite, message: T) -> anyhow::Result<()>
+/// Writes one frame, a compression flag and the length followed by the cbor encoded message
+pub async fn write_message<T>(writer: impl AsyncWrite, message: T) -> anyhow::Result<()>
+where
+    T: serde::Serialize,
+{
+    write_message_with(writer, message, CodecKind::Cbor).await
+}
+
+/// Like [`write_message`] with the message encoded by `codec` instead
+pub async fn write_message_with<T>(
+    writer: impl AsyncWrite,
+    message: T,
+    codec: impl Codec,
+) -> anyhow::Result<()>
 where
     T: serde::Serialize,
 {
     tokio::pin!(writer);
 
-    let mut bytes = vec![];
-    ciborium::into_writer(&message, &mut bytes)?;
+    let bytes = codec.encode(&message)?;
+
+    #[cfg(feature = "compression")]
+    let (compressed, bytes) = if bytes.len() >= COMPRESSION_THRESHOLD {
+        (true, zstd::bulk::compress(&bytes, 0)?)
+    } else {
+        (false, bytes)
+    };
+    #[cfg(not(feature = "compression"))]
+    let compressed = false;
 
+    writer.write_u8(compressed.into()).await?;
     writer
         .write_all(&u64::to_be_bytes(bytes.len().try_into()?))
         .await?;
@@ -264,18 +1423,59 @@ where
     Ok(())
 }
 
-async fn read_message<T>(reader: impl AsyncRead) -> anyhow::Result<T>
+/// Reads one frame written by [`write_message`]
+pub async fn read_message<T>(reader: impl AsyncRead) -> anyhow::Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    read_message_with(reader, CodecKind::Cbor).await
+}
+
+/// Reads one frame written by [`write_message_with`] using the same codec
+pub async fn read_message_with<T>(reader: impl AsyncRead, codec: impl Codec) -> anyhow::Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    read_message_with_limit(reader, MAX_MESSAGE_SIZE, codec).await
+}
+
+/// Like [`read_message_with`], but frames bigger than `max_size` bytes are an error instead of
+/// [`MAX_MESSAGE_SIZE`]
+pub async fn read_message_with_limit<T>(
+    reader: impl AsyncRead,
+    max_size: usize,
+    codec: impl Codec,
+) -> anyhow::Result<T>
 where
     T: serde::de::DeserializeOwned,
 {
     tokio::pin!(reader);
 
+    let compressed = match reader.read_u8().await? {
+        0 => false,
+        1 => true,
+        flag => bail!("unknown message compression flag {flag}"),
+    };
+
     let mut length_bytes = [0; std::mem::size_of::<u64>()];
     reader.read_exact(&mut length_bytes).await?;
-    let length: usize = u64::from_be_bytes(length_bytes).try_into()?;
+    let length = u64::from_be_bytes(length_bytes);
+    if length > max_size as u64 {
+        bail!("message of {length} bytes exceeds the maximum size of {max_size} bytes");
+    }
+    let length = length as usize;
 
     let mut bytes = vec![0; length];
     reader.read_exact(bytes.as_mut_slice()).await?;
 
-    Ok(ciborium::from_reader(bytes.as_slice())?)
+    if compressed {
+        #[cfg(feature = "compression")]
+        {
+            bytes = zstd::bulk::decompress(&bytes, max_size)?;
+        }
+        #[cfg(not(feature = "compression"))]
+        bail!("received a compressed message but the `compression` feature is disabled");
+    }
+
+    codec.decode(&bytes)
 }
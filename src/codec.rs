@@ -0,0 +1,128 @@
+use anyhow::bail;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Turns messages into bytes and back, the framing around them is the transport's job
+pub trait Codec {
+    fn encode<T: Serialize>(&self, message: &T) -> anyhow::Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CborCodec;
+
+impl Codec for CborCodec {
+    fn encode<T: Serialize>(&self, message: &T) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        ciborium::into_writer(message, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T> {
+        Ok(ciborium::from_reader(bytes)?)
+    }
+}
+
+/// Human readable, for looking at the traffic while debugging
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl Codec for JsonCodec {
+    fn encode<T: Serialize>(&self, message: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(message)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Smaller and quicker than cbor, but the bytes mean nothing without knowing the exact types
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl Codec for BincodeCodec {
+    fn encode<T: Serialize>(&self, message: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::serialize(message)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Picks a [`Codec`] at runtime, both ends of a connection have to use the same one, see
+/// [`crate::client::ClientConfig::codec`] and [`crate::server::ServerConfig::codec`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CodecKind {
+    #[default]
+    Cbor,
+    /// Needs the `json` feature, see [`JsonCodec`]
+    Json,
+    /// Needs the `bincode` feature, see [`BincodeCodec`]
+    Bincode,
+}
+
+impl CodecKind {
+    /// Whether this build was compiled with the feature the codec needs
+    pub fn is_available(self) -> bool {
+        match self {
+            Self::Cbor => true,
+            Self::Json => cfg!(feature = "json"),
+            Self::Bincode => cfg!(feature = "bincode"),
+        }
+    }
+}
+
+impl Codec for CodecKind {
+    fn encode<T: Serialize>(&self, message: &T) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Cbor => CborCodec.encode(message),
+            #[cfg(feature = "json")]
+            Self::Json => JsonCodec.encode(message),
+            #[cfg(feature = "bincode")]
+            Self::Bincode => BincodeCodec.encode(message),
+            #[allow(unreachable_patterns)]
+            _ => bail!("{self} needs the `{self}` feature"),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T> {
+        match self {
+            Self::Cbor => CborCodec.decode(bytes),
+            #[cfg(feature = "json")]
+            Self::Json => JsonCodec.decode(bytes),
+            #[cfg(feature = "bincode")]
+            Self::Bincode => BincodeCodec.decode(bytes),
+            #[allow(unreachable_patterns)]
+            _ => bail!("{self} needs the `{self}` feature"),
+        }
+    }
+}
+
+impl std::fmt::Display for CodecKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Cbor => "cbor",
+            Self::Json => "json",
+            Self::Bincode => "bincode",
+        })
+    }
+}
+
+impl FromStr for CodecKind {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "cbor" => Ok(Self::Cbor),
+            "json" => Ok(Self::Json),
+            "bincode" => Ok(Self::Bincode),
+            _ => bail!("unknown codec `{name}`, expected `cbor`, `json` or `bincode`"),
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Environment variable that overrides where [`Config::load`] looks for the config file
+pub const CONFIG_ENV_VAR: &str = "MULTIPLAYER_CONFIG";
+pub const DEFAULT_CONFIG_PATH: &str = "multiplayer.toml";
+
+/// Launch settings read from a TOML file, any missing keys keep their default
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Host a server at `addr` instead of joining one
+    pub host: bool,
+    pub addr: String,
+    /// RGBA in `0.0..=1.0`, the server picks a color when this is not set
+    pub color: Option<[f32; 4]>,
+    pub name: String,
+    /// One of the preloaded sprites to draw over the circle, see [`crate::client::SPRITE_COUNT`]
+    pub sprite: Option<u32>,
+    /// Zoom to fit every player on screen as soon as they show up
+    pub fit_on_start: bool,
+    /// How far behind other players are drawn so there is always an update to move towards
+    pub interpolation_delay_ms: u64,
+    /// How many times a second circle updates are sent, see
+    /// [`crate::client::ClientConfig::player_update_interval`]
+    pub updates_per_second: u32,
+    /// Circles are never drawn smaller than this many pixels across
+    pub min_circle_pixels: f32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            host: false,
+            addr: "127.0.0.1:1234".to_string(),
+            color: None,
+            name: String::new(),
+            sprite: None,
+            fit_on_start: false,
+            interpolation_delay_ms: 100,
+            updates_per_second: 30,
+            min_circle_pixels: 2.0,
+        }
+    }
+}
+
+impl Config {
+    /// The file named by [`CONFIG_ENV_VAR`], or [`DEFAULT_CONFIG_PATH`] if that is not set
+    pub fn path() -> PathBuf {
+        std::env::var_os(CONFIG_ENV_VAR).map_or_else(|| DEFAULT_CONFIG_PATH.into(), PathBuf::from)
+    }
+
+    /// Loads the file named by [`CONFIG_ENV_VAR`], or [`DEFAULT_CONFIG_PATH`] if that is not set,
+    /// falling back to the defaults if the default file does not exist
+    pub fn load() -> anyhow::Result<Self> {
+        match std::env::var_os(CONFIG_ENV_VAR) {
+            Some(path) => Self::load_from(PathBuf::from(path)),
+            None => match std::fs::read_to_string(DEFAULT_CONFIG_PATH) {
+                Ok(text) => Self::parse(&text)
+                    .with_context(|| format!("failed to parse `{DEFAULT_CONFIG_PATH}`")),
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+                Err(error) => {
+                    Err(error).with_context(|| format!("failed to read `{DEFAULT_CONFIG_PATH}`"))
+                }
+            },
+        }
+    }
+
+    pub fn load_from(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read `{}`", path.display()))?;
+        Self::parse(&text).with_context(|| format!("failed to parse `{}`", path.display()))
+    }
+
+    /// Writes to [`Config::path`], see [`Config::load`]
+    pub fn save(&self) -> anyhow::Result<()> {
+        self.save_to(Self::path())
+    }
+
+    pub fn save_to(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, toml::to_string(self)?)
+            .with_context(|| format!("failed to write `{}`", path.display()))
+    }
+
+    pub fn interpolation_delay(&self) -> Duration {
+        Duration::from_millis(self.interpolation_delay_ms)
+    }
+
+    pub fn player_update_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.updates_per_second.max(1)
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+}
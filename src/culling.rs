@@ -0,0 +1,69 @@
+use cgmath::Vector2;
+
+/// Upper bound on the number of circles uploaded to the gpu each frame
+pub const MAX_RENDERED_CIRCLES: usize = 4096;
+
+/// The region of the world visible through the camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewRect {
+    pub min: Vector2<f32>,
+    pub max: Vector2<f32>,
+}
+
+impl ViewRect {
+    /// A rotated camera sees a rotated rectangle, so this is its bounding box
+    pub fn from_camera(position: Vector2<f32>, aspect: f32, zoom: f32, rotation: f32) -> Self {
+        let (sin, cos) = rotation.sin_cos();
+        let half_size = Vector2 {
+            x: (aspect * cos.abs() + sin.abs()) / zoom,
+            y: (aspect * sin.abs() + cos.abs()) / zoom,
+        };
+        Self {
+            min: position - half_size,
+            max: position + half_size,
+        }
+    }
+
+    pub fn intersects(self, position: Vector2<f32>, extent: f32) -> bool {
+        position.x + extent >= self.min.x
+            && position.x - extent <= self.max.x
+            && position.y + extent >= self.min.y
+            && position.y - extent <= self.max.y
+    }
+}
+
+/// Keeps the items whose bounding box intersects `view`, if more than `max` remain an evenly
+/// spaced subset is kept so the draw order is preserved
+pub fn cull<T>(
+    items: impl IntoIterator<Item = T>,
+    view: ViewRect,
+    bounds: impl Fn(&T) -> (Vector2<f32>, f32),
+    max: usize,
+) -> Vec<T> {
+    let visible = items
+        .into_iter()
+        .filter(|item| {
+            let (position, extent) = bounds(item);
+            view.intersects(position, extent)
+        })
+        .collect::<Vec<_>>();
+    if visible.len() <= max {
+        return visible;
+    }
+
+    let step = visible.len() as f64 / max as f64;
+    let mut next = 0.0;
+    visible
+        .into_iter()
+        .enumerate()
+        .filter(|&(index, _)| {
+            if index as f64 >= next {
+                next += step;
+                true
+            } else {
+                false
+            }
+        })
+        .map(|(_, item)| item)
+        .collect()
+}
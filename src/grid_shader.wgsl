@@ -0,0 +1,64 @@
+struct VertexInput {
+    @builtin(vertex_index) vertex_index: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec2<f32>,
+};
+
+struct Camera {
+    position: vec2<f32>,
+    aspect: f32,
+    zoom: f32,
+    rotation: f32,
+    viewport_height: f32,
+    min_circle_pixels: f32,
+};
+
+@group(0)
+@binding(0)
+var<uniform> camera: Camera;
+
+// rotates offsets from the camera position into view space
+fn view_matrix() -> mat2x2<f32> {
+    let c = cos(camera.rotation);
+    let s = sin(camera.rotation);
+    return mat2x2<f32>(c, -s, s, c);
+}
+
+
+struct Grid {
+    color: vec3<f32>,
+    spacing: f32,
+    background: vec3<f32>,
+};
+
+@group(1)
+@binding(0)
+var<uniform> grid: Grid;
+
+@vertex
+fn vertex(input: VertexInput) -> VertexOutput {
+    var output: VertexOutput;
+
+    let position = vec2<f32>(
+        f32((input.vertex_index >> 0u) & 1u) * 2.0 - 1.0,
+        f32((input.vertex_index >> 1u) & 1u) * 2.0 - 1.0,
+    );
+
+    output.clip_position = vec4<f32>(position, 0.0, 1.0);
+    // the inverse of a rotation is its transpose
+    output.world_position = transpose(view_matrix()) * (position * vec2<f32>(camera.aspect, 1.0) / camera.zoom) + camera.position;
+
+    return output;
+}
+
+@fragment
+fn pixel(input: VertexOutput) -> @location(0) vec4<f32> {
+    let grid_position = input.world_position / grid.spacing;
+    let line_distance = abs(fract(grid_position - 0.5) - 0.5) / fwidth(grid_position);
+    let alpha = 1.0 - min(min(line_distance.x, line_distance.y), 1.0);
+    // the grid covers the whole view, so it also clears it to the background color
+    return vec4<f32>(mix(grid.background, grid.color, alpha), 1.0);
+}
@@ -0,0 +1,50 @@
+use cgmath::Vector2;
+use std::{collections::VecDeque, time::Instant};
+
+pub type Sample = (Instant, Vector2<f32>);
+
+pub fn interpolate(
+    (start_time, start): Sample,
+    (end_time, end): Sample,
+    time: Instant,
+) -> Vector2<f32> {
+    let duration = end_time.saturating_duration_since(start_time).as_secs_f32();
+    if duration <= 0.0 {
+        return end;
+    }
+    let t = (time.saturating_duration_since(start_time).as_secs_f32() / duration).clamp(0.0, 1.0);
+    start + (end - start) * t
+}
+
+#[derive(Default)]
+pub struct PositionHistory {
+    samples: VecDeque<Sample>,
+}
+
+impl PositionHistory {
+    pub fn push(&mut self, time: Instant, position: Vector2<f32>) {
+        self.samples.push_back((time, position));
+    }
+
+    pub fn sample(&self, time: Instant) -> Option<Vector2<f32>> {
+        match self
+            .samples
+            .iter()
+            .position(|&(sample_time, _)| sample_time > time)
+        {
+            Some(0) => self.samples.front().map(|&(_, position)| position),
+            Some(index) => Some(interpolate(
+                self.samples[index - 1],
+                self.samples[index],
+                time,
+            )),
+            None => self.samples.back().map(|&(_, position)| position),
+        }
+    }
+
+    pub fn discard_before(&mut self, time: Instant) {
+        while self.samples.len() > 1 && self.samples[1].0 <= time {
+            self.samples.pop_front();
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::{
+    client::{read_message_with, write_message_with},
+    codec::CodecKind,
+    transport::{TcpTransport, Transport},
+};
+use nanorand::{Rng as _, WyRand};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{io, time::Duration};
+use tokio::{
+    select,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    time::Instant,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct LagConfig {
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    /// The chance of each message being dropped, between 0 and 1
+    pub drop_probability: f32,
+    /// Lets a message overtake the ones sent before it when it gets a shorter delay
+    pub reorder: bool,
+}
+
+impl Default for LagConfig {
+    fn default() -> Self {
+        Self {
+            min_delay: Duration::from_millis(50),
+            max_delay: Duration::from_millis(150),
+            drop_probability: 0.0,
+            reorder: false,
+        }
+    }
+}
+
+struct DelayQueue<T> {
+    config: LagConfig,
+    rng: WyRand,
+    items: Vec<(Instant, T)>,
+}
+
+impl<T> DelayQueue<T> {
+    fn new(config: LagConfig) -> Self {
+        Self {
+            config,
+            rng: WyRand::new(),
+            items: vec![],
+        }
+    }
+
+    fn push(&mut self, item: T) {
+        if self.rng.generate::<f32>() < self.config.drop_probability {
+            return;
+        }
+        let jitter = self.config.max_delay.saturating_sub(self.config.min_delay);
+        let release = Instant::now() + self.config.min_delay + jitter.mul_f32(self.rng.generate());
+        if self.config.reorder {
+            self.items.push((release, item));
+        } else {
+            self.push_last(release, item);
+        }
+    }
+
+    /// Queues `item` so it is released after everything that is already queued
+    fn push_last(&mut self, release: Instant, item: T) {
+        let last = self.items.iter().map(|&(release, _)| release).max();
+        self.items
+            .push((last.map_or(release, |last| last.max(release)), item));
+    }
+
+    fn next_release(&self) -> Option<Instant> {
+        self.items.iter().map(|&(release, _)| release).min()
+    }
+
+    fn pop_due(&mut self) -> Option<T> {
+        let now = Instant::now();
+        let (index, _) = self
+            .items
+            .iter()
+            .enumerate()
+            .filter(|(_, &(release, _))| release <= now)
+            .min_by_key(|(_, &(release, _))| release)?;
+        Some(self.items.remove(index).1)
+    }
+}
+
+enum Command {
+    Send(Vec<u8>),
+    Shutdown(oneshot::Sender<io::Result<()>>),
+}
+
+/// Holds every message in both directions for a random delay and occasionally drops them, to test
+/// interpolation and reconnection without a flaky network
+pub struct LaggyTransport {
+    commands: UnboundedSender<Command>,
+    incoming: UnboundedReceiver<anyhow::Result<Vec<u8>>>,
+    codec: CodecKind,
+}
+
+impl LaggyTransport {
+    pub fn new(transport: TcpTransport, config: LagConfig) -> Self {
+        let (commands, command_receiver) = unbounded_channel();
+        let (incoming_sender, incoming) = unbounded_channel();
+        let codec = transport.codec();
+        tokio::spawn(relay(transport, config, command_receiver, incoming_sender));
+        Self {
+            commands,
+            incoming,
+            codec,
+        }
+    }
+}
+
+impl Transport for LaggyTransport {
+    async fn send<T>(&mut self, message: T) -> anyhow::Result<()>
+    where
+        T: Serialize + Send,
+    {
+        let mut frame = vec![];
+        write_message_with(&mut frame, message, self.codec).await?;
+        self.commands
+            .send(Command::Send(frame))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(())
+    }
+
+    async fn receive<T>(&mut self) -> anyhow::Result<T>
+    where
+        T: DeserializeOwned + Send,
+    {
+        match self.incoming.recv().await {
+            Some(frame) => read_message_with(frame?.as_slice(), self.codec).await,
+            None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        let (done, wait_for_done) = oneshot::channel();
+        self.commands
+            .send(Command::Shutdown(done))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        wait_for_done
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?
+    }
+}
+
+async fn relay(
+    mut transport: TcpTransport,
+    config: LagConfig,
+    mut commands: UnboundedReceiver<Command>,
+    incoming: UnboundedSender<anyhow::Result<Vec<u8>>>,
+) {
+    let mut outgoing = DelayQueue::new(config);
+    let mut delayed_incoming = DelayQueue::new(config);
+    let mut receiving = true;
+    loop {
+        let next_release = outgoing
+            .next_release()
+            .into_iter()
+            .chain(delayed_incoming.next_release())
+            .min();
+        select! {
+            command = commands.recv() => match command {
+                Some(Command::Send(frame)) => outgoing.push(frame),
+                Some(Command::Shutdown(done)) => {
+                    // messages that were already sent still arrive, just late
+                    let result = async {
+                        while let Some(release) = outgoing.next_release() {
+                            tokio::time::sleep_until(release).await;
+                            while let Some(frame) = outgoing.pop_due() {
+                                transport.send_frame(&frame).await?;
+                            }
+                        }
+                        transport.shutdown().await
+                    }
+                    .await;
+                    _ = done.send(result);
+                    return;
+                }
+                None => return,
+            },
+
+            result = transport.receive_frame(), if receiving => match result {
+                Ok(frame) => delayed_incoming.push(Ok(frame)),
+                Err(error) => {
+                    // errors are never dropped, and only show up after the messages before them
+                    receiving = false;
+                    delayed_incoming.push_last(Instant::now(), Err(error));
+                }
+            },
+
+            () = async {
+                match next_release {
+                    Some(release) => tokio::time::sleep_until(release).await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                while let Some(frame) = outgoing.pop_due() {
+                    if let Err(error) = transport.send_frame(&frame).await {
+                        _ = incoming.send(Err(error.into()));
+                        return;
+                    }
+                }
+                while let Some(result) = delayed_incoming.pop_due() {
+                    let Ok(()) = incoming.send(result) else {
+                        return;
+                    };
+                }
+            }
+        }
+    }
+}
@@ -1,153 +1,1695 @@
-use cgmath::Vector2;
-use client::{Circle, Client, ClientToServerMessage, ServerToClientMessage};
-use eframe::{egui, egui_wgpu::Callback};
-use renderer::{create_render_state, GpuCamera, GpuCircle, RenderCallback};
+use anyhow::{bail, Context as _};
+use cgmath::{InnerSpace as _, Matrix2, Rad, Vector2, Vector3, Vector4, VectorSpace as _};
+use client::{
+    Circle, CircleId, CircleKey, Client, ClientConfig, ClientToServerMessage, ConnectionHealth,
+    ConnectionState, DisconnectReason, ServerToClientMessage, Shape, MAX_CHAT_MESSAGE_LENGTH,
+    MAX_NAME_LENGTH, MAX_OUTLINE_THICKNESS, MAX_RADIUS, MIN_RADIUS, SPAWN_CIRCLE_ID, SPRITE_COUNT,
+};
+use config::Config;
+use culling::{cull, ViewRect, MAX_RENDERED_CIRCLES};
+use eframe::{
+    egui,
+    egui_wgpu::{self, Callback},
+    wgpu,
+};
+use interpolation::PositionHistory;
+use renderer::{
+    create_render_state, GpuCamera, GpuCircle, GpuGrid, RenderCallback, TextLabel,
+    NO_ADAPTER_ERROR, NO_SPRITE, SHAPE_CIRCLE, SHAPE_SQUARE,
+};
 use std::{
-    collections::HashMap,
-    net::{SocketAddr, ToSocketAddrs},
+    cell::Cell,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, VecDeque},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
 };
+use tracing::error;
+use uuid::Uuid;
 
 pub mod client;
+pub mod codec;
+pub mod config;
+pub mod culling;
+pub mod interpolation;
+#[cfg(feature = "test-transport")]
+pub mod laggy;
+pub mod logging;
+pub mod recording;
 pub mod renderer;
+pub mod server;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod transport;
+
+const MOVEMENT_SPEED: f32 = 2.0;
+const MOVEMENT_UPDATE_INTERVAL: Duration = Duration::from_millis(50);
+/// How often the server hears about the camera moving, see [`ClientToServerMessage::SetViewport`]
+const VIEWPORT_UPDATE_INTERVAL: Duration = Duration::from_millis(200);
+const MAX_CHAT_HISTORY: usize = 100;
+const MAX_INTERPOLATION_DELAY_MS: u64 = 500;
+const MAX_UPDATES_PER_SECOND: u32 = 120;
+pub const MIN_ZOOM: f32 = 0.01;
+pub const MAX_ZOOM: f32 = 100.0;
+const ZOOM_SPEED: f32 = 0.002;
+const ZOOM_SMOOTHING: f32 = 15.0;
+const CAMERA_PAN_SPEED: f32 = 2.0;
+const CAMERA_ROTATION_SPEED: f32 = 1.5;
+const RECENTER_SMOOTHING: f32 = 8.0;
+/// How much space is left around the circles when fitting them on screen
+const FIT_PADDING: f32 = 1.2;
+const FRAME_TIME_SAMPLES: usize = 120;
+const BACKGROUND_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const HOVER_TINT: f32 = 0.3;
+const SELECTED_OUTLINE_COLOR: Vector3<f32> = Vector3::new(1.0, 1.0, 0.0);
+const SELECTED_OUTLINE_THICKNESS: f32 = 0.05;
+const TAG_OUTLINE_COLOR: Vector3<f32> = Vector3::new(1.0, 0.2, 0.2);
+const TAG_OUTLINE_THICKNESS: f32 = 0.1;
+const MINIMAP_SIZE: f32 = 180.0;
+const MINIMAP_MARGIN: f32 = 8.0;
+const MINIMAP_MIN_DOT_RADIUS: f32 = 1.5;
+const HEARTBEAT_RADIUS: f32 = 5.0;
+const HEARTBEAT_MARGIN: f32 = 12.0;
+const MAX_TRAIL_LENGTH: usize = 100;
+const TRAIL_RADIUS_SCALE: f32 = 0.3;
+const MAX_GLOW: f32 = 1.0;
+const MAX_MIN_CIRCLE_PIXELS: f32 = 16.0;
+/// Seconds it takes a remote circle to fade all the way from one color to another
+const COLOR_TRANSITION_TIME: f32 = 0.2;
+/// Names are drawn in world space, so these are in world units
+const NAME_HEIGHT: f32 = 0.3;
+const NAME_GAP: f32 = 0.05;
+/// How far apart a new circle is put from the one it was copied from, relative to its size
+const NEW_CIRCLE_SPACING: f32 = 2.5;
+/// In the same order as the sprite atlas
+const SPRITE_NAMES: [&str; SPRITE_COUNT as usize] = ["Smiley", "Star", "Heart", "Eye"];
+const BACKENDS: &[(&str, wgpu::Backends)] = &[
+    ("vulkan", wgpu::Backends::VULKAN),
+    ("dx12", wgpu::Backends::DX12),
+    ("metal", wgpu::Backends::METAL),
+    ("gl", wgpu::Backends::GL),
+    ("primary", wgpu::Backends::PRIMARY),
+];
+const POWER_PREFERENCES: &[(&str, wgpu::PowerPreference)] = &[
+    ("high", wgpu::PowerPreference::HighPerformance),
+    ("low", wgpu::PowerPreference::LowPower),
+    ("none", wgpu::PowerPreference::None),
+];
 
 struct Camera {
     position: Vector2<f32>,
     zoom: f32,
+    target_zoom: f32,
+    zoom_anchor: Vector2<f32>,
+    recentering: bool,
+    /// Set to fit every circle on screen, this waits until there are circles to fit
+    fitting: bool,
+    /// Counter-clockwise rotation of the view in radians
+    rotation: f32,
+    /// The camera stays on the middle of this player's circles until it is moved by hand
+    following: Option<Uuid>,
+}
+
+impl Camera {
+    /// Rotates a direction on the screen into world space
+    fn screen_to_world_direction(&self, direction: Vector2<f32>) -> Vector2<f32> {
+        Matrix2::from_angle(Rad(self.rotation)) * direction
+    }
+
+    /// Converts a normalized screen position into world space
+    fn screen_to_world(&self, position: Vector2<f32>, aspect: f32) -> Vector2<f32> {
+        self.position
+            + self.screen_to_world_direction(cgmath::vec2(position.x * aspect, position.y))
+                / self.zoom
+    }
+
+    /// Converts a world position into a normalized screen position
+    fn world_to_screen(&self, position: Vector2<f32>, aspect: f32) -> Vector2<f32> {
+        let view = Matrix2::from_angle(Rad(-self.rotation)) * (position - self.position);
+        cgmath::vec2(view.x / aspect, view.y) * self.zoom
+    }
+}
+
+fn normalized_screen_position(rect: egui::Rect, position: egui::Pos2) -> Vector2<f32> {
+    let position = ((position - rect.left_top()) / rect.size() * 2.0 - egui::vec2(1.0, 1.0))
+        * egui::vec2(1.0, -1.0);
+    cgmath::vec2(position.x, position.y)
+}
+
+struct Grid {
+    color: Vector3<f32>,
+    spacing: f32,
+}
+
+struct Display {
+    background: Vector3<f32>,
+    /// How far circles glow past their edge, zero turns it off
+    glow: f32,
+    /// Remote circles fade to a new color instead of snapping to it
+    animate_colors: bool,
+    /// Draws the sprites players picked, otherwise every circle is a flat color
+    show_sprites: bool,
+    /// Circles are never drawn smaller than this many pixels across, so they dont vanish when
+    /// zoomed out
+    min_circle_pixels: f32,
+}
+
+struct Trails {
+    enabled: bool,
+    length: usize,
+    /// The opacity of the newest point, older points fade out from there
+    opacity: f32,
+    positions: HashMap<CircleKey, VecDeque<Vector2<f32>>>,
+}
+
+impl Trails {
+    fn push(&mut self, key: CircleKey, position: Vector2<f32>) {
+        if !self.enabled {
+            return;
+        }
+        let trail = self.positions.entry(key).or_default();
+        if trail.back() != Some(&position) {
+            trail.push_back(position);
+        }
+        while trail.len() > self.length {
+            trail.pop_front();
+        }
+    }
+}
+
+struct Stats {
+    visible: bool,
+    frame_times: VecDeque<f32>,
+    messages_this_second: u32,
+    messages_per_second: u32,
+    second_start: Instant,
+    rendered_circles: usize,
+}
+
+impl Stats {
+    fn average_frame_time(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        self.frame_times.iter().sum::<f32>() / self.frame_times.len() as f32
+    }
+}
+
+/// Reads `--{flag}=<value>`, falling back to the `env_var` environment variable
+fn graphics_option<T: Copy>(
+    flag: &str,
+    env_var: &str,
+    values: &[(&str, T)],
+) -> anyhow::Result<Option<T>> {
+    let prefix = format!("--{flag}=");
+    let Some(value) = std::env::args()
+        .find_map(|arg| arg.strip_prefix(&prefix).map(str::to_owned))
+        .or_else(|| std::env::var(env_var).ok())
+    else {
+        return Ok(None);
+    };
+    values
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(value.trim()))
+        .map(|&(_, option)| Some(option))
+        .with_context(|| {
+            let names = values.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            format!(
+                "unknown value `{value}` for `--{flag}` or `{env_var}`, expected one of: {}",
+                names.join(", ")
+            )
+        })
+}
+
+/// Window options shared by the binaries, the graphics backend and power preference can be
+/// overridden with `--backend`/`WGPU_BACKEND` and `--power-preference`/`WGPU_POWER_PREF`
+pub fn native_options() -> anyhow::Result<eframe::NativeOptions> {
+    let default = eframe::egui_wgpu::WgpuConfiguration::default();
+    Ok(eframe::NativeOptions {
+        renderer: eframe::Renderer::Wgpu,
+        vsync: false,
+        hardware_acceleration: eframe::HardwareAcceleration::Preferred,
+        wgpu_options: eframe::egui_wgpu::WgpuConfiguration {
+            supported_backends: graphics_option("backend", "WGPU_BACKEND", BACKENDS)?
+                .unwrap_or(default.supported_backends),
+            present_mode: wgpu::PresentMode::AutoNoVsync,
+            power_preference: graphics_option(
+                "power-preference",
+                "WGPU_POWER_PREF",
+                POWER_PREFERENCES,
+            )?
+            .unwrap_or(wgpu::PowerPreference::HighPerformance),
+            ..default
+        },
+        ..Default::default()
+    })
+}
+
+/// Opens the window and runs the app that `create` makes, errors from creating the app, like not
+/// finding a gpu, are returned instead of panicking inside eframe
+pub fn run_app(
+    title: &str,
+    create: impl FnOnce(&eframe::CreationContext) -> anyhow::Result<App> + 'static,
+) -> anyhow::Result<()> {
+    let create_error = Rc::new(Cell::new(None));
+    let result = eframe::run_native(
+        title,
+        native_options()?,
+        Box::new({
+            let create_error = create_error.clone();
+            move |cc| match create(cc) {
+                Ok(app) => Box::new(app),
+                Err(error) => {
+                    create_error.set(Some(error));
+                    Box::new(Closing)
+                }
+            }
+        }),
+    );
+    if let Some(error) = create_error.take() {
+        return Err(error);
+    }
+    match result {
+        Err(eframe::Error::Wgpu(egui_wgpu::WgpuError::NoSuitableAdapterFound)) => {
+            bail!(NO_ADAPTER_ERROR)
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Stands in for an app that failed to start, so the window closes straight away
+struct Closing;
+
+impl eframe::App for Closing {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+    }
+}
+
+/// The players own circles are always drawn on top, then translucent circles are drawn after the
+/// opaque ones so they blend over them, and bigger circles are drawn first so they dont hide
+/// smaller ones, sorting by key keeps the order from flickering between frames
+fn draw_order_key(
+    key: CircleKey,
+    circle: &Circle,
+    own_uuid: Uuid,
+) -> (bool, bool, Reverse<u32>, CircleKey) {
+    (
+        key.0 == own_uuid,
+        circle.color.w < 1.0,
+        // extents are never negative, and positive floats order the same as their bits
+        Reverse(circle.shape.extent().to_bits()),
+        key,
+    )
+}
+
+enum ChatEntry {
+    Message(Uuid, String),
+    Notice(String),
 }
 
 pub struct App {
     camera: Camera,
-    circle: Circle,
-    circles: HashMap<SocketAddr, Circle>,
+    grid: Grid,
+    display: Display,
+    own_circles: BTreeMap<CircleId, Circle>,
+    /// The circle that moves and is edited in the settings, this is always in `own_circles`
+    controlled: CircleId,
+    /// What the circles are reset to, see [`Circle::spawn`]
+    start_color: Vector4<f32>,
+    /// The circle from before the last reset, so it can be put back
+    undo_reset: Option<(CircleId, Circle)>,
+    /// The own circle being dragged with the primary button, and where it was grabbed relative
+    /// to its center
+    dragging: Option<(CircleId, Vector2<f32>)>,
+    circle_moved: bool,
+    last_movement_update: Instant,
+    /// The last [`ClientToServerMessage::SetViewport`] and when it was sent
+    sent_viewport: (Option<(Vector2<f32>, f32)>, Instant),
+    name: String,
+    sprite: Option<u32>,
+    circles: HashMap<CircleKey, Circle>,
+    position_histories: HashMap<CircleKey, PositionHistory>,
+    trails: Trails,
+    /// Where each circle was drawn in the last frame
+    render_positions: HashMap<CircleKey, Vector2<f32>>,
+    /// What color each circle was drawn with in the last frame
+    render_colors: HashMap<CircleKey, Vector4<f32>>,
+    interpolation_delay: Duration,
+    names: HashMap<Uuid, String>,
+    sprites: HashMap<Uuid, u32>,
+    /// Who is it when the server is playing tag
+    tagged: Option<Uuid>,
+    tag_scores: HashMap<Uuid, Duration>,
+    /// Set by the host, movement is ignored until it is unpaused
+    paused: bool,
+    chat_history: VecDeque<ChatEntry>,
+    chat_input: String,
+    stats: Stats,
+    selected: Option<CircleKey>,
+    show_minimap: bool,
+    share_addresses: Vec<SocketAddr>,
+    /// Where the "Network Tuning" values are written back to on exit, see [`App::save_tuning_to`]
+    tuning_path: Option<PathBuf>,
     client: Client,
-    _runtime: tokio::runtime::Runtime,
+    runtime: tokio::runtime::Runtime,
 }
 
 impl App {
-    pub fn new(cc: &eframe::CreationContext, host: bool) -> Self {
-        create_render_state(cc);
-        let runtime = tokio::runtime::Builder::new_multi_thread()
+    /// Hosts or joins `addr` depending on [`Config::host`], `addr` can be a hostname like
+    /// `"example.com:1234"`
+    pub fn new(
+        cc: &eframe::CreationContext,
+        addr: &str,
+        config: &Config,
+        client_config: ClientConfig,
+    ) -> anyhow::Result<Self> {
+        // checked first so a missing gpu doesnt leave a server running for nothing
+        create_render_state(cc)?;
+        let runtime = Self::create_runtime();
+        let spectator = client_config.spectator;
+        let client = runtime.block_on(async {
+            if config.host {
+                let addr = tokio::net::lookup_host(addr)
+                    .await
+                    .with_context(|| format!("failed to look up `{addr}`"))?
+                    .next()
+                    .with_context(|| format!("`{addr}` did not resolve to any addresses"))?;
+                let server_config = server::ServerConfig {
+                    codec: client_config.codec,
+                    ..Default::default()
+                };
+                Client::create_local_with_config(addr, server_config).await
+            } else {
+                Client::connect_host_with_config(addr, client_config).await
+            }
+        })?;
+        Ok(Self::with_client(runtime, client, spectator, config))
+    }
+
+    /// Plays back a log written by [`App::record`] as a spectator
+    pub fn replay(cc: &eframe::CreationContext, path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        create_render_state(cc)?;
+        let runtime = Self::create_runtime();
+        let path = path.as_ref();
+        let client = runtime
+            .block_on(Client::replay(path))
+            .with_context(|| format!("failed to open `{}`", path.display()))?;
+        Ok(Self::with_client(runtime, client, true, &Config::default()))
+    }
+
+    pub fn record(&mut self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        self.client.record(path)
+    }
+
+    /// Writes the interpolation delay and send rate back into the config file at `path` on exit
+    pub fn save_tuning_to(&mut self, path: impl Into<PathBuf>) {
+        self.tuning_path = Some(path.into());
+    }
+
+    fn updates_per_second(&self) -> u32 {
+        (1.0 / self.client.player_update_interval().as_secs_f64()).round() as u32
+    }
+
+    fn save_tuning(&self, path: &Path) -> anyhow::Result<()> {
+        // read again instead of keeping the loaded config so command line overrides arent saved
+        let mut config = if path.exists() {
+            Config::load_from(path)?
+        } else {
+            Config::default()
+        };
+        let saved = config.clone();
+        config.interpolation_delay_ms = self.interpolation_delay.as_millis() as u64;
+        config.updates_per_second = self.updates_per_second();
+        if config != saved {
+            config.save_to(path)?;
+        }
+        Ok(())
+    }
+
+    fn create_runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_multi_thread()
             .enable_all()
             .build()
-            .unwrap();
-        let app = Self {
+            .unwrap()
+    }
+
+    fn with_client(
+        runtime: tokio::runtime::Runtime,
+        client: Client,
+        spectator: bool,
+        config: &Config,
+    ) -> Self {
+        let start_color = config
+            .color
+            .map_or_else(|| client.default_color(), Vector4::from);
+        let mut app = Self {
             camera: Camera {
                 position: cgmath::vec2(0.0, 0.0),
                 zoom: 1.0,
+                target_zoom: 1.0,
+                zoom_anchor: cgmath::vec2(0.0, 0.0),
+                recentering: false,
+                fitting: config.fit_on_start,
+                rotation: 0.0,
+                following: None,
             },
-            circle: Circle {
-                position: cgmath::vec2(0.0, 0.0),
-                color: cgmath::vec3(0.0, 0.0, 1.0),
-                radius: 0.5,
+            grid: Grid {
+                color: cgmath::vec3(0.2, 0.2, 0.2),
+                spacing: 1.0,
             },
+            display: Display {
+                background: cgmath::vec3(0.0, 0.0, 0.0),
+                glow: 0.0,
+                animate_colors: true,
+                show_sprites: true,
+                min_circle_pixels: config.min_circle_pixels,
+            },
+            own_circles: BTreeMap::from([(
+                SPAWN_CIRCLE_ID,
+                Circle::spawn(client.spawn_position(), start_color),
+            )]),
+            controlled: SPAWN_CIRCLE_ID,
+            start_color,
+            undo_reset: None,
+            dragging: None,
+            circle_moved: false,
+            last_movement_update: Instant::now(),
+            sent_viewport: (None, Instant::now()),
+            name: config.name.chars().take(MAX_NAME_LENGTH).collect(),
+            sprite: config.sprite.filter(|&sprite| sprite < SPRITE_COUNT),
             circles: HashMap::new(),
-            client: runtime.block_on(async {
-                let [addr] = "127.0.0.1:1234"
-                    .to_socket_addrs()
-                    .unwrap()
-                    .collect::<Vec<_>>()
-                    .try_into()
-                    .unwrap();
-                if host {
-                    Client::create_local(addr).await.unwrap()
-                } else {
-                    Client::connect(addr).await.unwrap()
-                }
-            }),
-            _runtime: runtime,
+            position_histories: HashMap::new(),
+            trails: Trails {
+                enabled: true,
+                length: 20,
+                opacity: 0.5,
+                positions: HashMap::new(),
+            },
+            render_positions: HashMap::new(),
+            render_colors: HashMap::new(),
+            interpolation_delay: config.interpolation_delay(),
+            names: HashMap::new(),
+            sprites: HashMap::new(),
+            tagged: None,
+            tag_scores: HashMap::new(),
+            paused: false,
+            chat_history: VecDeque::new(),
+            chat_input: String::new(),
+            stats: Stats {
+                visible: false,
+                frame_times: VecDeque::new(),
+                messages_this_second: 0,
+                messages_per_second: 0,
+                second_start: Instant::now(),
+                rendered_circles: 0,
+            },
+            selected: None,
+            show_minimap: true,
+            share_addresses: client.share_addresses(),
+            tuning_path: None,
+            client,
+            runtime,
         };
-        app.client
-            .send_message(ClientToServerMessage::PlayerChanged(app.circle))
-            .unwrap();
+        if spectator {
+            app.client.set_spectator(true).unwrap();
+        } else {
+            app.send_own_circles();
+        }
+        if !app.name.is_empty() {
+            app.client
+                .send_message(ClientToServerMessage::SetName(app.name.clone()))
+                .unwrap();
+        }
+        if app.sprite.is_some() {
+            app.client
+                .send_message(ClientToServerMessage::SetSprite(app.sprite))
+                .unwrap();
+        }
         app
     }
+
+    fn minimap(&mut self, ui: &mut egui::Ui, rect: egui::Rect, aspect: f32) {
+        let world_bounds = self.client.world_bounds();
+        let world_size = world_bounds.max - world_bounds.min;
+        let world_center = (world_bounds.min + world_bounds.max) / 2.0;
+        let scale = (rect.width() / world_size.x).min(rect.height() / world_size.y);
+        let to_minimap = |position: Vector2<f32>| {
+            rect.center()
+                + egui::vec2(
+                    (position.x - world_center.x) * scale,
+                    (world_center.y - position.y) * scale,
+                )
+        };
+
+        let response = ui.interact(rect, ui.id().with("Minimap"), egui::Sense::click_and_drag());
+        if response.clicked() || response.dragged_by(egui::PointerButton::Primary) {
+            if let Some(pointer) = response.interact_pointer_pos() {
+                let offset = pointer - rect.center();
+                self.camera.position = world_bounds.clamp(Vector2 {
+                    x: world_center.x + offset.x / scale,
+                    y: world_center.y - offset.y / scale,
+                });
+                self.camera.recentering = false;
+                self.camera.following = None;
+            }
+        }
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 4.0, egui::Color32::from_black_alpha(200));
+        painter.rect_stroke(
+            egui::Rect::from_two_pos(to_minimap(world_bounds.min), to_minimap(world_bounds.max)),
+            0.0,
+            egui::Stroke::new(1.0, egui::Color32::RED),
+        );
+
+        let mut dots = self
+            .circles
+            .iter()
+            .map(|(key, circle)| (self.render_positions[key], circle))
+            .collect::<Vec<_>>();
+        if !self.client.is_spectator() {
+            let own_uuid = self.client.uuid();
+            dots.extend(
+                self.own_circles
+                    .iter()
+                    .filter(|&(&id, _)| !self.circles.contains_key(&(own_uuid, id)))
+                    .map(|(_, circle)| (circle.position, circle)),
+            );
+        }
+        for (position, circle) in dots {
+            let [r, g, b, a] = circle.color.into();
+            painter.circle_filled(
+                to_minimap(position),
+                (circle.shape.extent() * scale).max(MINIMAP_MIN_DOT_RADIUS),
+                egui::Rgba::from_rgba_unmultiplied(r, g, b, a),
+            );
+        }
+
+        let view = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .into_iter()
+            .map(|(x, y)| to_minimap(self.camera.screen_to_world(cgmath::vec2(x, y), aspect)))
+            .collect();
+        painter.add(egui::Shape::closed_line(
+            view,
+            egui::Stroke::new(1.0, egui::Color32::WHITE),
+        ));
+    }
+
+    /// Finds the circle under `world_position` as it was last drawn, picking the one on top if
+    /// several overlap
+    fn circle_at(&self, world_position: Vector2<f32>) -> Option<CircleKey> {
+        self.circles
+            .iter()
+            .filter(|&(key, circle)| {
+                self.render_positions
+                    .get(key)
+                    .is_some_and(|&position| circle.shape.contains(position, world_position))
+            })
+            .max_by_key(|&(&key, circle)| draw_order_key(key, circle, self.client.uuid()))
+            .map(|(&key, _)| key)
+    }
+
+    fn player_changed(&mut self, key: CircleKey, circle: Circle) {
+        self.circles.insert(key, circle);
+        self.position_histories
+            .entry(key)
+            .or_default()
+            .push(Instant::now(), circle.position);
+        self.trails.push(key, circle.position);
+    }
+
+    fn remove_circles(&mut self, mut remove: impl FnMut(CircleKey) -> bool) {
+        self.circles.retain(|&key, _| !remove(key));
+        self.position_histories.retain(|&key, _| !remove(key));
+        self.trails.positions.retain(|&key, _| !remove(key));
+    }
+
+    /// Puts the controlled circle back how it started, at the spawn position
+    fn reset_controlled_circle(&mut self) {
+        let circle = Circle::spawn(self.client.spawn_position(), self.start_color);
+        let previous = self.own_circles.insert(self.controlled, circle);
+        self.undo_reset = previous.map(|previous| (self.controlled, previous));
+        _ = self
+            .client
+            .send_message(ClientToServerMessage::PlayerChanged(
+                self.controlled,
+                circle,
+            ));
+    }
+
+    /// (Re)sends every circle this player has, for when the server doesnt know about them yet
+    fn send_own_circles(&self) {
+        for (&id, &circle) in &self.own_circles {
+            _ = self
+                .client
+                .send_message(ClientToServerMessage::AddCircle(id, circle));
+        }
+    }
+
+    /// Copies the controlled circle next to itself and takes control of the copy
+    fn add_circle(&mut self) {
+        let id = self.own_circles.keys().last().map_or(0, |&id| id + 1);
+        let mut circle = self.own_circles[&self.controlled];
+        circle.position = self
+            .client
+            .world_bounds()
+            .clamp(circle.position + cgmath::vec2(circle.shape.extent() * NEW_CIRCLE_SPACING, 0.0));
+        self.own_circles.insert(id, circle);
+        self.controlled = id;
+        if !self.client.is_spectator() {
+            _ = self
+                .client
+                .send_message(ClientToServerMessage::AddCircle(id, circle));
+        }
+    }
+
+    /// The last circle is never removed, so there is always one to control
+    fn remove_controlled_circle(&mut self) {
+        if self.own_circles.len() <= 1 {
+            return;
+        }
+        self.own_circles.remove(&self.controlled);
+        _ = self
+            .client
+            .send_message(ClientToServerMessage::RemoveCircle(self.controlled));
+        self.controlled = *self.own_circles.keys().next().unwrap();
+    }
+
+    /// The middle of every circle the player has, as they were last drawn
+    fn player_position(&self, uuid: Uuid) -> Option<Vector2<f32>> {
+        let (sum, count) = self
+            .render_positions
+            .iter()
+            .filter(|&(&(owner, _), _)| owner == uuid)
+            .fold(
+                (cgmath::vec2(0.0, 0.0), 0),
+                |(sum, count), (_, &position)| (sum + position, count + 1),
+            );
+        (count > 0).then(|| sum / count as f32)
+    }
+
+    /// The color of the player's first circle
+    fn player_color(&self, uuid: Uuid) -> Option<Vector4<f32>> {
+        self.circles
+            .iter()
+            .filter(|&(&(owner, _), _)| owner == uuid)
+            .min_by_key(|&(&(_, id), _)| id)
+            .map(|(_, circle)| circle.color)
+    }
+
+    /// Moves and zooms the camera so every circle is on screen, this does nothing if there are no
+    /// circles yet
+    fn fit_to_circles(&mut self, aspect: f32) {
+        // the bounds are taken in view space so they still fit tightly when the view is rotated
+        let to_view = Matrix2::from_angle(Rad(-self.camera.rotation));
+        let Some((min, max)) = self
+            .circles
+            .iter()
+            .map(|(key, circle)| {
+                let position = to_view * self.render_positions[key];
+                let extent = circle.shape.extent() + self.display.glow;
+                (
+                    position - cgmath::vec2(extent, extent),
+                    position + cgmath::vec2(extent, extent),
+                )
+            })
+            .reduce(|(min, max), (other_min, other_max)| {
+                (
+                    cgmath::vec2(min.x.min(other_min.x), min.y.min(other_min.y)),
+                    cgmath::vec2(max.x.max(other_max.x), max.y.max(other_max.y)),
+                )
+            })
+        else {
+            return;
+        };
+
+        let half_size = (max - min) / 2.0 * FIT_PADDING;
+        let zoom = (aspect / half_size.x)
+            .min(1.0 / half_size.y)
+            .clamp(MIN_ZOOM, MAX_ZOOM);
+        self.camera.position = Matrix2::from_angle(Rad(self.camera.rotation)) * ((min + max) / 2.0);
+        self.camera.zoom = zoom;
+        self.camera.target_zoom = zoom;
+        self.camera.fitting = false;
+        self.camera.recentering = false;
+        self.camera.following = None;
+    }
+
+    fn push_chat(&mut self, entry: ChatEntry) {
+        if self.chat_history.len() >= MAX_CHAT_HISTORY {
+            self.chat_history.pop_front();
+        }
+        self.chat_history.push_back(entry);
+    }
+
+    fn display_name(&self, uuid: Uuid) -> String {
+        self.names
+            .get(&uuid)
+            .filter(|name| !name.is_empty())
+            .cloned()
+            .unwrap_or_else(|| uuid.to_string())
+    }
 }
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
-        while let Some(message) = self.client.get_message() {
-            match message.unwrap() {
-                ServerToClientMessage::ClientConnected(addr) => {
-                    let new = self
-                        .circles
-                        .insert(
-                            addr,
-                            Circle {
-                                position: cgmath::vec2(0.0, 0.0),
-                                color: cgmath::vec3(1.0, 0.0, 1.0),
-                                radius: 0.5,
-                            },
-                        )
-                        .is_none();
-                    assert!(new);
-                }
-                ServerToClientMessage::ClientDisconnected(addr) => {
-                    let exists = self.circles.remove(&addr).is_some();
-                    assert!(exists);
-                }
-                ServerToClientMessage::Ping => {
-                    self.client
-                        .send_message(ClientToServerMessage::Ping)
-                        .unwrap();
+        let messages = self
+            .client
+            .drain()
+            .map_while(Result::ok)
+            .collect::<Vec<_>>();
+        self.stats.messages_this_second += messages.len() as u32;
+        for message in messages {
+            match message {
+                ServerToClientMessage::Handshake { .. }
+                | ServerToClientMessage::Rejected(_)
+                | ServerToClientMessage::Unauthorized
+                | ServerToClientMessage::Info { .. }
+                | ServerToClientMessage::Ack(_)
+                | ServerToClientMessage::ClientConnected(_)
+                | ServerToClientMessage::ClientReconnected(_) => {}
+                ServerToClientMessage::ClientDisconnected(uuid, reason) => {
+                    let name = self.display_name(uuid);
+                    self.push_chat(ChatEntry::Notice(match reason {
+                        DisconnectReason::Quit => format!("{name} left"),
+                        DisconnectReason::Timeout => format!("{name} timed out"),
+                        DisconnectReason::Kicked => format!("{name} was kicked"),
+                        DisconnectReason::Idle => format!("{name} was kicked for being idle"),
+                        DisconnectReason::Error => format!("{name} lost connection"),
+                    }));
+                    self.remove_circles(|(owner, _)| owner == uuid);
+                    self.names.remove(&uuid);
+                    self.sprites.remove(&uuid);
+                    self.tag_scores.remove(&uuid);
+                }
+                ServerToClientMessage::Ping(id) => {
+                    _ = self.client.send_message(ClientToServerMessage::Ping(id));
+                }
+                ServerToClientMessage::Latency(_) => {}
+                ServerToClientMessage::ProtocolError(error) => {
+                    self.push_chat(ChatEntry::Notice(format!("Protocol error: {error}")));
+                }
+                ServerToClientMessage::Reconnecting => {
+                    self.circles.clear();
+                    self.position_histories.clear();
+                    self.trails.positions.clear();
+                    self.names.clear();
+                    self.sprites.clear();
+                }
+                ServerToClientMessage::Reconnected(_) => {
+                    // the server says so again if the world is still paused
+                    self.paused = false;
+                    self.sent_viewport.0 = None;
+                    if !self.client.is_spectator() {
+                        self.send_own_circles();
+                    }
+                    if !self.name.is_empty() {
+                        _ = self
+                            .client
+                            .send_message(ClientToServerMessage::SetName(self.name.clone()));
+                    }
+                    if self.sprite.is_some() {
+                        _ = self
+                            .client
+                            .send_message(ClientToServerMessage::SetSprite(self.sprite));
+                    }
+                }
+                ServerToClientMessage::PlayerChanged(key, circle) => {
+                    self.player_changed(key, circle);
+                }
+                ServerToClientMessage::MovedTo(key, position) => {
+                    if let Some(&circle) = self.circles.get(&key) {
+                        self.player_changed(key, Circle { position, ..circle });
+                    }
+                }
+                ServerToClientMessage::PlayerDelta(key, delta) => {
+                    if let Some(&circle) = self.circles.get(&key) {
+                        self.player_changed(key, delta.apply(circle));
+                    }
+                }
+                ServerToClientMessage::Snapshot(circles)
+                | ServerToClientMessage::InitialState(circles) => {
+                    for (key, circle) in circles {
+                        self.player_changed(key, circle);
+                    }
+                }
+                ServerToClientMessage::PositionCorrected(id, position) => {
+                    if let Some(circle) = self.own_circles.get_mut(&id) {
+                        circle.position = position;
+                    }
+                }
+                ServerToClientMessage::CircleRemoved(removed)
+                | ServerToClientMessage::LeftView(removed) => {
+                    self.remove_circles(|key| key == removed);
+                }
+                ServerToClientMessage::NameChanged(uuid, name) => {
+                    self.names.insert(uuid, name);
+                }
+                ServerToClientMessage::SpriteChanged(uuid, sprite) => match sprite {
+                    Some(sprite) => {
+                        self.sprites.insert(uuid, sprite);
+                    }
+                    None => {
+                        self.sprites.remove(&uuid);
+                    }
+                },
+                ServerToClientMessage::SpectatorChanged(uuid, spectator) => {
+                    if spectator {
+                        self.remove_circles(|(owner, _)| owner == uuid);
+                    }
                 }
-                ServerToClientMessage::PlayerChanged(addr, circle) => {
-                    *self.circles.get_mut(&addr).unwrap() = circle;
+                ServerToClientMessage::TagChanged(uuid) => {
+                    self.tagged = Some(uuid);
                 }
+                ServerToClientMessage::TagScores(scores) => {
+                    self.tag_scores = scores.into_iter().collect();
+                }
+                ServerToClientMessage::Paused(paused) => {
+                    self.paused = paused;
+                }
+                ServerToClientMessage::ServerShutdown | ServerToClientMessage::Kicked => {}
+                // batches are unpacked by the client
+                ServerToClientMessage::Batch(_) => {}
+                ServerToClientMessage::Chat(uuid, text) => {
+                    self.push_chat(ChatEntry::Message(uuid, text));
+                }
+            }
+        }
+
+        if !ctx.wants_keyboard_input() {
+            let (direction, rotation, recenter, fit, dt) = ctx.input(|input| {
+                let mut direction = cgmath::vec2(0.0f32, 0.0);
+                if input.key_down(egui::Key::W) || input.key_down(egui::Key::ArrowUp) {
+                    direction.y += 1.0;
+                }
+                if input.key_down(egui::Key::S) || input.key_down(egui::Key::ArrowDown) {
+                    direction.y -= 1.0;
+                }
+                if input.key_down(egui::Key::A) || input.key_down(egui::Key::ArrowLeft) {
+                    direction.x -= 1.0;
+                }
+                if input.key_down(egui::Key::D) || input.key_down(egui::Key::ArrowRight) {
+                    direction.x += 1.0;
+                }
+                let mut rotation = 0.0;
+                if input.key_down(egui::Key::Q) {
+                    rotation += 1.0;
+                }
+                if input.key_down(egui::Key::E) {
+                    rotation -= 1.0;
+                }
+                let recenter =
+                    input.key_pressed(egui::Key::Home) || input.key_pressed(egui::Key::Space);
+                let fit = input.key_pressed(egui::Key::F);
+                (direction, rotation, recenter, fit, input.stable_dt)
+            });
+            if recenter {
+                self.camera.recentering = true;
+                self.camera.following = None;
+            }
+            if fit {
+                self.camera.fitting = true;
             }
+            self.camera.rotation = (self.camera.rotation + rotation * CAMERA_ROTATION_SPEED * dt)
+                .rem_euclid(std::f32::consts::TAU);
+            if direction != cgmath::vec2(0.0, 0.0) {
+                // movement follows the screen, not the world axes
+                let direction = self.camera.screen_to_world_direction(direction);
+                if self.client.is_spectator() || self.paused {
+                    self.camera.position +=
+                        direction.normalize() * CAMERA_PAN_SPEED / self.camera.zoom * dt;
+                    self.camera.recentering = false;
+                    self.camera.following = None;
+                } else {
+                    self.camera.following = None;
+                    let world_bounds = self.client.world_bounds();
+                    let circle = self.own_circles.get_mut(&self.controlled).unwrap();
+                    circle.position = world_bounds
+                        .clamp(circle.position + direction.normalize() * MOVEMENT_SPEED * dt);
+                    self.circle_moved = true;
+                }
+            }
+        }
+        if self.circle_moved && self.last_movement_update.elapsed() >= MOVEMENT_UPDATE_INTERVAL {
+            self.circle_moved = false;
+            self.last_movement_update = Instant::now();
+            _ = self.client.send_message(ClientToServerMessage::MovedTo(
+                self.controlled,
+                self.own_circles[&self.controlled].position,
+            ));
+        }
+
+        let (minimized, focused) =
+            ctx.input(|input| (input.viewport().minimized == Some(true), input.focused));
+        if minimized {
+            // nothing is visible, so only wake up often enough to answer pings and stay current
+            ctx.request_repaint_after(BACKGROUND_POLL_INTERVAL);
+            return;
         }
 
         egui::Window::new("Circle Settings").show(ctx, |ui| {
+            let mut spectator = self.client.is_spectator();
+            if ui.checkbox(&mut spectator, "Spectate").changed() {
+                _ = self.client.set_spectator(spectator);
+                if !spectator {
+                    self.send_own_circles();
+                }
+            }
+            ui.horizontal(|ui| {
+                ui.label("Name: ");
+                if ui
+                    .add(egui::TextEdit::singleline(&mut self.name).char_limit(MAX_NAME_LENGTH))
+                    .changed()
+                {
+                    _ = self
+                        .client
+                        .send_message(ClientToServerMessage::SetName(self.name.clone()));
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Circle: ");
+                egui::ComboBox::from_id_source("Controlled Circle")
+                    .selected_text(format!("#{}", self.controlled))
+                    .show_ui(ui, |ui| {
+                        for &id in self.own_circles.keys() {
+                            ui.selectable_value(&mut self.controlled, id, format!("#{id}"));
+                        }
+                    });
+                if ui.button("Add").clicked() {
+                    self.add_circle();
+                }
+                if ui
+                    .add_enabled(self.own_circles.len() > 1, egui::Button::new("Remove"))
+                    .clicked()
+                {
+                    self.remove_controlled_circle();
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Reset").clicked() {
+                    self.reset_controlled_circle();
+                }
+                let undo = self
+                    .undo_reset
+                    .filter(|&(id, _)| self.own_circles.contains_key(&id));
+                if ui
+                    .add_enabled(undo.is_some(), egui::Button::new("Undo Reset"))
+                    .clicked()
+                {
+                    let (id, circle) = undo.unwrap();
+                    self.undo_reset = None;
+                    self.own_circles.insert(id, circle);
+                    _ = self
+                        .client
+                        .send_message(ClientToServerMessage::PlayerChanged(id, circle));
+                }
+            });
+            let controlled = self.controlled;
+            let circle = self.own_circles.get_mut(&controlled).unwrap();
             ui.horizontal(|ui| {
                 ui.label("Color: ");
+                let color_changed = ui
+                    .color_edit_button_rgba_unmultiplied(circle.color.as_mut())
+                    .changed();
+                let alpha_changed = ui
+                    .add(egui::Slider::new(&mut circle.color.w, 0.0..=1.0).text("Alpha"))
+                    .changed();
+                if color_changed || alpha_changed {
+                    _ = self
+                        .client
+                        .send_message(ClientToServerMessage::PlayerChanged(controlled, *circle));
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Outline: ");
+                let color_changed = ui
+                    .color_edit_button_rgb(circle.outline_color.as_mut())
+                    .changed();
+                let thickness_changed = ui
+                    .add(egui::Slider::new(
+                        &mut circle.outline_thickness,
+                        0.0..=MAX_OUTLINE_THICKNESS,
+                    ))
+                    .changed();
+                if color_changed || thickness_changed {
+                    _ = self
+                        .client
+                        .send_message(ClientToServerMessage::PlayerChanged(controlled, *circle));
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Shape: ");
+                let extent = circle.shape.extent();
+                let mut changed = false;
+                egui::ComboBox::from_id_source("Shape")
+                    .selected_text(match circle.shape {
+                        Shape::Circle { .. } => "Circle",
+                        Shape::Square { .. } => "Square",
+                    })
+                    .show_ui(ui, |ui| {
+                        changed |= ui
+                            .selectable_value(
+                                &mut circle.shape,
+                                Shape::Circle { radius: extent },
+                                "Circle",
+                            )
+                            .changed();
+                        changed |= ui
+                            .selectable_value(
+                                &mut circle.shape,
+                                Shape::Square { size: extent * 2.0 },
+                                "Square",
+                            )
+                            .changed();
+                    });
+                if changed {
+                    _ = self
+                        .client
+                        .send_message(ClientToServerMessage::PlayerChanged(controlled, *circle));
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Sprite: ");
+                let mut changed = false;
+                egui::ComboBox::from_id_source("Sprite")
+                    .selected_text(
+                        self.sprite
+                            .map_or("None", |sprite| SPRITE_NAMES[sprite as usize]),
+                    )
+                    .show_ui(ui, |ui| {
+                        changed |= ui
+                            .selectable_value(&mut self.sprite, None, "None")
+                            .changed();
+                        for (sprite, name) in (0..).zip(SPRITE_NAMES) {
+                            changed |= ui
+                                .selectable_value(&mut self.sprite, Some(sprite), name)
+                                .changed();
+                        }
+                    });
+                if changed {
+                    _ = self
+                        .client
+                        .send_message(ClientToServerMessage::SetSprite(self.sprite));
+                }
+            });
+            ui.horizontal(|ui| {
+                let slider = match &mut circle.shape {
+                    Shape::Circle { radius } => {
+                        ui.label("Radius: ");
+                        egui::Slider::new(radius, MIN_RADIUS..=MAX_RADIUS)
+                    }
+                    Shape::Square { size } => {
+                        ui.label("Size: ");
+                        egui::Slider::new(size, MIN_RADIUS * 2.0..=MAX_RADIUS * 2.0)
+                    }
+                };
+                if ui.add(slider).changed() {
+                    circle.shape = circle
+                        .shape
+                        .with_extent(circle.shape.extent().clamp(MIN_RADIUS, MAX_RADIUS));
+                    _ = self
+                        .client
+                        .send_message(ClientToServerMessage::PlayerChanged(controlled, *circle));
+                }
+            });
+        });
+
+        egui::Window::new("View Settings").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Grid Color: ");
+                ui.color_edit_button_rgb(self.grid.color.as_mut());
+            });
+            ui.horizontal(|ui| {
+                ui.label("Grid Spacing: ");
+                ui.add(egui::Slider::new(&mut self.grid.spacing, 0.1..=10.0).logarithmic(true));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Rotation: ");
+                ui.drag_angle(&mut self.camera.rotation);
+                if ui.button("Reset").clicked() {
+                    self.camera.rotation = 0.0;
+                }
+            });
+            if ui.button("Fit to Players (F)").clicked() {
+                self.camera.fitting = true;
+            }
+            ui.checkbox(&mut self.show_minimap, "Minimap");
+            if ui.checkbox(&mut self.trails.enabled, "Trails").changed() && !self.trails.enabled {
+                self.trails.positions.clear();
+            }
+            ui.add_enabled_ui(self.trails.enabled, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Trail Length: ");
+                    ui.add(egui::Slider::new(
+                        &mut self.trails.length,
+                        1..=MAX_TRAIL_LENGTH,
+                    ));
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Trail Opacity: ");
+                    ui.add(egui::Slider::new(&mut self.trails.opacity, 0.0..=1.0));
+                });
+            });
+        });
+
+        egui::Window::new("Display Settings").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Background Color: ");
+                ui.color_edit_button_rgb(self.display.background.as_mut());
+            });
+            ui.horizontal(|ui| {
+                ui.label("Glow: ");
+                ui.add(egui::Slider::new(&mut self.display.glow, 0.0..=MAX_GLOW));
+            });
+            ui.horizontal(|ui| {
+                ui.label("Minimum Circle Size: ");
+                ui.add(
+                    egui::Slider::new(
+                        &mut self.display.min_circle_pixels,
+                        0.0..=MAX_MIN_CIRCLE_PIXELS,
+                    )
+                    .suffix("px"),
+                );
+            });
+            ui.checkbox(&mut self.display.animate_colors, "Animate Color Changes");
+            ui.checkbox(&mut self.display.show_sprites, "Show Sprites");
+        });
+
+        egui::Window::new("Network Tuning").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Interpolation Delay: ");
+                let mut delay_ms = self.interpolation_delay.as_millis() as u64;
+                if ui
+                    .add(
+                        egui::Slider::new(&mut delay_ms, 0..=MAX_INTERPOLATION_DELAY_MS)
+                            .suffix(" ms"),
+                    )
+                    .changed()
+                {
+                    self.interpolation_delay = Duration::from_millis(delay_ms);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Send Rate: ");
+                let mut updates_per_second = self.updates_per_second();
                 if ui
-                    .color_edit_button_rgb(self.circle.color.as_mut())
+                    .add(
+                        egui::Slider::new(&mut updates_per_second, 1..=MAX_UPDATES_PER_SECOND)
+                            .suffix(" /s"),
+                    )
                     .changed()
                 {
                     self.client
-                        .send_message(ClientToServerMessage::PlayerChanged(self.circle))
-                        .unwrap();
+                        .set_player_update_interval(Duration::from_secs(1) / updates_per_second);
                 }
             });
+            if ui.button("Reset").clicked() {
+                let defaults = Config::default();
+                self.interpolation_delay = defaults.interpolation_delay();
+                self.client
+                    .set_player_update_interval(defaults.player_update_interval());
+            }
         });
 
+        egui::Window::new("Chat").show(ctx, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(200.0)
+                .stick_to_bottom(true)
+                .show(ui, |ui| {
+                    for entry in &self.chat_history {
+                        let (uuid, text) = match entry {
+                            ChatEntry::Message(uuid, text) => (uuid, text),
+                            ChatEntry::Notice(text) => {
+                                ui.label(egui::RichText::new(text).italics().weak());
+                                continue;
+                            }
+                        };
+                        ui.horizontal(|ui| {
+                            let color =
+                                self.player_color(*uuid).map_or(egui::Rgba::WHITE, |color| {
+                                    egui::Rgba::from_rgb(color.x, color.y, color.z)
+                                });
+                            egui::color_picker::show_color(ui, color, egui::vec2(12.0, 12.0));
+                            ui.label(format!("{}: {text}", self.display_name(*uuid)));
+                        });
+                    }
+                });
+
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.chat_input)
+                    .char_limit(MAX_CHAT_MESSAGE_LENGTH)
+                    .hint_text("Press enter to send"),
+            );
+            if response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter)) {
+                let text = std::mem::take(&mut self.chat_input);
+                if !text.trim().is_empty() {
+                    _ = self.client.send_message(ClientToServerMessage::Chat(text));
+                }
+                response.request_focus();
+            }
+        });
+
+        if !self.share_addresses.is_empty() {
+            egui::Window::new("Share this address").show(ctx, |ui| {
+                for addr in &self.share_addresses {
+                    ui.horizontal(|ui| {
+                        ui.monospace(addr.to_string());
+                        if ui.button("Copy").clicked() {
+                            ui.output_mut(|output| output.copied_text = addr.to_string());
+                        }
+                    });
+                }
+            });
+        }
+
+        egui::Window::new("Players").show(ctx, |ui| {
+            let own_uuid = self.client.uuid();
+            let hosting = self.client.server_addr().is_some();
+            if hosting {
+                let mut paused = self.paused;
+                if ui.checkbox(&mut paused, "Pause the world").changed() {
+                    _ = self
+                        .client
+                        .send_message(ClientToServerMessage::SetPaused(paused));
+                }
+            }
+            let mut players = self.client.peers();
+            players.push(own_uuid);
+            players.sort();
+            for uuid in players {
+                ui.horizontal(|ui| {
+                    if let Some(color) = self.player_color(uuid) {
+                        let [r, g, b, a] = color.into();
+                        egui::color_picker::show_color(
+                            ui,
+                            egui::Rgba::from_rgba_unmultiplied(r, g, b, a),
+                            egui::vec2(12.0, 12.0),
+                        );
+                    }
+                    ui.label(self.display_name(uuid));
+                    if self.tagged == Some(uuid) {
+                        ui.label("(it)");
+                    }
+                    if let Some(score) = self.tag_scores.get(&uuid) {
+                        ui.label(format!("{}s", score.as_secs()));
+                    }
+                    if uuid == own_uuid {
+                        ui.label(if hosting { "(host)" } else { "(you)" });
+                        return;
+                    }
+
+                    // spectators dont have a circle to go to
+                    let position = self.player_position(uuid);
+                    ui.add_enabled_ui(position.is_some(), |ui| {
+                        if ui.button("Go to").clicked() {
+                            self.camera.position = position.unwrap();
+                            self.camera.recentering = false;
+                            self.camera.following = None;
+                        }
+                        let mut following = self.camera.following == Some(uuid);
+                        if ui.toggle_value(&mut following, "Follow").changed() {
+                            self.camera.recentering = false;
+                            self.camera.following = following.then_some(uuid);
+                        }
+                    });
+                    if hosting && ui.button("Kick").clicked() {
+                        _ = self.client.send_message(ClientToServerMessage::Kick(uuid));
+                    }
+                });
+            }
+        });
+
+        let (frame_time, toggle_stats) =
+            ctx.input(|input| (input.stable_dt, input.key_pressed(egui::Key::F3)));
+        if toggle_stats {
+            self.stats.visible = !self.stats.visible;
+        }
+        if self.stats.frame_times.len() >= FRAME_TIME_SAMPLES {
+            self.stats.frame_times.pop_front();
+        }
+        self.stats.frame_times.push_back(frame_time);
+        if self.stats.second_start.elapsed() >= Duration::from_secs(1) {
+            self.stats.messages_per_second = std::mem::take(&mut self.stats.messages_this_second);
+            self.stats.second_start = Instant::now();
+        }
+
+        let average_frame_time = self.stats.average_frame_time();
+        let messages_per_second = self.stats.messages_per_second;
+        let rendered_circles = self.stats.rendered_circles;
+        egui::Window::new("Stats")
+            .open(&mut self.stats.visible)
+            .show(ctx, |ui| {
+                ui.label(format!("Frame time: {:.2}ms", average_frame_time * 1000.0));
+                ui.label(format!(
+                    "FPS: {:.0}",
+                    if average_frame_time > 0.0 {
+                        1.0 / average_frame_time
+                    } else {
+                        0.0
+                    }
+                ));
+                ui.label(format!("Messages: {messages_per_second}/s"));
+                ui.label(format!(
+                    "Circles: {rendered_circles}/{} rendered",
+                    self.circles.len()
+                ));
+                match self.client.latency() {
+                    Some(latency) => ui.label(format!("Latency: {}ms", latency.as_millis())),
+                    None => ui.label("Latency: ?"),
+                };
+            });
+
+        egui::Area::new("Latency")
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-8.0, 8.0))
+            .show(ctx, |ui| {
+                match self.client.connection_state() {
+                    ConnectionState::Connected => match self.client.latency() {
+                        Some(latency) => ui.label(format!("Ping: {}ms", latency.as_millis())),
+                        None => ui.label("Ping: ?"),
+                    },
+                    ConnectionState::Reconnecting => ui.label("Reconnecting…"),
+                    ConnectionState::Disconnected => ui.label("Disconnected"),
+                    ConnectionState::ServerShutdown => ui.label("Server shut down"),
+                    ConnectionState::Kicked => ui.label("Kicked"),
+                    ConnectionState::ProtocolError => ui.label("Protocol error"),
+                };
+                if let Some(server_addr) = self.client.server_addr() {
+                    ui.label(format!("Hosting on {server_addr}"));
+                }
+            });
+
+        let disconnected_message = match self.client.connection_state() {
+            ConnectionState::Disconnected => Some("Disconnected from the server"),
+            ConnectionState::ServerShutdown => Some("The server has shut down"),
+            ConnectionState::Kicked => Some("You were kicked from the server"),
+            ConnectionState::ProtocolError => {
+                Some("The server sent something this version of the game doesnt understand")
+            }
+            ConnectionState::Connected | ConnectionState::Reconnecting => None,
+        };
+        if self.paused {
+            egui::Area::new("Paused")
+                .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 8.0))
+                .order(egui::Order::Foreground)
+                .show(ctx, |ui| {
+                    ui.label(egui::RichText::new("Paused by the host").heading());
+                });
+        }
+        if let ConnectionHealth::Reconnecting(attempt) = self.client.health() {
+            egui::Area::new("Reconnecting")
+                .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+                .order(egui::Order::Foreground)
+                .show(ctx, |ui| {
+                    ui.label(
+                        egui::RichText::new(format!("Reconnecting (attempt {attempt})…"))
+                            .heading()
+                            .color(egui::Color32::YELLOW),
+                    );
+                });
+        }
+        if let Some(disconnected_message) = disconnected_message {
+            egui::Area::new("Disconnected")
+                .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+                .order(egui::Order::Foreground)
+                .show(ctx, |ui| {
+                    ui.label(
+                        egui::RichText::new(disconnected_message)
+                            .heading()
+                            .color(egui::Color32::RED),
+                    );
+                });
+        }
+
         egui::CentralPanel::default()
-            .frame(egui::Frame::none().fill(egui::Color32::BLACK))
+            .frame(egui::Frame::none().fill({
+                let [r, g, b] = self.display.background.into();
+                egui::Rgba::from_rgb(r, g, b).into()
+            }))
             .show(ctx, |ui| {
                 let (rect, response) =
                     ui.allocate_exact_size(ui.available_size(), egui::Sense::click_and_drag());
                 let aspect = rect.width() / rect.height();
+                let minimap_rect = self.show_minimap.then(|| {
+                    egui::Rect::from_min_max(
+                        rect.right_bottom()
+                            - egui::vec2(
+                                MINIMAP_SIZE + MINIMAP_MARGIN,
+                                MINIMAP_SIZE + MINIMAP_MARGIN,
+                            ),
+                        rect.right_bottom() - egui::vec2(MINIMAP_MARGIN, MINIMAP_MARGIN),
+                    )
+                });
+                let over_minimap = |position: egui::Pos2| {
+                    minimap_rect.is_some_and(|minimap_rect| minimap_rect.contains(position))
+                };
 
-                if response.dragged_by(egui::PointerButton::Secondary) {
+                if response.dragged_by(egui::PointerButton::Secondary)
+                    || response.dragged_by(egui::PointerButton::Middle)
+                {
+                    self.camera.recentering = false;
+                    self.camera.following = None;
                     let delta = response.drag_delta();
-                    self.camera.position.x -=
-                        delta.x / self.camera.zoom / rect.width() * 2.0 * aspect;
-                    self.camera.position.y += delta.y / self.camera.zoom / rect.height() * 2.0;
-                }
-
-                if response.clicked_by(egui::PointerButton::Primary) {
-                    let interact_pointer_pos = response.interact_pointer_pos().unwrap();
-                    let mouse_position = ((interact_pointer_pos - rect.left_top()) / rect.size()
-                        * 2.0
-                        - egui::vec2(1.0, 1.0))
-                        * egui::vec2(1.0, -1.0);
-                    let world_position = Vector2 {
-                        x: mouse_position.x * aspect / self.camera.zoom + self.camera.position.x,
-                        y: mouse_position.y / self.camera.zoom + self.camera.position.y,
-                    };
-
-                    self.circle.position = world_position;
-                    self.client
-                        .send_message(ClientToServerMessage::PlayerChanged(self.circle))
-                        .unwrap();
+                    self.camera.position -= self.camera.screen_to_world_direction(cgmath::vec2(
+                        delta.x / rect.width() * 2.0 * aspect,
+                        -delta.y / rect.height() * 2.0,
+                    )) / self.camera.zoom;
                 }
 
-                if response.hovered() {
-                    ctx.input(|input| match input.scroll_delta.y.total_cmp(&0.0) {
-                        std::cmp::Ordering::Less => self.camera.zoom *= 0.9,
-                        std::cmp::Ordering::Greater => self.camera.zoom /= 0.9,
-                        _ => {}
+                let (scroll, hover_pos, dt) = ctx.input(|input| {
+                    (
+                        input.scroll_delta.y,
+                        input.pointer.hover_pos(),
+                        input.stable_dt,
+                    )
+                });
+                if response.hovered() && scroll != 0.0 {
+                    self.camera.target_zoom = (self.camera.target_zoom
+                        * (scroll * ZOOM_SPEED).exp())
+                    .clamp(MIN_ZOOM, MAX_ZOOM);
+                    if let Some(hover_pos) = hover_pos {
+                        self.camera.zoom_anchor = normalized_screen_position(rect, hover_pos);
+                    }
+                }
+
+                // interpolate in log space so zooming in and out feel the same, and keep the
+                // world position under the zoom anchor fixed
+                let zoom = self.camera.target_zoom
+                    * (self.camera.zoom / self.camera.target_zoom)
+                        .powf((-ZOOM_SMOOTHING * dt).exp());
+                let anchor = self.camera.zoom_anchor;
+                self.camera.position += self
+                    .camera
+                    .screen_to_world_direction(cgmath::vec2(anchor.x * aspect, anchor.y))
+                    * (1.0 / self.camera.zoom - 1.0 / zoom);
+                self.camera.zoom = zoom;
+
+                if self.camera.recentering {
+                    let target = self.own_circles[&self.controlled].position;
+                    let offset = target - self.camera.position;
+                    if offset.magnitude() < 0.001 {
+                        self.camera.position = target;
+                        self.camera.recentering = false;
+                    } else {
+                        self.camera.position += offset * (1.0 - (-RECENTER_SMOOTHING * dt).exp());
+                    }
+                }
+
+                let now = Instant::now();
+                let render_time = now.checked_sub(self.interpolation_delay).unwrap_or(now);
+                for history in self.position_histories.values_mut() {
+                    history.discard_before(render_time);
+                }
+                let own_uuid = self.client.uuid();
+                self.render_positions = self
+                    .circles
+                    .iter()
+                    .map(|(&key, circle)| {
+                        let position = if key.0 == own_uuid {
+                            self.own_circles
+                                .get(&key.1)
+                                .map_or(circle.position, |circle| circle.position)
+                        } else {
+                            self.position_histories
+                                .get(&key)
+                                .and_then(|history| history.sample(render_time))
+                                .unwrap_or(circle.position)
+                        };
+                        (key, position)
+                    })
+                    .collect();
+                if let Some(target) = self.camera.following {
+                    match self.player_position(target) {
+                        Some(position) => self.camera.position = position,
+                        // the player left or started spectating
+                        None => self.camera.following = None,
+                    }
+                }
+                if self.camera.fitting {
+                    self.fit_to_circles(aspect);
+                }
+                let positions = &self.render_positions;
+
+                // moving each channel by at most this much means the color cant overshoot
+                let max_color_step = dt / COLOR_TRANSITION_TIME;
+                self.render_colors = self
+                    .circles
+                    .iter()
+                    .map(|(&key, circle)| {
+                        let color = match self.render_colors.get(&key) {
+                            Some(&current) if key.0 != own_uuid && self.display.animate_colors => {
+                                current.zip(circle.color, |current, target| {
+                                    current
+                                        + (target - current).clamp(-max_color_step, max_color_step)
+                                })
+                            }
+                            _ => circle.color,
+                        };
+                        (key, color)
+                    })
+                    .collect();
+                let colors = &self.render_colors;
+
+                let mut draw_order = self.circles.iter().collect::<Vec<_>>();
+                draw_order.sort_by_key(|&(&key, circle)| draw_order_key(key, circle, own_uuid));
+                let view = ViewRect::from_camera(
+                    self.camera.position,
+                    aspect,
+                    self.camera.zoom,
+                    self.camera.rotation,
+                );
+                let viewport = (
+                    (view.min + view.max) / 2.0,
+                    (view.max - view.min).magnitude() / 2.0,
+                );
+                if self.sent_viewport.0 != Some(viewport)
+                    && self.sent_viewport.1.elapsed() >= VIEWPORT_UPDATE_INTERVAL
+                {
+                    self.sent_viewport = (Some(viewport), Instant::now());
+                    _ = self
+                        .client
+                        .send_message(ClientToServerMessage::SetViewport(viewport.0, viewport.1));
+                }
+                let draw_order = cull(
+                    draw_order,
+                    view,
+                    |&(key, circle)| (positions[key], circle.shape.extent() + self.display.glow),
+                    MAX_RENDERED_CIRCLES,
+                );
+                self.stats.rendered_circles = draw_order.len();
+
+                let to_world = |screen_position: egui::Pos2| {
+                    self.camera
+                        .screen_to_world(normalized_screen_position(rect, screen_position), aspect)
+                };
+
+                let hovered = response
+                    .hover_pos()
+                    .filter(|&position| !over_minimap(position))
+                    .and_then(|position| self.circle_at(to_world(position)));
+
+                // worked out up front as the camera can change below
+                let press_origin = ctx
+                    .input(|input| input.pointer.press_origin())
+                    .filter(|&position| !over_minimap(position))
+                    .map(to_world);
+                let pointer_position = response.interact_pointer_pos().map(to_world);
+
+                if response.clicked_by(egui::PointerButton::Primary)
+                    && !response.interact_pointer_pos().is_some_and(over_minimap)
+                {
+                    self.selected = hovered;
+                    match hovered {
+                        Some((uuid, id))
+                            if uuid == own_uuid && self.own_circles.contains_key(&id) =>
+                        {
+                            self.controlled = id;
+                        }
+                        Some(_) => {}
+                        None if !self.client.is_spectator() && !self.paused => {
+                            let world_position = to_world(response.interact_pointer_pos().unwrap());
+                            self.camera.following = None;
+                            let circle = self.own_circles.get_mut(&self.controlled).unwrap();
+                            circle.position = self.client.world_bounds().clamp(world_position);
+                            _ = self.client.send_message(ClientToServerMessage::MovedTo(
+                                self.controlled,
+                                circle.position,
+                            ));
+                        }
+                        None => {}
+                    }
+                }
+                if response.drag_started_by(egui::PointerButton::Primary) {
+                    // dragging empty space or someone elses circle does nothing
+                    self.dragging = press_origin.and_then(|world_position| {
+                        let (uuid, id) = self.circle_at(world_position)?;
+                        let circle = self.own_circles.get(&id).filter(|_| uuid == own_uuid)?;
+                        Some((id, circle.position - world_position))
                     });
                 }
+                if !response.dragged_by(egui::PointerButton::Primary) {
+                    self.dragging = None;
+                }
+                if let Some((id, offset)) = self.dragging {
+                    let world_bounds = self.client.world_bounds();
+                    if let Some((world_position, circle)) = pointer_position
+                        .zip(self.own_circles.get_mut(&id))
+                        .filter(|_| !self.client.is_spectator() && !self.paused)
+                    {
+                        let position = world_bounds.clamp(world_position + offset);
+                        if position != circle.position {
+                            circle.position = position;
+                            self.controlled = id;
+                            self.camera.following = None;
+                            // sent like keyboard movement so dragging doesnt flood the server
+                            self.circle_moved = true;
+                        }
+                    }
+                }
+                self.selected = self.selected.filter(|key| self.circles.contains_key(key));
+
+                let labels = self
+                    .circles
+                    .iter()
+                    .filter_map(|(key, circle)| {
+                        let name = self.names.get(&key.0).filter(|name| !name.is_empty())?;
+                        Some(TextLabel {
+                            position: positions[key]
+                                + cgmath::vec2(0.0, circle.shape.extent() + NAME_GAP),
+                            height: NAME_HEIGHT,
+                            color: cgmath::vec4(1.0, 1.0, 1.0, 1.0),
+                            text: name.clone(),
+                        })
+                    })
+                    .collect();
+
+                // trails are drawn first so every circle stays on top of them
+                let trail_opacity = self.trails.opacity;
+                let trail_circles = draw_order
+                    .iter()
+                    .filter(|_| self.trails.enabled)
+                    .filter_map(|&(key, circle)| {
+                        Some((self.trails.positions.get(key)?, colors[key], circle))
+                    })
+                    .flat_map(|(trail, color, circle)| {
+                        let length = trail.len() as f32;
+                        trail.iter().enumerate().map(move |(index, &position)| {
+                            let fade = (index + 1) as f32 / length;
+                            GpuCircle {
+                                position,
+                                color: color.truncate().extend(color.w * trail_opacity * fade),
+                                radius: circle.shape.extent() * TRAIL_RADIUS_SCALE,
+                                shape: SHAPE_CIRCLE,
+                                outline_color: circle.outline_color,
+                                outline_thickness: 0.0,
+                                glow: 0.0,
+                                sprite: NO_SPRITE,
+                            }
+                        })
+                    })
+                    .collect::<Vec<_>>();
 
                 ui.painter().add(Callback::new_paint_callback(
                     rect,
@@ -156,26 +1698,148 @@ impl eframe::App for App {
                             position: self.camera.position,
                             aspect,
                             zoom: self.camera.zoom,
+                            rotation: self.camera.rotation,
+                            viewport_height: rect.height() * ctx.pixels_per_point(),
+                            min_circle_pixels: self.display.min_circle_pixels,
                         },
-                        circles: self
-                            .circles
-                            .values()
-                            .map(
-                                |&Circle {
-                                     position,
-                                     color,
-                                     radius,
-                                 }| GpuCircle {
-                                    position,
-                                    color,
-                                    radius,
+                        grid: GpuGrid {
+                            color: self.grid.color,
+                            spacing: self.grid.spacing,
+                            background: self.display.background,
+                        },
+                        circles: trail_circles
+                            .into_iter()
+                            .chain(draw_order.into_iter().map(
+                                |(
+                                    key,
+                                    &Circle {
+                                        shape,
+                                        outline_color,
+                                        outline_thickness,
+                                        ..
+                                    },
+                                )| {
+                                    let color = colors[key];
+                                    let color = if hovered == Some(*key) {
+                                        color
+                                            .truncate()
+                                            .lerp(Vector3::new(1.0, 1.0, 1.0), HOVER_TINT)
+                                            .extend(color.w)
+                                    } else {
+                                        color
+                                    };
+                                    let (outline_color, outline_thickness) =
+                                        if self.selected == Some(*key) {
+                                            (
+                                                SELECTED_OUTLINE_COLOR,
+                                                outline_thickness.max(SELECTED_OUTLINE_THICKNESS),
+                                            )
+                                        } else if self.tagged == Some(key.0) {
+                                            (
+                                                TAG_OUTLINE_COLOR,
+                                                outline_thickness.max(TAG_OUTLINE_THICKNESS),
+                                            )
+                                        } else {
+                                            (outline_color, outline_thickness)
+                                        };
+                                    GpuCircle {
+                                        position: positions[key],
+                                        color,
+                                        radius: shape.extent(),
+                                        shape: match shape {
+                                            Shape::Circle { .. } => SHAPE_CIRCLE,
+                                            Shape::Square { .. } => SHAPE_SQUARE,
+                                        },
+                                        outline_color,
+                                        outline_thickness,
+                                        glow: self.display.glow,
+                                        sprite: self
+                                            .sprites
+                                            .get(&key.0)
+                                            .copied()
+                                            .filter(|_| self.display.show_sprites)
+                                            .unwrap_or(NO_SPRITE),
+                                    }
                                 },
-                            )
+                            ))
                             .collect(),
+                        labels,
                     },
                 ));
+
+                let to_screen = |position: Vector2<f32>| {
+                    let position = self.camera.world_to_screen(position, aspect);
+                    rect.left_top()
+                        + (egui::vec2(position.x, -position.y) + egui::vec2(1.0, 1.0)) / 2.0
+                            * rect.size()
+                };
+
+                let world_bounds = self.client.world_bounds();
+                let corners = [
+                    world_bounds.min,
+                    cgmath::vec2(world_bounds.max.x, world_bounds.min.y),
+                    world_bounds.max,
+                    cgmath::vec2(world_bounds.min.x, world_bounds.max.y),
+                ];
+                ui.painter().add(egui::Shape::closed_line(
+                    corners.into_iter().map(to_screen).collect(),
+                    egui::Stroke::new(2.0, egui::Color32::RED),
+                ));
+
+                if let Some(key @ (uuid, id)) = self.selected {
+                    let circle = &self.circles[&key];
+                    let position = positions[&key];
+                    let name = self.names.get(&uuid).filter(|name| !name.is_empty());
+                    egui::Area::new("Selected")
+                        .fixed_pos(to_screen(
+                            position + cgmath::vec2(circle.shape.extent(), 0.0),
+                        ))
+                        .order(egui::Order::Foreground)
+                        .interactable(false)
+                        .show(ctx, |ui| {
+                            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                                ui.label(format!("Name: {}", name.map_or("-", String::as_str)));
+                                ui.label(format!("UUID: {uuid}"));
+                                ui.label(format!("Circle: {id}"));
+                                ui.label(format!(
+                                    "Position: ({:.2}, {:.2})",
+                                    position.x, position.y
+                                ));
+                            });
+                        });
+                }
+
+                if let Some(minimap_rect) = minimap_rect {
+                    self.minimap(ui, minimap_rect, aspect);
+                }
+
+                let heartbeat_color = match self.client.health() {
+                    ConnectionHealth::Healthy => egui::Color32::GREEN,
+                    ConnectionHealth::Overdue => egui::Color32::YELLOW,
+                    ConnectionHealth::Reconnecting(_) | ConnectionHealth::Disconnected => {
+                        egui::Color32::RED
+                    }
+                };
+                ui.painter().circle_filled(
+                    rect.left_bottom() + egui::vec2(HEARTBEAT_MARGIN, -HEARTBEAT_MARGIN),
+                    HEARTBEAT_RADIUS,
+                    heartbeat_color,
+                );
             });
 
-        ctx.request_repaint();
+        if focused {
+            ctx.request_repaint();
+        } else {
+            ctx.request_repaint_after(BACKGROUND_POLL_INTERVAL);
+        }
+    }
+
+    fn on_exit(&mut self) {
+        if let Some(path) = &self.tuning_path {
+            if let Err(error) = self.save_tuning(path) {
+                error!(path = %path.display(), %error, "failed to save the network tuning");
+            }
+        }
+        self.runtime.block_on(self.client.disconnect());
     }
 }
@@ -0,0 +1,13 @@
+use tracing_subscriber::EnvFilter;
+
+/// Used when `RUST_LOG` is not set, this keeps the noise from the graphics crates out
+const DEFAULT_FILTER: &str = "info,wgpu_core=warn,wgpu_hal=warn,naga=warn";
+
+/// Logs to the console, filtered by the `RUST_LOG` environment variable
+pub fn init() {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER)),
+        )
+        .init();
+}
@@ -0,0 +1,72 @@
+use crate::client::ServerToClientMessage;
+use std::{
+    fs::File,
+    io::{BufRead as _, BufReader, BufWriter, Read as _, Write as _},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// How often buffered entries are written out, a crash loses at most this much of the recording
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Writes received messages to a file as timestamped CBOR values, each followed by a newline
+pub struct Recorder {
+    start: Instant,
+    last_flush: Instant,
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Ok(Self {
+            start: Instant::now(),
+            last_flush: Instant::now(),
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn record(&mut self, message: &ServerToClientMessage) -> anyhow::Result<()> {
+        ciborium::into_writer(&(self.start.elapsed(), message), &mut self.writer)?;
+        self.writer.write_all(b"\n")?;
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.writer.flush()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Writes out whatever is still buffered, dropping the recorder does this too but loses the
+    /// error
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads back a log written by a [`Recorder`], stopping at the first malformed entry
+pub fn replay_log(
+    path: impl AsRef<Path>,
+) -> std::io::Result<impl Iterator<Item = (Duration, ServerToClientMessage)>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(std::iter::from_fn(move || {
+        if reader.fill_buf().ok()?.is_empty() {
+            return None;
+        }
+        let entry = match ciborium::from_reader(&mut reader) {
+            Ok(entry) => entry,
+            Err(error) => {
+                tracing::warn!(%error, "stopping replay at a malformed log entry");
+                return None;
+            }
+        };
+        // the newline is only a separator, the entries are found by decoding them as cbor can
+        // contain newline bytes itself
+        let mut separator = [0];
+        match reader.read_exact(&mut separator) {
+            Ok(()) if separator == *b"\n" => Some(entry),
+            _ => {
+                tracing::warn!("stopping replay at a log entry that isnt followed by a newline");
+                None
+            }
+        }
+    }))
+}
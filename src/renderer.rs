@@ -1,22 +1,65 @@
-use cgmath::{Vector2, Vector3};
+use crate::client::SPRITE_COUNT;
+use ab_glyph::{Font as _, ScaleFont as _};
+use anyhow::Context as _;
+use cgmath::{ElementWise as _, Vector2, Vector3, Vector4};
 use eframe::{
+    egui,
     egui_wgpu::{self, CallbackTrait},
     wgpu,
 };
-use encase::{ShaderSize, ShaderType, StorageBuffer, UniformBuffer};
+use encase::{internal::WriteInto, ShaderSize, ShaderType, StorageBuffer, UniformBuffer};
+
+const GLYPH_FONT: &str = "Ubuntu-Light";
+const GLYPH_PIXEL_SIZE: f32 = 48.0;
+const GLYPH_PADDING: u32 = 2;
+const ATLAS_COLUMNS: u32 = 16;
+/// Only printable ascii is in the atlas, anything else is drawn as a question mark
+const FIRST_GLYPH: char = ' ';
+const LAST_GLYPH: char = '~';
+const FALLBACK_GLYPH: char = '?';
 
 #[derive(ShaderType)]
 pub struct GpuCamera {
     pub position: Vector2<f32>,
     pub aspect: f32,
     pub zoom: f32,
+    /// Counter-clockwise rotation of the view in radians
+    pub rotation: f32,
+    /// In physical pixels
+    pub viewport_height: f32,
+    /// Circles that would be smaller than this many pixels across are drawn this big instead
+    pub min_circle_pixels: f32,
 }
 
+pub const SHAPE_CIRCLE: u32 = 0;
+pub const SHAPE_SQUARE: u32 = 1;
+/// For [`GpuCircle::sprite`], draws the circle in its flat color
+pub const NO_SPRITE: u32 = u32::MAX;
+pub const NO_ADAPTER_ERROR: &str =
+    "no compatible GPU adapter found, a different one can be picked with `--backend` or `WGPU_BACKEND`";
+
+/// A row of square sprites, [`SPRITE_COUNT`] wide
+const SPRITE_ATLAS: &[u8] = include_bytes!("./sprites.png");
+
 #[derive(ShaderType)]
 pub struct GpuCircle {
     pub position: Vector2<f32>,
-    pub color: Vector3<f32>,
+    pub color: Vector4<f32>,
     pub radius: f32,
+    pub shape: u32,
+    pub outline_color: Vector3<f32>,
+    pub outline_thickness: f32,
+    /// How far the glow reaches past the edge in world units, zero turns it off
+    pub glow: f32,
+    /// Index into the sprite atlas drawn over the color, or [`NO_SPRITE`]
+    pub sprite: u32,
+}
+
+#[derive(ShaderType)]
+pub struct GpuGrid {
+    pub color: Vector3<f32>,
+    pub spacing: f32,
+    pub background: Vector3<f32>,
 }
 
 #[derive(ShaderType)]
@@ -25,23 +68,320 @@ struct GpuCircles<'a> {
     circles: &'a [GpuCircle],
 }
 
+/// Text that is drawn in world space, so it pans and zooms along with the circles but stays upright
+pub struct TextLabel {
+    /// The bottom center of the text
+    pub position: Vector2<f32>,
+    /// The height of a line in world units
+    pub height: f32,
+    pub color: Vector4<f32>,
+    pub text: String,
+}
+
+#[derive(ShaderType)]
+struct GpuGlyph {
+    anchor: Vector2<f32>,
+    /// Relative to `anchor` in unrotated view space
+    offset: Vector2<f32>,
+    size: Vector2<f32>,
+    uv_min: Vector2<f32>,
+    uv_max: Vector2<f32>,
+    color: Vector4<f32>,
+}
+
+#[derive(ShaderType)]
+struct GpuGlyphs<'a> {
+    #[size(runtime)]
+    glyphs: &'a [GpuGlyph],
+}
+
+/// Sizes are in line heights, with y going up from the baseline
+#[derive(Clone, Copy)]
+struct GlyphMetrics {
+    offset: Vector2<f32>,
+    size: Vector2<f32>,
+    uv_min: Vector2<f32>,
+    uv_max: Vector2<f32>,
+    advance: f32,
+}
+
+struct GlyphAtlas {
+    glyphs: Vec<GlyphMetrics>,
+    /// How far the bottom of a line is below the baseline, in line heights
+    descent: f32,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl GlyphAtlas {
+    fn rasterize() -> Self {
+        let fonts = egui::FontDefinitions::default();
+        let data = &fonts.font_data[GLYPH_FONT];
+        let font = ab_glyph::FontRef::try_from_slice_and_index(&data.font, data.index)
+            .expect("egui's default font should be valid");
+        let scale = ab_glyph::PxScale::from(GLYPH_PIXEL_SIZE);
+        let scaled_font = font.as_scaled(scale);
+        let line_height = scaled_font.ascent() - scaled_font.descent();
+
+        let glyph_count = LAST_GLYPH as u32 - FIRST_GLYPH as u32 + 1;
+        let cell_size = line_height.ceil() as u32 + GLYPH_PADDING * 2;
+        let width = ATLAS_COLUMNS * cell_size;
+        let height = glyph_count.div_ceil(ATLAS_COLUMNS) * cell_size;
+        let mut pixels = vec![0; (width * height) as usize];
+
+        let glyphs = (FIRST_GLYPH..=LAST_GLYPH)
+            .enumerate()
+            .map(|(index, c)| {
+                let index = index as u32;
+                let glyph_id = font.glyph_id(c);
+                let advance = scaled_font.h_advance(glyph_id) / line_height;
+                let Some(outline) = font.outline_glyph(glyph_id.with_scale(scale)) else {
+                    return GlyphMetrics {
+                        offset: cgmath::vec2(0.0, 0.0),
+                        size: cgmath::vec2(0.0, 0.0),
+                        uv_min: cgmath::vec2(0.0, 0.0),
+                        uv_max: cgmath::vec2(0.0, 0.0),
+                        advance,
+                    };
+                };
+
+                let cell_x = index % ATLAS_COLUMNS * cell_size + GLYPH_PADDING;
+                let cell_y = index / ATLAS_COLUMNS * cell_size + GLYPH_PADDING;
+                let inner_size = cell_size - GLYPH_PADDING * 2;
+                outline.draw(|x, y, coverage| {
+                    if x < inner_size && y < inner_size {
+                        pixels[((cell_y + y) * width + cell_x + x) as usize] =
+                            (coverage * 255.0) as u8;
+                    }
+                });
+
+                let bounds = outline.px_bounds();
+                let size = cgmath::vec2(
+                    bounds.width().min(inner_size as f32),
+                    bounds.height().min(inner_size as f32),
+                );
+                let atlas_size = cgmath::vec2(width as f32, height as f32);
+                let uv_min = cgmath::vec2(cell_x as f32, cell_y as f32);
+                GlyphMetrics {
+                    offset: cgmath::vec2(bounds.min.x, -bounds.max.y) / line_height,
+                    size: size / line_height,
+                    uv_min: uv_min.div_element_wise(atlas_size),
+                    uv_max: (uv_min + size).div_element_wise(atlas_size),
+                    advance,
+                }
+            })
+            .collect();
+
+        Self {
+            glyphs,
+            descent: -scaled_font.descent() / line_height,
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    fn metrics(&self, c: char) -> GlyphMetrics {
+        let c = if (FIRST_GLYPH..=LAST_GLYPH).contains(&c) {
+            c
+        } else {
+            FALLBACK_GLYPH
+        };
+        self.glyphs[c as usize - FIRST_GLYPH as usize]
+    }
+
+    fn layout(&self, label: &TextLabel, glyphs: &mut Vec<GpuGlyph>) {
+        let width = label
+            .text
+            .chars()
+            .map(|c| self.metrics(c).advance)
+            .sum::<f32>()
+            * label.height;
+        let mut pen = cgmath::vec2(-width / 2.0, self.descent * label.height);
+        for c in label.text.chars() {
+            let metrics = self.metrics(c);
+            if metrics.size.x > 0.0 && metrics.size.y > 0.0 {
+                glyphs.push(GpuGlyph {
+                    anchor: label.position,
+                    offset: pen + metrics.offset * label.height,
+                    size: metrics.size * label.height,
+                    uv_min: metrics.uv_min,
+                    uv_max: metrics.uv_max,
+                    color: label.color,
+                });
+            }
+            pen.x += metrics.advance * label.height;
+        }
+    }
+}
+
 struct RenderState {
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
+    grid_buffer: wgpu::Buffer,
+    grid_bind_group: wgpu::BindGroup,
+    grid_render_pipeline: wgpu::RenderPipeline,
     circle_render_pipeline: wgpu::RenderPipeline,
-    circle_buffer_size: wgpu::BufferAddress,
-    circle_buffer: wgpu::Buffer,
-    circle_bind_group_layout: wgpu::BindGroupLayout,
-    circle_bind_group: wgpu::BindGroup,
+    circle_buffer: InstanceBuffer,
+    sprite_bind_group: wgpu::BindGroup,
+    glyph_atlas: GlyphAtlas,
+    atlas_bind_group: wgpu::BindGroup,
+    text_render_pipeline: wgpu::RenderPipeline,
+    glyph_buffer: InstanceBuffer,
+    /// Reused every frame so laying out the labels doesn't allocate
+    glyphs: Vec<GpuGlyph>,
+    glyph_count: u32,
+}
+
+/// A storage buffer that is rewritten every frame and only reallocated when it runs out of space
+struct InstanceBuffer {
+    label: &'static str,
+    size: wgpu::BufferAddress,
+    buffer: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    /// Reused every frame so encoding the contents doesn't allocate
+    staging: Vec<u8>,
 }
 
-pub fn create_render_state(cc: &eframe::CreationContext) {
+impl InstanceBuffer {
+    fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        bind_group_layout: wgpu::BindGroupLayout,
+        size: wgpu::BufferAddress,
+    ) -> Self {
+        let (buffer, bind_group) = Self::allocate(device, label, &bind_group_layout, size);
+        Self {
+            label,
+            size,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            staging: Vec::new(),
+        }
+    }
+
+    fn allocate(
+        device: &wgpu::Device,
+        label: &str,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        size: wgpu::BufferAddress,
+    ) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{label} Buffer")),
+            size,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("{label} Bind Group")),
+            layout: bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        (buffer, bind_group)
+    }
+
+    fn write<T: ShaderType + WriteInto>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        value: &T,
+    ) {
+        let mut staging = std::mem::take(&mut self.staging);
+        staging.clear();
+        let mut buffer = StorageBuffer::new(staging);
+        buffer.write(value).unwrap();
+        self.staging = buffer.into_inner();
+
+        let needed = self.staging.len() as wgpu::BufferAddress;
+        if needed > self.size {
+            // grow geometrically so a slowly rising count doesn't reallocate every frame
+            self.size = needed.max(self.size * 2);
+            (self.buffer, self.bind_group) =
+                Self::allocate(device, self.label, &self.bind_group_layout, self.size);
+        }
+
+        queue.write_buffer(&self.buffer, 0, &self.staging);
+    }
+}
+
+/// Uploads `pixels` into a new texture, bound together with a linear sampler
+fn create_texture_bind_group(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    layout: &wgpu::BindGroupLayout,
+    label: &str,
+    format: wgpu::TextureFormat,
+    (width, height): (u32, u32),
+    pixels: &[u8],
+) -> wgpu::BindGroup {
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        pixels,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(pixels.len() as u32 / height),
+            rows_per_image: Some(height),
+        },
+        size,
+    );
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some(&format!("{label} Sampler")),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(&format!("{label} Bind Group")),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(
+                    &texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            },
+        ],
+    })
+}
+
+pub fn create_render_state(cc: &eframe::CreationContext) -> anyhow::Result<()> {
     let egui_wgpu::RenderState {
         ref device,
+        ref queue,
         target_format,
         ref renderer,
         ..
-    } = *cc.wgpu_render_state.as_ref().unwrap();
+    } = *cc.wgpu_render_state.as_ref().context(NO_ADAPTER_ERROR)?;
 
     let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
         label: Some("Camera Buffer"),
@@ -74,14 +414,76 @@ pub fn create_render_state(cc: &eframe::CreationContext) {
         }],
     });
 
-    let circle_buffer_size = GpuCircles::min_size().get();
-    let circle_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-        label: Some("Circle Buffer"),
-        size: circle_buffer_size,
-        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
+    let grid_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Grid Buffer"),
+        size: GpuGrid::SHADER_SIZE.get(),
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
         mapped_at_creation: false,
     });
 
+    let grid_bind_group_layout =
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Grid Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(GpuGrid::min_size()),
+                },
+                count: None,
+            }],
+        });
+
+    let grid_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Grid Bind Group"),
+        layout: &grid_bind_group_layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: grid_buffer.as_entire_binding(),
+        }],
+    });
+
+    let grid_shader = device.create_shader_module(wgpu::include_wgsl!("./grid_shader.wgsl"));
+
+    let grid_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Grid Pipeline Layout"),
+        bind_group_layouts: &[&camera_bind_group_layout, &grid_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    let grid_render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Grid Render Pipeline"),
+        layout: Some(&grid_pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &grid_shader,
+            entry_point: "vertex",
+            buffers: &[],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+            module: &grid_shader,
+            entry_point: "pixel",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: target_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    });
+
     let circle_bind_group_layout =
         device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             label: Some("Circle Bind Group Layout"),
@@ -97,20 +499,63 @@ pub fn create_render_state(cc: &eframe::CreationContext) {
             }],
         });
 
-    let circle_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("Circle Bind Group"),
-        layout: &circle_bind_group_layout,
-        entries: &[wgpu::BindGroupEntry {
-            binding: 0,
-            resource: circle_buffer.as_entire_binding(),
-        }],
-    });
+    let circle_buffer = InstanceBuffer::new(
+        device,
+        "Circle",
+        circle_bind_group_layout,
+        GpuCircles::min_size().get(),
+    );
+
+    let texture_bind_group_layout =
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Texture Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+    let sprite_atlas = image::load_from_memory_with_format(SPRITE_ATLAS, image::ImageFormat::Png)
+        .expect("the sprite atlas should be a valid png")
+        .into_rgba8();
+    assert_eq!(
+        sprite_atlas.width(),
+        sprite_atlas.height() * SPRITE_COUNT,
+        "the sprite atlas should be a row of square sprites"
+    );
+    let sprite_bind_group = create_texture_bind_group(
+        device,
+        queue,
+        &texture_bind_group_layout,
+        "Sprite Atlas",
+        wgpu::TextureFormat::Rgba8Unorm,
+        sprite_atlas.dimensions(),
+        &sprite_atlas,
+    );
 
     let circle_shader = device.create_shader_module(wgpu::include_wgsl!("./circle_shader.wgsl"));
 
     let circle_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
         label: Some("Circle Pipeline Layout"),
-        bind_group_layouts: &[&camera_bind_group_layout, &circle_bind_group_layout],
+        bind_group_layouts: &[
+            &camera_bind_group_layout,
+            &circle_buffer.bind_group_layout,
+            &texture_bind_group_layout,
+        ],
         push_constant_ranges: &[],
     });
 
@@ -136,7 +581,87 @@ pub fn create_render_state(cc: &eframe::CreationContext) {
         fragment: Some(wgpu::FragmentState {
             module: &circle_shader,
             entry_point: "pixel",
-            targets: &[Some(target_format.into())],
+            targets: &[Some(wgpu::ColorTargetState {
+                format: target_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+    });
+
+    let glyph_atlas = GlyphAtlas::rasterize();
+    let atlas_bind_group = create_texture_bind_group(
+        device,
+        queue,
+        &texture_bind_group_layout,
+        "Glyph Atlas",
+        wgpu::TextureFormat::R8Unorm,
+        (glyph_atlas.width, glyph_atlas.height),
+        &glyph_atlas.pixels,
+    );
+
+    let glyph_bind_group_layout =
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Glyph Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(GpuGlyphs::min_size()),
+                },
+                count: None,
+            }],
+        });
+
+    let glyph_buffer = InstanceBuffer::new(
+        device,
+        "Glyph",
+        glyph_bind_group_layout,
+        GpuGlyphs::min_size().get(),
+    );
+
+    let text_shader = device.create_shader_module(wgpu::include_wgsl!("./text_shader.wgsl"));
+
+    let text_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Text Pipeline Layout"),
+        bind_group_layouts: &[
+            &camera_bind_group_layout,
+            &glyph_buffer.bind_group_layout,
+            &texture_bind_group_layout,
+        ],
+        push_constant_ranges: &[],
+    });
+
+    let text_render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Text Render Pipeline"),
+        layout: Some(&text_pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &text_shader,
+            entry_point: "vertex",
+            buffers: &[],
+        },
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Cw,
+            cull_mode: None,
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        fragment: Some(wgpu::FragmentState {
+            module: &text_shader,
+            entry_point: "pixel",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: target_format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
         }),
         multiview: None,
     });
@@ -144,17 +669,27 @@ pub fn create_render_state(cc: &eframe::CreationContext) {
     renderer.write().callback_resources.insert(RenderState {
         camera_buffer,
         camera_bind_group,
+        grid_buffer,
+        grid_bind_group,
+        grid_render_pipeline,
         circle_render_pipeline,
-        circle_buffer_size,
         circle_buffer,
-        circle_bind_group_layout,
-        circle_bind_group,
+        sprite_bind_group,
+        glyph_atlas,
+        atlas_bind_group,
+        text_render_pipeline,
+        glyph_buffer,
+        glyphs: Vec::new(),
+        glyph_count: 0,
     });
+    Ok(())
 }
 
 pub struct RenderCallback {
     pub camera: GpuCamera,
+    pub grid: GpuGrid,
     pub circles: Vec<GpuCircle>,
+    pub labels: Vec<TextLabel>,
 }
 
 impl CallbackTrait for RenderCallback {
@@ -174,37 +709,33 @@ impl CallbackTrait for RenderCallback {
         }
 
         {
-            let mut circle_buffer =
-                StorageBuffer::new(Vec::with_capacity(render_state.circle_buffer_size as _));
-            circle_buffer
-                .write(&GpuCircles {
-                    circles: &self.circles,
-                })
-                .unwrap();
-            let circle_buffer = circle_buffer.into_inner();
-
-            if circle_buffer.len() as wgpu::BufferAddress > render_state.circle_buffer_size {
-                render_state.circle_buffer_size = circle_buffer.len() as _;
-
-                render_state.circle_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-                    label: Some("Circle Buffer"),
-                    size: render_state.circle_buffer_size,
-                    usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::STORAGE,
-                    mapped_at_creation: false,
-                });
+            let mut grid_buffer = UniformBuffer::new([0u8; GpuGrid::SHADER_SIZE.get() as _]);
+            grid_buffer.write(&self.grid).unwrap();
+            queue.write_buffer(&render_state.grid_buffer, 0, &grid_buffer.into_inner());
+        }
 
-                render_state.circle_bind_group =
-                    device.create_bind_group(&wgpu::BindGroupDescriptor {
-                        label: Some("Circle Bind Group"),
-                        layout: &render_state.circle_bind_group_layout,
-                        entries: &[wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: render_state.circle_buffer.as_entire_binding(),
-                        }],
-                    });
-            }
+        render_state.circle_buffer.write(
+            device,
+            queue,
+            &GpuCircles {
+                circles: &self.circles,
+            },
+        );
 
-            queue.write_buffer(&render_state.circle_buffer, 0, &circle_buffer);
+        {
+            let RenderState {
+                glyph_atlas,
+                glyph_buffer,
+                glyphs,
+                glyph_count,
+                ..
+            } = render_state;
+            glyphs.clear();
+            for label in &self.labels {
+                glyph_atlas.layout(label, glyphs);
+            }
+            *glyph_count = glyphs.len() as _;
+            glyph_buffer.write(device, queue, &GpuGlyphs { glyphs });
         }
 
         Vec::new()
@@ -228,9 +759,21 @@ impl CallbackTrait for RenderCallback {
     ) {
         let render_state: &RenderState = callback_resources.get().unwrap();
 
+        render_pass.set_pipeline(&render_state.grid_render_pipeline);
+        render_pass.set_bind_group(0, &render_state.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &render_state.grid_bind_group, &[]);
+        render_pass.draw(0..4, 0..1);
+
         render_pass.set_pipeline(&render_state.circle_render_pipeline);
         render_pass.set_bind_group(0, &render_state.camera_bind_group, &[]);
-        render_pass.set_bind_group(1, &render_state.circle_bind_group, &[]);
+        render_pass.set_bind_group(1, &render_state.circle_buffer.bind_group, &[]);
+        render_pass.set_bind_group(2, &render_state.sprite_bind_group, &[]);
         render_pass.draw(0..4, 0..self.circles.len() as _);
+
+        render_pass.set_pipeline(&render_state.text_render_pipeline);
+        render_pass.set_bind_group(0, &render_state.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &render_state.glyph_buffer.bind_group, &[]);
+        render_pass.set_bind_group(2, &render_state.atlas_bind_group, &[]);
+        render_pass.draw(0..4, 0..render_state.glyph_count);
     }
 }
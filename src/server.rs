@@ -0,0 +1,1975 @@
+#[cfg(feature = "tls")]
+use crate::tls::rustls;
+#[cfg(feature = "test-transport")]
+use crate::transport::MemoryConnector;
+#[cfg(feature = "websocket")]
+use crate::transport::WsTransport;
+use crate::{
+    client::{
+        Circle, CircleDelta, CircleKey, ClientToServerMessage, ConnectionState, DisconnectReason,
+        ServerToClientMessage, WorldBounds, MAX_CHAT_MESSAGE_LENGTH, MAX_NAME_LENGTH,
+        MAX_OUTLINE_THICKNESS, MAX_RADIUS, MIN_RADIUS, PROTOCOL_VERSION, SPAWN_CIRCLE_ID,
+        SPRITE_COUNT,
+    },
+    codec::CodecKind,
+    transport::{decode_datagram, encode_datagram, ServerTransport, TcpTransport, Transport as _},
+};
+use anyhow::bail;
+use cgmath::{InnerSpace as _, Vector2, Vector4};
+use std::{
+    cell::Cell,
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    future::Future,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    net::{TcpListener, TcpStream, UdpSocket},
+    select,
+    sync::{
+        mpsc::{self, error::TrySendError, unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot, watch,
+    },
+    task::{AbortHandle, JoinHandle, JoinSet},
+    time::MissedTickBehavior,
+};
+use tracing::{error, info, info_span, warn, Instrument as _};
+use uuid::Uuid;
+
+/// Circles can overlap by this much before [`separate_circles`] pushes them apart
+pub const COLLISION_TOLERANCE: f32 = 0.001;
+const MAX_BATCH_SIZE: usize = 256;
+const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+const GREETING_TIMEOUT: Duration = Duration::from_secs(5);
+/// How close circles have to be to count as touching in [`GameMode::Tag`], collisions leave them
+/// just touching rather than overlapping
+const TAG_REACH: f32 = 0.01;
+/// How long the player who was just it cant be tagged back
+const TAG_IMMUNITY: Duration = Duration::from_secs(2);
+const TAG_SCORE_INTERVAL: Duration = Duration::from_secs(1);
+/// Public addresses that are only used to find the interface outgoing traffic would leave
+/// through, nothing is actually sent to them
+const ROUTE_PROBE_V4: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)), 80);
+const ROUTE_PROBE_V6: SocketAddr = SocketAddr::new(
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888)),
+    80,
+);
+/// Stands in for the peer address of in-memory connections in the logs
+const MEMORY_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// How often every client is pinged, this also controls how often timeouts are checked
+    pub ping_interval: Duration,
+    /// Clients that have not answered a ping for this long are disconnected
+    pub ping_timeout: Duration,
+    pub max_players: Option<usize>,
+    /// Further [`ClientToServerMessage::AddCircle`]s are ignored once a player has this many
+    pub max_circles_per_player: usize,
+    /// How many messages can be queued for a client before it is disconnected for falling behind
+    pub client_queue_capacity: usize,
+    /// Disables Nagle's algorithm on accepted connections so small messages are sent immediately
+    pub tcp_nodelay: bool,
+    pub world_bounds: WorldBounds,
+    /// New players start somewhere within this distance of the origin, see [`spawn_position`]
+    pub spawn_radius: f32,
+    /// The circles are loaded from this file on startup, and saved to it periodically and on shutdown
+    pub save_path: Option<PathBuf>,
+    pub save_interval: Duration,
+    /// Circle changes are collected and sent as one [`ServerToClientMessage::Snapshot`] this many
+    /// times per second, instead of being broadcast as soon as they arrive
+    pub tick_rate: Option<u32>,
+    /// Shown to clients that use [`crate::client::query_server`]
+    pub name: String,
+    /// Clients have to send this token with [`ClientToServerMessage::Join`] before they are let in
+    pub auth_token: Option<String>,
+    /// Limits how many messages each remote client can send, the host is never limited
+    pub rate_limit: Option<RateLimit>,
+    /// Remote clients that send nothing but pings for this long are disconnected, checked every
+    /// `ping_interval`
+    pub idle_timeout: Option<Duration>,
+    /// Remote clients that lose their connection can come back with their
+    /// [`ClientToServerMessage::Join`] reconnect token within this long and keep their uuid and
+    /// circle, everyone else only hears about them leaving once it runs out. Off by default, as
+    /// clients that time out would otherwise stay around for the whole grace period as well
+    pub reconnect_grace: Option<Duration>,
+    pub game_mode: GameMode,
+    /// Sends only the fields of a circle that changed since each client last heard about it, see
+    /// [`ServerToClientMessage::PlayerDelta`]
+    pub delta_encoding: bool,
+    /// How often everyone is sent every circle in full when using `delta_encoding`, so a client
+    /// that missed a delta doesnt stay out of sync
+    pub keyframe_interval: Duration,
+    /// Every remote client's bandwidth since the last report is logged and sent as a
+    /// [`ServerEvent::Bandwidth`] this often
+    pub bandwidth_report_interval: Option<Duration>,
+    /// Clients that sent a [`ClientToServerMessage::SetViewport`] only hear about circles within
+    /// this far of what they can see, everything else is left out until it comes into view
+    pub interest_radius: Option<f32>,
+    /// Remote clients have to ask for this codec when joining, see [`crate::client::ClientConfig::codec`]
+    pub codec: CodecKind,
+    /// Browsers can connect over websockets when this is set, see [`WebSocketListener`]
+    #[cfg(feature = "websocket")]
+    pub websocket: Option<WebSocketListener>,
+    /// Accepted connections are encrypted when this is set, see [`crate::tls::load_server_config`]
+    #[cfg(feature = "tls")]
+    pub tls: Option<Arc<rustls::ServerConfig>>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval: Duration::from_secs(1),
+            ping_timeout: Duration::from_secs(5),
+            max_players: None,
+            max_circles_per_player: 16,
+            client_queue_capacity: 1024,
+            tcp_nodelay: true,
+            world_bounds: WorldBounds::default(),
+            spawn_radius: 5.0,
+            save_path: None,
+            save_interval: Duration::from_secs(30),
+            tick_rate: None,
+            name: "Multiplayer".to_string(),
+            auth_token: None,
+            rate_limit: Some(RateLimit::default()),
+            idle_timeout: None,
+            reconnect_grace: None,
+            game_mode: GameMode::default(),
+            delta_encoding: false,
+            keyframe_interval: Duration::from_secs(5),
+            bandwidth_report_interval: None,
+            interest_radius: None,
+            codec: CodecKind::default(),
+            #[cfg(feature = "websocket")]
+            websocket: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+/// Where to accept websocket connections, each message is one binary websocket message holding
+/// the same cbor the tcp connections use, without the frame header
+#[cfg(feature = "websocket")]
+#[derive(Debug, Clone)]
+pub struct WebSocketListener {
+    pub addr: SocketAddr,
+    /// Upgrade requests for any other path are turned away
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GameMode {
+    #[default]
+    FreeRoam,
+    /// One player is it and passes it on by touching someone else, everyone is scored on how long
+    /// they were it, see [`ServerToClientMessage::TagChanged`] and [`ServerToClientMessage::TagScores`]
+    Tag,
+}
+
+/// A token bucket, messages that arrive while it is empty are dropped
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub messages_per_second: f32,
+    /// How many messages can arrive at once after a client has been quiet for a while
+    pub burst: u32,
+    /// Clients are disconnected once this many of their messages were dropped without the bucket
+    /// getting the chance to fill back up
+    pub max_dropped: u32,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            messages_per_second: 60.0,
+            burst: 120,
+            max_dropped: 600,
+        }
+    }
+}
+
+struct TokenBucket {
+    tokens: f32,
+    last_refill: Instant,
+    dropped: u32,
+}
+
+impl TokenBucket {
+    fn new(limit: Option<RateLimit>) -> Self {
+        Self {
+            tokens: limit.map_or(0.0, |limit| limit.burst as f32),
+            last_refill: Instant::now(),
+            dropped: 0,
+        }
+    }
+
+    /// Returns false when the message should be dropped
+    fn take(&mut self, limit: RateLimit) -> bool {
+        let now = Instant::now();
+        let refilled = self.tokens
+            + now.duration_since(self.last_refill).as_secs_f32() * limit.messages_per_second;
+        self.last_refill = now;
+        self.tokens = refilled.min(limit.burst as f32);
+        if refilled >= limit.burst as f32 {
+            self.dropped = 0;
+        }
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            self.dropped += 1;
+            false
+        }
+    }
+}
+
+struct UdpPeer {
+    addr: SocketAddr,
+    receive_sequence: u64,
+    send_sequence: u64,
+}
+
+/// A remote client that lost its connection, it is kept around until `deadline` in case it
+/// reconnects
+struct DepartedClient {
+    uuid: Uuid,
+    name: String,
+    sprite: Option<u32>,
+    spectator: bool,
+    reason: DisconnectReason,
+    deadline: Instant,
+}
+
+struct ConnectedClient {
+    messages: mpsc::Sender<ServerToClientMessage>,
+    overflowed: Cell<bool>,
+    name: String,
+    sprite: Option<u32>,
+    last_pong: Instant,
+    /// When the last message other than a ping arrived
+    last_active: Instant,
+    connection_state: Option<watch::Sender<ConnectionState>>,
+    task: Option<AbortHandle>,
+    udp: Option<UdpPeer>,
+    /// A secret from the handshake that every datagram from this client has to carry, the uuid
+    /// alone isnt enough as everyone else knows it
+    udp_token: Uuid,
+    /// Where the connection came from, datagrams from any other ip are dropped, unknown for
+    /// connections that dont go over the network
+    peer_ip: Option<IpAddr>,
+    spectator: bool,
+    rate_limiter: TokenBucket,
+    reconnect_token: Uuid,
+    /// The circles as this client last heard about them, only kept with
+    /// [`ServerConfig::delta_encoding`]
+    sent_circles: Option<HashMap<CircleKey, Circle>>,
+    bandwidth: Arc<BandwidthCounter>,
+    /// Only set once the client sent a [`ClientToServerMessage::SetViewport`], see
+    /// [`ServerConfig::interest_radius`]
+    interest: Option<Interest>,
+}
+
+/// Where a client is looking and which circles it knows about because of that
+struct Interest {
+    owner: Uuid,
+    center: Vector2<f32>,
+    /// The viewport extent plus [`ServerConfig::interest_radius`]
+    radius: f32,
+    visible: HashSet<CircleKey>,
+}
+
+impl Interest {
+    fn contains(&self, (owner, _): CircleKey, circle: Circle) -> bool {
+        // the client always has its own circles
+        owner == self.owner
+            || (circle.position - self.center).magnitude() <= self.radius + circle.shape.extent()
+    }
+}
+
+impl ConnectedClient {
+    fn is_local(&self) -> bool {
+        self.connection_state.is_some()
+    }
+
+    fn send(&self, message: ServerToClientMessage) {
+        if let Err(TrySendError::Full(_)) = self.messages.try_send(message) {
+            self.overflowed.set(true);
+        }
+    }
+
+    /// Keeps track of what the client has, so later changes can be sent as deltas
+    fn record_sent(&mut self, key: CircleKey, circle: Circle) {
+        if let Some(sent) = &mut self.sent_circles {
+            sent.insert(key, circle);
+        }
+    }
+
+    /// The client threw these circles away, so they have to be sent in full again
+    fn forget_sent(&mut self, removed: impl Fn(CircleKey) -> bool) {
+        if let Some(sent) = &mut self.sent_circles {
+            sent.retain(|&key, _| !removed(key));
+        }
+        if let Some(interest) = &mut self.interest {
+            interest.visible.retain(|&key| !removed(key));
+        }
+    }
+
+    /// `None` if the client isnt interested in the circle, telling it when the circle left its
+    /// view, otherwise whether the circle just came into view
+    fn update_interest(&mut self, key: CircleKey, circle: Circle) -> Option<bool> {
+        let Some(interest) = &mut self.interest else {
+            return Some(false);
+        };
+        if interest.contains(key, circle) {
+            return Some(interest.visible.insert(key));
+        }
+        if interest.visible.contains(&key) {
+            self.forget_sent(|other| other == key);
+            self.send(ServerToClientMessage::LeftView(key));
+        }
+        None
+    }
+
+    /// Sends the circles the client is interested in as one snapshot
+    fn send_snapshot(&mut self, snapshot: &[(CircleKey, Circle)]) {
+        let mut visible = Vec::with_capacity(snapshot.len());
+        for &(key, circle) in snapshot {
+            if self.update_interest(key, circle).is_some() {
+                visible.push((key, circle));
+                self.record_sent(key, circle);
+            }
+        }
+        if !visible.is_empty() {
+            self.send(ServerToClientMessage::Snapshot(visible));
+        }
+    }
+
+    fn send_player_changed(
+        &mut self,
+        key: CircleKey,
+        circle: Circle,
+        only_moved: bool,
+        socket: &UdpSocket,
+    ) {
+        let Some(came_into_view) = self.update_interest(key, circle) else {
+            return;
+        };
+        // whatever the client had from before it lost sight of the circle is out of date
+        let only_moved = only_moved && !came_into_view;
+        let message = match &mut self.sent_circles {
+            Some(sent) => match sent.insert(key, circle) {
+                Some(old) => {
+                    ServerToClientMessage::PlayerDelta(key, CircleDelta::between(old, circle))
+                }
+                None => ServerToClientMessage::PlayerChanged(key, circle),
+            },
+            None if only_moved => ServerToClientMessage::MovedTo(key, circle.position),
+            None => ServerToClientMessage::PlayerChanged(key, circle),
+        };
+        // deltas only make sense on top of what the client really has, so none of them can get lost,
+        // and a circle coming into view wouldnt show up again until it changes
+        let reliable = self.sent_circles.is_some() || came_into_view;
+        let Some(udp) = self.udp.as_mut().filter(|_| !reliable) else {
+            self.send(message);
+            return;
+        };
+        udp.send_sequence += 1;
+        match encode_datagram(udp.send_sequence, message) {
+            // position updates are unreliable anyway, so a full socket buffer just drops this one
+            Ok(bytes) => {
+                if socket.try_send_to(&bytes, udp.addr).is_ok() {
+                    self.bandwidth.add(bytes.len() as u64, 0);
+                }
+            }
+            Err(error) => error!(uuid = %key.0, %error, "failed to encode a datagram"),
+        }
+    }
+}
+
+struct TagGame {
+    it: Option<Uuid>,
+    /// The player who was it before, and when they can be tagged again
+    immune: Option<(Uuid, Instant)>,
+    /// How long each player has been it, up to `scored_until`
+    scores: HashMap<Uuid, Duration>,
+    scored_until: Instant,
+}
+
+impl TagGame {
+    fn new() -> Self {
+        Self {
+            it: None,
+            immune: None,
+            scores: HashMap::new(),
+            scored_until: Instant::now(),
+        }
+    }
+
+    /// Counts the time since the last call towards whoever is it
+    fn score(&mut self) {
+        let now = Instant::now();
+        if let Some(it) = self.it {
+            *self.scores.entry(it).or_default() += now - self.scored_until;
+        }
+        self.scored_until = now;
+    }
+
+    fn set_it(&mut self, it: Option<Uuid>, clients: &HashMap<Uuid, ConnectedClient>) {
+        self.score();
+        self.it = it;
+        if let Some(it) = it {
+            for client in clients.values() {
+                client.send(ServerToClientMessage::TagChanged(it));
+            }
+        }
+    }
+
+    /// Passes it on when one of its circles touches another player's, or picks someone new when
+    /// it left or started spectating
+    fn update(
+        &mut self,
+        clients: &HashMap<Uuid, ConnectedClient>,
+        circles: &HashMap<CircleKey, Circle>,
+    ) {
+        let playing = |uuid| clients.get(&uuid).is_some_and(|client| !client.spectator);
+        let Some(it) = self.it.filter(|&it| playing(it)) else {
+            // whoever has been it the least takes over
+            let next = clients
+                .iter()
+                .filter(|(_, client)| !client.spectator)
+                .map(|(&uuid, _)| uuid)
+                .min_by_key(|uuid| self.scores.get(uuid).copied().unwrap_or_default());
+            if next != self.it {
+                self.set_it(next, clients);
+            }
+            return;
+        };
+
+        let now = Instant::now();
+        let immune = self
+            .immune
+            .filter(|&(_, until)| until > now)
+            .map(|(uuid, _)| uuid);
+        let tagged = circles
+            .iter()
+            .filter(|(&(owner, _), _)| owner == it)
+            .find_map(|(_, circle)| {
+                circles
+                    .iter()
+                    .find(|(&(owner, _), other)| {
+                        owner != it
+                            && Some(owner) != immune
+                            && playing(owner)
+                            && (other.position - circle.position).magnitude()
+                                <= circle.shape.extent() + other.shape.extent() + TAG_REACH
+                    })
+                    .map(|(&(owner, _), _)| owner)
+            });
+        if let Some(tagged) = tagged {
+            info!(%it, %tagged, "tagged");
+            self.immune = Some((it, now + TAG_IMMUNITY));
+            self.set_it(Some(tagged), clients);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerEvent {
+    ClientJoined(Uuid),
+    ClientLeft(Uuid, DisconnectReason),
+    /// The client lost its connection and came back in time, see [`ServerConfig::reconnect_grace`]
+    ClientReconnected(Uuid),
+    /// The busiest clients come first, see [`ServerConfig::bandwidth_report_interval`]
+    Bandwidth(Vec<(Uuid, BandwidthUsage)>),
+    Error(String),
+}
+
+/// Bytes sent to and received from one client over both TCP and UDP, including framing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BandwidthUsage {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl BandwidthUsage {
+    pub fn total(&self) -> u64 {
+        self.sent.saturating_add(self.received)
+    }
+}
+
+/// Shared between a client's task and the server loop, so counting never has to go through a
+/// channel
+#[derive(Default)]
+struct BandwidthCounter {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl BandwidthCounter {
+    fn add(&self, sent: u64, received: u64) {
+        // these wrap around instead of overflowing, reports take the counts long before that
+        self.sent.fetch_add(sent, Ordering::Relaxed);
+        self.received.fetch_add(received, Ordering::Relaxed);
+    }
+
+    fn take(&self) -> BandwidthUsage {
+        BandwidthUsage {
+            sent: self.sent.swap(0, Ordering::Relaxed),
+            received: self.received.swap(0, Ordering::Relaxed),
+        }
+    }
+}
+
+type Events = UnboundedSender<ServerEvent>;
+
+type ClientMessage = (ClientToServerMessage, Uuid);
+
+type LocalClient = (
+    Uuid,
+    mpsc::Sender<ServerToClientMessage>,
+    watch::Sender<ConnectionState>,
+    oneshot::Sender<()>,
+);
+
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    websocket_addr: Option<SocketAddr>,
+    client_queue_capacity: usize,
+    to_server_messages: UnboundedSender<ClientMessage>,
+    local_clients: UnboundedSender<LocalClient>,
+    #[cfg(feature = "test-transport")]
+    memory_connections: UnboundedSender<TcpTransport>,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    pub(crate) async fn connect_local(
+        &self,
+    ) -> (
+        Uuid,
+        UnboundedSender<ClientMessage>,
+        mpsc::Receiver<ServerToClientMessage>,
+        watch::Receiver<ConnectionState>,
+    ) {
+        let (to_client_messages, from_server_messages) = mpsc::channel(self.client_queue_capacity);
+        let (connection_state, connection_state_receiver) =
+            watch::channel(ConnectionState::Connected);
+        let (registered, wait_for_registration) = oneshot::channel();
+        let uuid = Uuid::new_v4();
+        if self
+            .local_clients
+            .send((uuid, to_client_messages, connection_state, registered))
+            .is_ok()
+        {
+            _ = wait_for_registration.await;
+        }
+        (
+            uuid,
+            self.to_server_messages.clone(),
+            from_server_messages,
+            connection_state_receiver,
+        )
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Where websocket connections are accepted, if the server was configured to
+    pub fn websocket_addr(&self) -> Option<SocketAddr> {
+        self.websocket_addr
+    }
+
+    #[cfg(feature = "test-transport")]
+    pub fn memory_connector(&self) -> MemoryConnector {
+        MemoryConnector::new(self.memory_connections.clone())
+    }
+
+    /// The addresses other players should connect to, when the server is bound to every
+    /// interface this is the LAN address, or the loopback address if there is no network
+    pub fn share_addresses(&self) -> Vec<SocketAddr> {
+        let ip = self.local_addr.ip();
+        if !ip.is_unspecified() {
+            return vec![self.local_addr];
+        }
+
+        let (probe, loopback) = match ip {
+            IpAddr::V4(_) => (ROUTE_PROBE_V4, IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            IpAddr::V6(_) => (ROUTE_PROBE_V6, IpAddr::V6(Ipv6Addr::LOCALHOST)),
+        };
+        let outgoing_ip = std::net::UdpSocket::bind(SocketAddr::new(ip, 0))
+            .and_then(|socket| {
+                socket.connect(probe)?;
+                socket.local_addr()
+            })
+            .map(|addr| addr.ip())
+            .ok()
+            .filter(|ip| !ip.is_loopback() && !ip.is_unspecified());
+        vec![SocketAddr::new(
+            outgoing_ip.unwrap_or(loopback),
+            self.local_addr.port(),
+        )]
+    }
+
+    pub async fn shutdown(self) {
+        _ = self.shutdown.send(());
+        _ = self.task.await;
+    }
+}
+
+/// The events are sent in the same order as the changes they describe, they can be ignored by
+/// dropping the receiver
+pub async fn run_server(
+    addr: SocketAddr,
+    config: ServerConfig,
+) -> anyhow::Result<(ServerHandle, UnboundedReceiver<ServerEvent>)> {
+    if config.ping_interval.is_zero() {
+        bail!("the ping interval must not be zero");
+    }
+    if config.max_circles_per_player == 0 {
+        bail!("players must be allowed at least one circle");
+    }
+    if config.client_queue_capacity == 0 {
+        bail!("the client queue capacity must not be zero");
+    }
+    if config.save_interval.is_zero() {
+        bail!("the save interval must not be zero");
+    }
+    if config.keyframe_interval.is_zero() {
+        bail!("the keyframe interval must not be zero");
+    }
+    if config
+        .bandwidth_report_interval
+        .is_some_and(|interval| interval.is_zero())
+    {
+        bail!("the bandwidth report interval must not be zero");
+    }
+    if config.tick_rate == Some(0) {
+        bail!("the tick rate must not be zero");
+    }
+    if config.reconnect_grace.is_some_and(|grace| grace.is_zero()) {
+        bail!("the reconnect grace period must not be zero");
+    }
+    if config.idle_timeout.is_some_and(|timeout| timeout.is_zero()) {
+        bail!("the idle timeout must not be zero");
+    }
+    if let Some(RateLimit {
+        messages_per_second,
+        burst,
+        ..
+    }) = config.rate_limit
+    {
+        if messages_per_second <= 0.0 || burst == 0 {
+            bail!("the rate limit must let at least some messages through");
+        }
+    }
+    if config
+        .interest_radius
+        .is_some_and(|radius| !radius.is_finite() || radius < 0.0)
+    {
+        bail!("the interest radius must be a positive distance");
+    }
+    if !config.spawn_radius.is_finite() || config.spawn_radius < 0.0 {
+        bail!(
+            "the spawn radius {} is not a valid distance",
+            config.spawn_radius
+        );
+    }
+    if !config.codec.is_available() {
+        bail!(
+            "the {} codec needs the `{}` feature",
+            config.codec,
+            config.codec
+        );
+    }
+    #[cfg(feature = "websocket")]
+    if let Some(websocket) = &config.websocket {
+        if !websocket.path.starts_with('/') {
+            bail!(
+                "the websocket path `{}` has to start with a `/`",
+                websocket.path
+            );
+        }
+    }
+    let WorldBounds { min, max } = config.world_bounds;
+    if min.x > max.x || min.y > max.y {
+        bail!("the world bounds minimum {min:?} is larger than the maximum {max:?}");
+    }
+
+    let circles = match &config.save_path {
+        Some(path) => load_circles(path).await?,
+        None => HashMap::new(),
+    };
+
+    let listener = TcpListener::bind(addr).await?;
+    let local_addr = listener.local_addr()?;
+    let udp_socket = UdpSocket::bind(local_addr).await?;
+    #[cfg(feature = "websocket")]
+    let websocket_listener = match &config.websocket {
+        Some(websocket) => Some(TcpListener::bind(websocket.addr).await?),
+        None => None,
+    };
+    #[cfg(not(feature = "websocket"))]
+    let websocket_listener = None;
+    let websocket_addr = websocket_listener
+        .as_ref()
+        .map(TcpListener::local_addr)
+        .transpose()?;
+
+    let (to_server_messages, from_clients_messages) = unbounded_channel();
+    let (local_clients, new_local_clients) = unbounded_channel();
+    // without the feature nothing can connect this way, so the sender is dropped straight away
+    #[cfg_attr(not(feature = "test-transport"), allow(unused_variables))]
+    let (memory_connections, new_memory_connections) = unbounded_channel();
+    let (shutdown, shutdown_signal) = oneshot::channel();
+    let (events, event_receiver) = unbounded_channel();
+
+    let client_queue_capacity = config.client_queue_capacity;
+    let task = tokio::spawn(
+        server_loop(
+            (
+                listener,
+                websocket_listener,
+                udp_socket,
+                new_memory_connections,
+            ),
+            config,
+            circles,
+            (to_server_messages.clone(), from_clients_messages),
+            new_local_clients,
+            events,
+            shutdown_signal,
+        )
+        .instrument(info_span!("server", %local_addr)),
+    );
+
+    Ok((
+        ServerHandle {
+            local_addr,
+            websocket_addr,
+            client_queue_capacity,
+            to_server_messages,
+            local_clients,
+            #[cfg(feature = "test-transport")]
+            memory_connections,
+            shutdown,
+            task,
+        },
+        event_receiver,
+    ))
+}
+
+async fn server_loop(
+    (listener, websocket_listener, udp_socket, mut new_memory_connections): (
+        TcpListener,
+        Option<TcpListener>,
+        UdpSocket,
+        UnboundedReceiver<TcpTransport>,
+    ),
+    config: ServerConfig,
+    mut circles: HashMap<CircleKey, Circle>,
+    (to_server_messages, mut from_clients_messages): (
+        UnboundedSender<ClientMessage>,
+        UnboundedReceiver<ClientMessage>,
+    ),
+    mut new_local_clients: UnboundedReceiver<LocalClient>,
+    events: Events,
+    mut shutdown_signal: oneshot::Receiver<()>,
+) {
+    let mut clients: HashMap<Uuid, ConnectedClient> = HashMap::new();
+    // keyed by the reconnect token
+    let mut departed: HashMap<Uuid, DepartedClient> = HashMap::new();
+    let mut client_tasks = JoinSet::new();
+    let (authenticated, mut authenticated_clients) = unbounded_channel();
+    let (info_queries, mut info_requests) = unbounded_channel::<(ServerTransport, SocketAddr)>();
+    let (client_errors, mut failed_clients) = unbounded_channel();
+    let mut udp_buffer = vec![0; u16::MAX.into()];
+
+    let mut last_ping = (0, Instant::now());
+    let mut ping_interval = tokio::time::interval(config.ping_interval);
+    ping_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut tag = (config.game_mode == GameMode::Tag).then(TagGame::new);
+    // circles cant move while the host has the world paused
+    let mut paused = false;
+    let mut tag_score_interval = tokio::time::interval(TAG_SCORE_INTERVAL);
+    tag_score_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut keyframe_interval = tokio::time::interval(config.keyframe_interval);
+    keyframe_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    keyframe_interval.reset();
+    let mut bandwidth_reports = config.bandwidth_report_interval.map(|interval| {
+        let mut bandwidth_interval = tokio::time::interval(interval);
+        bandwidth_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        bandwidth_interval.reset();
+        bandwidth_interval
+    });
+    let mut save_interval = tokio::time::interval(config.save_interval);
+    save_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    save_interval.reset();
+    // the uuids of circles that changed since the last snapshot
+    let mut snapshots = config.tick_rate.map(|tick_rate| {
+        let mut tick_interval = tokio::time::interval(Duration::from_secs(1) / tick_rate);
+        tick_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        (tick_interval, HashSet::new())
+    });
+    loop {
+        disconnect_overflowed_clients(&mut clients, &mut circles, &events);
+        if let Some(tag) = &mut tag {
+            tag.update(&clients, &circles);
+        }
+
+        select! {
+            Some((message, uuid)) = from_clients_messages.recv() => {
+                let changed_circles = snapshots.as_mut().map(|(_, changed)| changed);
+                handle_message(message, uuid, &mut clients, &mut circles, (&events, &mut paused), (&udp_socket, &config, last_ping, changed_circles));
+            }
+
+            Some((uuid, error)) = failed_clients.recv() => {
+                error!(%uuid, %error, "connection failed");
+                _ = events.send(ServerEvent::Error(format!("{uuid}: {error}")));
+                lose_client(uuid, DisconnectReason::Error, &mut clients, &mut circles, &events, (&mut departed, &config));
+            }
+
+            Ok((length, addr)) = udp_socket.recv_from(&mut udp_buffer) => {
+                let (sequence, (uuid, token, message)): (u64, (Uuid, Uuid, _)) = match decode_datagram(&udp_buffer[..length]) {
+                    Ok(datagram) => datagram,
+                    Err(error) => {
+                        warn!(%addr, %error, "dropped a malformed datagram");
+                        continue;
+                    }
+                };
+                let (ClientToServerMessage::PlayerChanged(..) | ClientToServerMessage::MovedTo(..)) = message else {
+                    continue;
+                };
+                let Some(client) = clients.get_mut(&uuid) else {
+                    continue;
+                };
+                if token != client.udp_token || client.peer_ip.is_some_and(|ip| ip != addr.ip()) {
+                    warn!(%uuid, %addr, "dropped a datagram that didnt come from the client");
+                    continue;
+                }
+                client.bandwidth.add(0, length as u64);
+                match &mut client.udp {
+                    Some(udp) if udp.addr == addr => {
+                        if sequence <= udp.receive_sequence {
+                            continue;
+                        }
+                        udp.receive_sequence = sequence;
+                    }
+                    udp => {
+                        *udp = Some(UdpPeer {
+                            addr,
+                            receive_sequence: sequence,
+                            send_sequence: 0,
+                        });
+                    }
+                }
+                let changed_circles = snapshots.as_mut().map(|(_, changed)| changed);
+                handle_message(message, uuid, &mut clients, &mut circles, (&events, &mut paused), (&udp_socket, &config, last_ping, changed_circles));
+            }
+
+            Some((uuid, to_client_messages, connection_state, registered)) = new_local_clients.recv() => {
+                add_client(uuid, to_client_messages, Some(connection_state), &mut clients, &mut circles, &config, (&departed, None));
+                if let Some(it) = tag.as_ref().and_then(|tag| tag.it) {
+                    clients[&uuid].send(ServerToClientMessage::TagChanged(it));
+                }
+                if paused {
+                    clients[&uuid].send(ServerToClientMessage::Paused(true));
+                }
+                _ = events.send(ServerEvent::ClientJoined(uuid));
+                _ = registered.send(());
+            }
+
+            Ok((stream, addr)) = listener.accept() => {
+                if let Err(error) = stream.set_nodelay(config.tcp_nodelay) {
+                    warn!(%addr, %error, "failed to set nodelay");
+                }
+                let accept = accept_transport(stream, &config);
+                spawn_greeting(accept, addr, &mut client_tasks, (&config, &authenticated, &info_queries, &events));
+            }
+
+            Ok((stream, addr)) = async { websocket_listener.as_ref().unwrap().accept().await }, if websocket_listener.is_some() => {
+                if let Err(error) = stream.set_nodelay(config.tcp_nodelay) {
+                    warn!(%addr, %error, "failed to set nodelay");
+                }
+                let accept = accept_websocket(stream, &config);
+                spawn_greeting(accept, addr, &mut client_tasks, (&config, &authenticated, &info_queries, &events));
+            }
+
+            Some(transport) = new_memory_connections.recv() => {
+                let accept = async { Ok(transport.into()) };
+                spawn_greeting(accept, MEMORY_ADDR, &mut client_tasks, (&config, &authenticated, &info_queries, &events));
+            }
+
+            Some((mut transport, addr)) = info_requests.recv() => {
+                let info = ServerToClientMessage::Info {
+                    player_count: clients.len(),
+                    max_players: config.max_players,
+                    name: config.name.clone(),
+                };
+                let query = async move {
+                    if let Err(error) = transport.send(info).await {
+                        warn!(%error, "failed to send the server info");
+                    }
+                    _ = transport.shutdown().await;
+                };
+                client_tasks.spawn(query.instrument(info_span!("connection", %addr)));
+            }
+
+            Some((mut transport, addr, reconnect_token)) = authenticated_clients.recv() => {
+                if config.max_players.is_some_and(|max_players| clients.len() >= max_players) {
+                    info!(%addr, "rejected, the server is full");
+                    let rejection = async move {
+                        let message = ServerToClientMessage::Rejected("the server is full".into());
+                        if let Err(error) = transport.send(message).await {
+                            warn!(%error, "failed to send the rejection");
+                        }
+                        _ = transport.shutdown().await;
+                    };
+                    client_tasks.spawn(rejection.instrument(info_span!("connection", %addr)));
+                    continue;
+                }
+
+                let (to_client_messages, from_server_messages) =
+                    mpsc::channel(config.client_queue_capacity);
+                // an expired token has to be treated like any other new client
+                expire_departed_clients(&mut departed, &mut clients, &mut circles, &events);
+                let resumed = reconnect_token.and_then(|token| departed.remove(&token));
+                let uuid = resumed.as_ref().map_or_else(Uuid::new_v4, |departed| departed.uuid);
+                if resumed.is_some() {
+                    info!(%uuid, %addr, "reconnected");
+                    _ = events.send(ServerEvent::ClientReconnected(uuid));
+                } else {
+                    info!(%uuid, %addr, "connected");
+                    _ = events.send(ServerEvent::ClientJoined(uuid));
+                }
+                add_client(uuid, to_client_messages, None, &mut clients, &mut circles, &config, (&departed, resumed));
+                if let Some(client) = clients.get_mut(&uuid) {
+                    client.peer_ip = Some(addr.ip()).filter(|_| addr != MEMORY_ADDR);
+                }
+                if let Some(it) = tag.as_ref().and_then(|tag| tag.it) {
+                    clients[&uuid].send(ServerToClientMessage::TagChanged(it));
+                }
+                if paused {
+                    clients[&uuid].send(ServerToClientMessage::Paused(true));
+                }
+                let task = client_tasks.spawn({
+                    let to_server_messages = to_server_messages.clone();
+                    let client_errors = client_errors.clone();
+                    let bandwidth = clients[&uuid].bandwidth.clone();
+                    let codec = config.codec;
+                    async move {
+                        if let Err(error) = handle_client(transport, uuid, (to_server_messages, from_server_messages), &bandwidth, codec).await {
+                            _ = client_errors.send((uuid, error));
+                        }
+                    }
+                    .instrument(info_span!("client", %uuid))
+                });
+                if let Some(client) = clients.get_mut(&uuid) {
+                    client.task = Some(task);
+                }
+            }
+
+            Some(_) = client_tasks.join_next() => {}
+
+            _ = ping_interval.tick() => {
+                let timed_out = clients
+                    .iter()
+                    .filter(|(_, client)| {
+                        !client.is_local() && client.last_pong.elapsed() > config.ping_timeout
+                    })
+                    .map(|(&uuid, _)| uuid)
+                    .collect::<Vec<_>>();
+                for uuid in timed_out {
+                    warn!(%uuid, "timed out");
+                    lose_client(uuid, DisconnectReason::Timeout, &mut clients, &mut circles, &events, (&mut departed, &config));
+                }
+                expire_departed_clients(&mut departed, &mut clients, &mut circles, &events);
+                if let Some(idle_timeout) = config.idle_timeout {
+                    let idle = clients
+                        .iter()
+                        .filter(|(_, client)| {
+                            !client.is_local() && client.last_active.elapsed() > idle_timeout
+                        })
+                        .map(|(&uuid, _)| uuid)
+                        .collect::<Vec<_>>();
+                    for uuid in idle {
+                        info!(%uuid, "kicked for being idle");
+                        kick_client(uuid, DisconnectReason::Idle, &mut clients, &mut circles, &events);
+                    }
+                }
+
+                last_ping = (last_ping.0.wrapping_add(1), Instant::now());
+                for client in clients.values() {
+                    client.send(ServerToClientMessage::Ping(last_ping.0));
+                }
+            }
+
+            // the expression is evaluated even when the branch is disabled, so the unwrap has to be deferred
+            _ = async { snapshots.as_mut().unwrap().0.tick().await }, if snapshots.is_some() => {
+                let (_, changed) = snapshots.as_mut().unwrap();
+                let snapshot = changed
+                    .drain()
+                    .filter_map(|key| Some((key, *circles.get(&key)?)))
+                    .collect::<Vec<_>>();
+                if !snapshot.is_empty() {
+                    for client in clients.values_mut() {
+                        client.send_snapshot(&snapshot);
+                    }
+                }
+            }
+
+            _ = tag_score_interval.tick(), if tag.is_some() => {
+                let tag = tag.as_mut().unwrap();
+                tag.score();
+                let scores = clients
+                    .keys()
+                    .map(|uuid| (*uuid, tag.scores.get(uuid).copied().unwrap_or_default()))
+                    .collect::<Vec<_>>();
+                for client in clients.values() {
+                    client.send(ServerToClientMessage::TagScores(scores.clone()));
+                }
+            }
+
+            _ = keyframe_interval.tick(), if config.delta_encoding => {
+                let keyframe = circles.iter().map(|(&key, &circle)| (key, circle)).collect::<Vec<_>>();
+                if !keyframe.is_empty() {
+                    for client in clients.values_mut() {
+                        if let Some(sent) = &mut client.sent_circles {
+                            sent.clear();
+                        }
+                        client.send_snapshot(&keyframe);
+                    }
+                }
+            }
+
+            _ = async { bandwidth_reports.as_mut().unwrap().tick().await }, if bandwidth_reports.is_some() => {
+                let mut usage = clients
+                    .iter()
+                    .filter(|(_, client)| !client.is_local())
+                    .map(|(&uuid, client)| (uuid, client.bandwidth.take()))
+                    .collect::<Vec<_>>();
+                usage.sort_by_key(|(_, usage)| Reverse(usage.total()));
+                for (uuid, usage) in &usage {
+                    info!(%uuid, sent = usage.sent, received = usage.received, "bandwidth");
+                }
+                _ = events.send(ServerEvent::Bandwidth(usage));
+            }
+
+            _ = save_interval.tick(), if config.save_path.is_some() => {
+                let path = config.save_path.as_deref().unwrap();
+                if let Err(error) = save_circles(path, &circles).await {
+                    error!(path = %path.display(), %error, "failed to save");
+                    _ = events.send(ServerEvent::Error(format!("failed to save to {}: {error}", path.display())));
+                }
+            }
+
+            _ = &mut shutdown_signal => {
+                for client in clients.values() {
+                    client.send(ServerToClientMessage::ServerShutdown);
+                    if let Some(connection_state) = &client.connection_state {
+                        connection_state.send_replace(ConnectionState::ServerShutdown);
+                    }
+                }
+                break;
+            }
+        }
+    }
+
+    drop(listener);
+    drop(clients);
+    // a client that stopped reading would otherwise keep the server from ever shutting down
+    let flush = async { while client_tasks.join_next().await.is_some() {} };
+    if tokio::time::timeout(SHUTDOWN_FLUSH_TIMEOUT, flush)
+        .await
+        .is_err()
+    {
+        client_tasks.shutdown().await;
+    }
+
+    if let Some(path) = &config.save_path {
+        if let Err(error) = save_circles(path, &circles).await {
+            error!(path = %path.display(), %error, "failed to save");
+            _ = events.send(ServerEvent::Error(format!(
+                "failed to save to {}: {error}",
+                path.display()
+            )));
+        }
+    }
+}
+
+pub async fn load_circles(path: &Path) -> anyhow::Result<HashMap<CircleKey, Circle>> {
+    match tokio::fs::read(path).await {
+        Ok(bytes) => Ok(ciborium::from_reader(bytes.as_slice())?),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+pub async fn save_circles(path: &Path, circles: &HashMap<CircleKey, Circle>) -> anyhow::Result<()> {
+    let mut bytes = vec![];
+    ciborium::into_writer(circles, &mut bytes)?;
+    // write to a temporary file first so a crash while saving cant corrupt the previous save
+    let temporary_path = path.with_extension("tmp");
+    tokio::fs::write(&temporary_path, bytes).await?;
+    tokio::fs::rename(temporary_path, path).await?;
+    Ok(())
+}
+
+fn accept_transport(
+    stream: TcpStream,
+    config: &ServerConfig,
+) -> impl Future<Output = io::Result<ServerTransport>> {
+    #[cfg(feature = "tls")]
+    let acceptor = config.tls.clone().map(tokio_rustls::TlsAcceptor::from);
+    #[cfg(not(feature = "tls"))]
+    let _ = config;
+    // the tls handshake happens in the client's task so a slow client cant stall the server
+    async move {
+        #[cfg(feature = "tls")]
+        if let Some(acceptor) = acceptor {
+            return TcpTransport::accept_tls(stream, &acceptor)
+                .await
+                .map(Into::into);
+        }
+        Ok(TcpTransport::new(stream).into())
+    }
+}
+
+#[cfg(feature = "websocket")]
+fn accept_websocket(
+    stream: TcpStream,
+    config: &ServerConfig,
+) -> impl Future<Output = io::Result<ServerTransport>> {
+    let path = config
+        .websocket
+        .as_ref()
+        .map_or_else(|| "/".to_string(), |websocket| websocket.path.clone());
+    // the upgrade also happens in the client's task
+    async move { WsTransport::accept(stream, &path).await.map(Into::into) }
+}
+
+#[cfg(not(feature = "websocket"))]
+fn accept_websocket(
+    _: TcpStream,
+    _: &ServerConfig,
+) -> impl Future<Output = io::Result<ServerTransport>> {
+    // the listener is never bound without the feature
+    async { Err(io::Error::from(io::ErrorKind::Unsupported)) }
+}
+
+type Authenticated = (ServerTransport, SocketAddr, Option<Uuid>);
+
+/// Accepts and greets the connection in its own task, then hands it back to the server loop
+fn spawn_greeting(
+    accept: impl Future<Output = io::Result<ServerTransport>> + Send + 'static,
+    addr: SocketAddr,
+    client_tasks: &mut JoinSet<()>,
+    (config, authenticated, info_queries, events): (
+        &ServerConfig,
+        &UnboundedSender<Authenticated>,
+        &UnboundedSender<(ServerTransport, SocketAddr)>,
+        &Events,
+    ),
+) {
+    let auth_token = config.auth_token.clone();
+    let codec = config.codec;
+    let authenticated = authenticated.clone();
+    let info_queries = info_queries.clone();
+    let events = events.clone();
+    let connection = async move {
+        let result = match accept.await {
+            Ok(transport) => greet(transport, (auth_token.as_deref(), codec)).await,
+            Err(error) => Err(error.into()),
+        };
+        match result {
+            Ok(Greeting::Join(transport, reconnect_token)) => {
+                _ = authenticated.send((transport, addr, reconnect_token))
+            }
+            Ok(Greeting::QueryInfo(transport)) => _ = info_queries.send((transport, addr)),
+            Err(error) => {
+                warn!(%error, "failed to accept the connection");
+                _ = events.send(ServerEvent::Error(format!("{addr}: {error}")));
+            }
+        }
+    };
+    client_tasks.spawn(connection.instrument(info_span!("connection", %addr)));
+}
+
+enum Greeting {
+    /// With the reconnect token, if the client has one
+    Join(ServerTransport, Option<Uuid>),
+    QueryInfo(ServerTransport),
+}
+
+/// Waits for the client to say whether it wants to join or only wants the server info, joining
+/// needs the right token when the server requires one
+async fn greet(
+    mut transport: ServerTransport,
+    (auth_token, codec): (Option<&str>, CodecKind),
+) -> anyhow::Result<Greeting> {
+    let message = match tokio::time::timeout(GREETING_TIMEOUT, transport.receive()).await {
+        Ok(Ok(message)) => message,
+        Ok(Err(error)) => return Err(error),
+        Err(_) => {
+            _ = transport.shutdown().await;
+            bail!("timed out waiting for the client to join");
+        }
+    };
+    match message {
+        ClientToServerMessage::QueryInfo => Ok(Greeting::QueryInfo(transport)),
+        ClientToServerMessage::Join {
+            codec: requested, ..
+        } if requested != codec => {
+            let reason = format!("the server uses {codec} but the client asked for {requested}");
+            _ = transport
+                .send(ServerToClientMessage::Rejected(reason))
+                .await;
+            _ = transport.shutdown().await;
+            bail!("rejected, the client asked for {requested}");
+        }
+        ClientToServerMessage::Join {
+            auth_token: token,
+            reconnect_token,
+            ..
+        } if auth_token.is_none() || token.as_deref() == auth_token => {
+            Ok(Greeting::Join(transport, reconnect_token))
+        }
+        ClientToServerMessage::Join { .. } => {
+            _ = transport.send(ServerToClientMessage::Unauthorized).await;
+            _ = transport.shutdown().await;
+            bail!("rejected, failed to authenticate");
+        }
+        _ => {
+            _ = transport.shutdown().await;
+            bail!("the first message wasnt a join or an info query");
+        }
+    }
+}
+
+async fn handle_client(
+    mut transport: ServerTransport,
+    uuid: Uuid,
+    (to_server_messages, mut from_server_messages): (
+        UnboundedSender<ClientMessage>,
+        mpsc::Receiver<ServerToClientMessage>,
+    ),
+    bandwidth: &BandwidthCounter,
+    codec: CodecKind,
+) -> anyhow::Result<()> {
+    // the handshake is never batched so the client can read it on its own, and like the join it is
+    // always cbor, everything after it uses the codec the client asked for
+    if let Some(handshake) = from_server_messages.recv().await {
+        transport.send(handshake).await?;
+    }
+    transport.set_codec(codec);
+
+    loop {
+        let (sent, received) = transport.take_byte_counts();
+        bandwidth.add(sent, received);
+
+        select! {
+            message = from_server_messages.recv() => {
+                let Some(message) = message else {
+                    break;
+                };
+
+                // everything queued up so far is sent together to save on writes, batches are
+                // flattened because the client only unpacks one level
+                let mut messages = match message {
+                    ServerToClientMessage::Batch(batch) => batch,
+                    message => vec![message],
+                };
+                while messages.len() < MAX_BATCH_SIZE {
+                    match from_server_messages.try_recv() {
+                        Ok(ServerToClientMessage::Batch(batch)) => messages.extend(batch),
+                        Ok(message) => messages.push(message),
+                        Err(_) => break,
+                    }
+                }
+                let message = if messages.len() == 1 {
+                    messages.pop().unwrap()
+                } else {
+                    ServerToClientMessage::Batch(messages)
+                };
+                transport.send(message).await?;
+            }
+
+            result = transport.receive() => {
+                let message = result?;
+                let disconnect = matches!(message, ClientToServerMessage::Disconnect);
+                let Ok(()) = to_server_messages.send((message, uuid)) else {
+                    break;
+                };
+                // the client is about to close the connection, errors from that arent worth reporting
+                if disconnect {
+                    _ = transport.shutdown().await;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    transport.shutdown().await?;
+    Ok(())
+}
+
+/// The new client is sent its handshake, then everything that already exists as one batch, and
+/// only then starts hearing about changes, so every uuid it hears about has been announced with a
+/// [`ServerToClientMessage::ClientConnected`] first
+fn add_client(
+    uuid: Uuid,
+    to_client_messages: mpsc::Sender<ServerToClientMessage>,
+    connection_state: Option<watch::Sender<ConnectionState>>,
+    clients: &mut HashMap<Uuid, ConnectedClient>,
+    circles: &mut HashMap<CircleKey, Circle>,
+    config: &ServerConfig,
+    (departed, resumed): (&HashMap<Uuid, DepartedClient>, Option<DepartedClient>),
+) {
+    let mut client = ConnectedClient {
+        messages: to_client_messages,
+        overflowed: Cell::new(false),
+        name: resumed
+            .as_ref()
+            .map_or_else(String::new, |resumed| resumed.name.clone()),
+        sprite: resumed.as_ref().and_then(|resumed| resumed.sprite),
+        last_pong: Instant::now(),
+        last_active: Instant::now(),
+        connection_state,
+        task: None,
+        udp: None,
+        udp_token: Uuid::new_v4(),
+        peer_ip: None,
+        spectator: resumed.as_ref().is_some_and(|resumed| resumed.spectator),
+        rate_limiter: TokenBucket::new(config.rate_limit),
+        reconnect_token: Uuid::new_v4(),
+        sent_circles: config.delta_encoding.then(HashMap::new),
+        bandwidth: Arc::default(),
+        interest: None,
+    };
+    client.send(ServerToClientMessage::Handshake {
+        uuid,
+        version: PROTOCOL_VERSION,
+        world_bounds: config.world_bounds,
+        default_color: default_color(uuid),
+        spawn_position: spawn_position(uuid, config.spawn_radius, config.world_bounds),
+        reconnect_token: client.reconnect_token,
+        udp_token: client.udp_token,
+    });
+
+    // the existing state is sent as one batch so a big world doesnt overflow the queue
+    let mut existing = vec![];
+    let others = clients
+        .iter()
+        .map(|(&other_uuid, other)| (other_uuid, &other.name, other.sprite));
+    // clients waiting to reconnect still have their circles, so they are announced as well,
+    // otherwise their circles and eventual disconnect would come out of nowhere
+    let waiting = departed
+        .values()
+        .map(|other| (other.uuid, &other.name, other.sprite));
+    for (other_uuid, name, sprite) in others.chain(waiting) {
+        existing.push(ServerToClientMessage::ClientConnected(other_uuid));
+        if !name.is_empty() {
+            existing.push(ServerToClientMessage::NameChanged(other_uuid, name.clone()));
+        }
+        if sprite.is_some() {
+            existing.push(ServerToClientMessage::SpriteChanged(other_uuid, sprite));
+        }
+    }
+    // this includes circles loaded from a save that dont belong to a connected client
+    let initial_state = circles
+        .iter()
+        .map(|(&key, &circle)| (key, circle))
+        .collect::<Vec<_>>();
+    for &(key, circle) in &initial_state {
+        client.record_sent(key, circle);
+    }
+    existing.push(ServerToClientMessage::InitialState(initial_state));
+    client.send(ServerToClientMessage::Batch(existing));
+
+    // everyone else still has the circle from before the connection was lost
+    if resumed.is_some() {
+        clients.insert(uuid, client);
+        for client in clients.values() {
+            client.send(ServerToClientMessage::ClientReconnected(uuid));
+        }
+        return;
+    }
+
+    // the client replaces this as soon as it sends its own circle, until then it still shows up
+    // where it spawned instead of not at all
+    let circle = Circle::spawn(
+        spawn_position(uuid, config.spawn_radius, config.world_bounds),
+        default_color(uuid),
+    );
+    let key = (uuid, SPAWN_CIRCLE_ID);
+    circles.insert(key, circle);
+
+    clients.insert(uuid, client);
+    for client in clients.values_mut() {
+        client.send(ServerToClientMessage::ClientConnected(uuid));
+        client.send(ServerToClientMessage::PlayerChanged(key, circle));
+        client.record_sent(key, circle);
+    }
+}
+
+/// Hashes the uuid into a point within `radius` of the origin, so players that join at the same
+/// time dont end up on top of each other
+pub fn spawn_position(uuid: Uuid, radius: f32, world_bounds: WorldBounds) -> Vector2<f32> {
+    let (high, low) = uuid.as_u64_pair();
+    let angle = high as f32 / u64::MAX as f32 * std::f32::consts::TAU;
+    // the top bits of `low` are the uuid variant, which is the same for every uuid, and the
+    // square root spreads the points evenly over the area instead of bunching them up in the middle
+    let distance = ((low << 2) as f32 / u64::MAX as f32).sqrt() * radius;
+    world_bounds.clamp(cgmath::vec2(angle.cos(), angle.sin()) * distance)
+}
+
+/// Hashes the uuid into a hue so new players are spread around the color wheel
+pub fn default_color(uuid: Uuid) -> Vector4<f32> {
+    const SATURATION: f32 = 0.65;
+    const VALUE: f32 = 0.9;
+
+    let (high, low) = uuid.as_u64_pair();
+    let hue = (high ^ low) as f32 / u64::MAX as f32 * 6.0;
+    let chroma = VALUE * SATURATION;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let min = VALUE - chroma;
+    cgmath::vec4(r + min, g + min, b + min, 1.0)
+}
+
+fn disconnect_overflowed_clients(
+    clients: &mut HashMap<Uuid, ConnectedClient>,
+    circles: &mut HashMap<CircleKey, Circle>,
+    events: &Events,
+) {
+    // disconnecting broadcasts to everyone else, which could overflow another queue
+    while let Some(uuid) = clients
+        .iter()
+        .find(|(_, client)| client.overflowed.get())
+        .map(|(&uuid, _)| uuid)
+    {
+        warn!(%uuid, "fell too far behind");
+        disconnect_client(uuid, DisconnectReason::Timeout, clients, circles, events);
+    }
+}
+
+fn disconnect_client(
+    uuid: Uuid,
+    reason: DisconnectReason,
+    clients: &mut HashMap<Uuid, ConnectedClient>,
+    circles: &mut HashMap<CircleKey, Circle>,
+    events: &Events,
+) {
+    info!(%uuid, "disconnected");
+    // the task might be stuck writing to a client that stopped reading
+    if let Some(task) =
+        remove_client(uuid, reason, clients, circles, events).and_then(|client| client.task)
+    {
+        task.abort();
+    }
+}
+
+/// Holds on to a remote client that lost its connection when reconnecting is enabled, otherwise
+/// this is the same as [`disconnect_client`]
+fn lose_client(
+    uuid: Uuid,
+    reason: DisconnectReason,
+    clients: &mut HashMap<Uuid, ConnectedClient>,
+    circles: &mut HashMap<CircleKey, Circle>,
+    events: &Events,
+    (departed, config): (&mut HashMap<Uuid, DepartedClient>, &ServerConfig),
+) {
+    let Some(grace) = config
+        .reconnect_grace
+        .filter(|_| clients.get(&uuid).is_some_and(|client| !client.is_local()))
+    else {
+        disconnect_client(uuid, reason, clients, circles, events);
+        return;
+    };
+    let Some(client) = clients.remove(&uuid) else {
+        return;
+    };
+    info!(%uuid, "waiting for a reconnect");
+    if let Some(task) = client.task {
+        task.abort();
+    }
+    departed.insert(
+        client.reconnect_token,
+        DepartedClient {
+            uuid,
+            name: client.name,
+            sprite: client.sprite,
+            spectator: client.spectator,
+            reason,
+            deadline: Instant::now() + grace,
+        },
+    );
+}
+
+/// Lets everyone know about the clients that did not reconnect in time
+fn expire_departed_clients(
+    departed: &mut HashMap<Uuid, DepartedClient>,
+    clients: &mut HashMap<Uuid, ConnectedClient>,
+    circles: &mut HashMap<CircleKey, Circle>,
+    events: &Events,
+) {
+    let now = Instant::now();
+    departed.retain(|_, departed| {
+        if departed.deadline > now {
+            return true;
+        }
+        info!(uuid = %departed.uuid, "did not reconnect in time");
+        circles.retain(|&(owner, _), _| owner != departed.uuid);
+        _ = events.send(ServerEvent::ClientLeft(departed.uuid, departed.reason));
+        for client in clients.values_mut() {
+            client.send(ServerToClientMessage::ClientDisconnected(
+                departed.uuid,
+                departed.reason,
+            ));
+            client.forget_sent(|(owner, _)| owner == departed.uuid);
+        }
+        false
+    });
+}
+
+fn remove_client(
+    uuid: Uuid,
+    reason: DisconnectReason,
+    clients: &mut HashMap<Uuid, ConnectedClient>,
+    circles: &mut HashMap<CircleKey, Circle>,
+    events: &Events,
+) -> Option<ConnectedClient> {
+    let client = clients.remove(&uuid)?;
+    _ = events.send(ServerEvent::ClientLeft(uuid, reason));
+    circles.retain(|&(owner, _), _| owner != uuid);
+    for client in clients.values_mut() {
+        client.send(ServerToClientMessage::ClientDisconnected(uuid, reason));
+        client.forget_sent(|(owner, _)| owner == uuid);
+    }
+    Some(client)
+}
+
+fn kick_client(
+    uuid: Uuid,
+    reason: DisconnectReason,
+    clients: &mut HashMap<Uuid, ConnectedClient>,
+    circles: &mut HashMap<CircleKey, Circle>,
+    events: &Events,
+) {
+    // the task is left running so it can deliver the message before closing the connection
+    if let Some(kicked) = remove_client(uuid, reason, clients, circles, events) {
+        kicked.send(ServerToClientMessage::Kicked);
+    }
+}
+
+fn handle_message(
+    message: ClientToServerMessage,
+    uuid: Uuid,
+    clients: &mut HashMap<Uuid, ConnectedClient>,
+    circles: &mut HashMap<CircleKey, Circle>,
+    (events, paused): (&Events, &mut bool),
+    (udp_socket, config, (last_ping_id, last_ping_time), changed_circles): (
+        &UdpSocket,
+        &ServerConfig,
+        (u64, Instant),
+        Option<&mut HashSet<CircleKey>>,
+    ),
+) {
+    let Some(client) = clients.get_mut(&uuid) else {
+        return;
+    };
+    // pings and disconnects are cheap and needed to keep the connection state right
+    let limited = !client.is_local()
+        && !matches!(
+            message,
+            ClientToServerMessage::Disconnect | ClientToServerMessage::Ping(_)
+        );
+    if let Some(rate_limit) = config.rate_limit.filter(|_| limited) {
+        if !client.rate_limiter.take(rate_limit) {
+            if client.rate_limiter.dropped > rate_limit.max_dropped {
+                warn!(%uuid, "kicked for sending too many messages");
+                kick_client(uuid, DisconnectReason::Kicked, clients, circles, events);
+            }
+            return;
+        }
+    }
+    if !matches!(message, ClientToServerMessage::Ping(_)) {
+        client.last_active = Instant::now();
+    }
+    match message {
+        ClientToServerMessage::Disconnect => {
+            disconnect_client(uuid, DisconnectReason::Quit, clients, circles, events);
+        }
+        // these are only meaningful before the client has been let in
+        ClientToServerMessage::Join { .. } | ClientToServerMessage::QueryInfo => {}
+        ClientToServerMessage::Ping(id) => {
+            client.last_pong = Instant::now();
+            if id == last_ping_id {
+                client.send(ServerToClientMessage::Latency(last_ping_time.elapsed()));
+            }
+        }
+        ClientToServerMessage::PlayerChanged(id, circle) => {
+            if client.spectator || !circles.contains_key(&(uuid, id)) {
+                return;
+            }
+            let circle = keep_position_while_paused((uuid, id), circle, client, circles, *paused);
+            update_circle(
+                (uuid, id),
+                circle,
+                clients,
+                (circles, *paused),
+                (udp_socket, config, changed_circles),
+            );
+        }
+        ClientToServerMessage::MovedTo(id, position) => {
+            // the rest of the circle stays whatever was sent last
+            let Some(&circle) = circles.get(&(uuid, id)).filter(|_| !client.spectator) else {
+                return;
+            };
+            if *paused {
+                client.send(ServerToClientMessage::PositionCorrected(
+                    id,
+                    circle.position,
+                ));
+                return;
+            }
+            update_circle(
+                (uuid, id),
+                Circle { position, ..circle },
+                clients,
+                (circles, *paused),
+                (udp_socket, config, changed_circles),
+            );
+        }
+        ClientToServerMessage::AddCircle(id, circle) => {
+            if client.spectator {
+                return;
+            }
+            let count = circles.keys().filter(|&&(owner, _)| owner == uuid).count();
+            if !circles.contains_key(&(uuid, id)) && count >= config.max_circles_per_player {
+                warn!(%uuid, count, "rejected a circle over the limit");
+                return;
+            }
+            let circle = keep_position_while_paused((uuid, id), circle, client, circles, *paused);
+            update_circle(
+                (uuid, id),
+                circle,
+                clients,
+                (circles, *paused),
+                (udp_socket, config, changed_circles),
+            );
+        }
+        ClientToServerMessage::RemoveCircle(id) => {
+            if circles.remove(&(uuid, id)).is_none() {
+                return;
+            }
+            for client in clients.values_mut() {
+                client.send(ServerToClientMessage::CircleRemoved((uuid, id)));
+                client.forget_sent(|key| key == (uuid, id));
+            }
+        }
+        ClientToServerMessage::SetSpectator(spectator) => {
+            if client.spectator == spectator {
+                return;
+            }
+            client.spectator = spectator;
+            circles.retain(|&(owner, _), _| owner != uuid);
+            for client in clients.values_mut() {
+                client.send(ServerToClientMessage::SpectatorChanged(uuid, spectator));
+                client.forget_sent(|(owner, _)| owner == uuid);
+            }
+        }
+        ClientToServerMessage::Reliable(id, message) => {
+            handle_message(
+                *message,
+                uuid,
+                clients,
+                circles,
+                (events, paused),
+                (
+                    udp_socket,
+                    config,
+                    (last_ping_id, last_ping_time),
+                    changed_circles,
+                ),
+            );
+            if let Some(client) = clients.get(&uuid) {
+                client.send(ServerToClientMessage::Ack(id));
+            }
+        }
+        ClientToServerMessage::Kick(kicked_uuid) => {
+            if !client.is_local() {
+                warn!(%uuid, %kicked_uuid, "tried to kick without being the host");
+                return;
+            }
+            if clients
+                .get(&kicked_uuid)
+                .is_none_or(ConnectedClient::is_local)
+            {
+                return;
+            }
+            info!(uuid = %kicked_uuid, "kicked");
+            kick_client(
+                kicked_uuid,
+                DisconnectReason::Kicked,
+                clients,
+                circles,
+                events,
+            );
+        }
+        ClientToServerMessage::SetPaused(pause) => {
+            if !client.is_local() {
+                warn!(%uuid, "tried to pause without being the host");
+                return;
+            }
+            if *paused == pause {
+                return;
+            }
+            info!(paused = pause, "pause changed");
+            *paused = pause;
+            for client in clients.values() {
+                client.send(ServerToClientMessage::Paused(pause));
+            }
+        }
+        ClientToServerMessage::SetViewport(center, extent) => {
+            let Some(interest_radius) = config.interest_radius else {
+                return;
+            };
+            if !center.x.is_finite() || !center.y.is_finite() || !extent.is_finite() {
+                warn!(%uuid, "dropped a nan or infinite viewport");
+                return;
+            }
+            // everything was sent to the client until now
+            let interest = client.interest.get_or_insert_with(|| Interest {
+                owner: uuid,
+                center,
+                radius: 0.0,
+                visible: circles.keys().copied().collect(),
+            });
+            interest.center = center;
+            interest.radius = extent.max(0.0) + interest_radius;
+            for (&key, &circle) in circles.iter() {
+                if client.update_interest(key, circle) == Some(true) {
+                    client.send(ServerToClientMessage::PlayerChanged(key, circle));
+                    client.record_sent(key, circle);
+                }
+            }
+        }
+        ClientToServerMessage::SetName(name) => {
+            if name.len() > MAX_NAME_LENGTH {
+                warn!(%uuid, bytes = name.len(), "rejected a name");
+                return;
+            }
+            client.name = name.clone();
+            for client in clients.values() {
+                client.send(ServerToClientMessage::NameChanged(uuid, name.clone()));
+            }
+        }
+        ClientToServerMessage::SetSprite(sprite) => {
+            if sprite.is_some_and(|sprite| sprite >= SPRITE_COUNT) {
+                warn!(%uuid, ?sprite, "rejected an unknown sprite");
+                return;
+            }
+            client.sprite = sprite;
+            for client in clients.values() {
+                client.send(ServerToClientMessage::SpriteChanged(uuid, sprite));
+            }
+        }
+        ClientToServerMessage::Chat(text) => {
+            if text.len() > MAX_CHAT_MESSAGE_LENGTH {
+                warn!(%uuid, bytes = text.len(), "rejected a chat message");
+                return;
+            }
+            for client in clients.values() {
+                client.send(ServerToClientMessage::Chat(uuid, text.clone()));
+            }
+        }
+    }
+}
+
+/// Whether every float in the circle is a real number
+fn is_finite(circle: &Circle) -> bool {
+    let Circle {
+        position,
+        color,
+        shape,
+        outline_color,
+        outline_thickness,
+    } = *circle;
+    [
+        position.x,
+        position.y,
+        color.x,
+        color.y,
+        color.z,
+        color.w,
+        shape.extent(),
+        outline_color.x,
+        outline_color.y,
+        outline_color.z,
+        outline_thickness,
+    ]
+    .into_iter()
+    .all(f32::is_finite)
+}
+
+/// Puts back whatever an existing circle was moved to before the client heard about the pause,
+/// the rest of the update still applies
+fn keep_position_while_paused(
+    key: CircleKey,
+    circle: Circle,
+    client: &ConnectedClient,
+    circles: &HashMap<CircleKey, Circle>,
+    paused: bool,
+) -> Circle {
+    match circles.get(&key).filter(|_| paused) {
+        Some(old) => {
+            client.send(ServerToClientMessage::PositionCorrected(
+                key.1,
+                old.position,
+            ));
+            Circle {
+                position: old.position,
+                ..circle
+            }
+        }
+        None => circle,
+    }
+}
+
+/// Cleans up the circle, pushes the circles it overlaps out of the way and broadcasts the result
+fn update_circle(
+    key: CircleKey,
+    mut circle: Circle,
+    clients: &mut HashMap<Uuid, ConnectedClient>,
+    (circles, paused): (&mut HashMap<CircleKey, Circle>, bool),
+    (udp_socket, config, mut changed_circles): (
+        &UdpSocket,
+        &ServerConfig,
+        Option<&mut HashSet<CircleKey>>,
+    ),
+) {
+    // there is no sensible value to clamp nan to, so the whole update is dropped
+    if !is_finite(&circle) {
+        warn!(uuid = %key.0, id = key.1, "dropped a circle with a nan or infinite field");
+        return;
+    }
+    circle.shape = circle
+        .shape
+        .with_extent(circle.shape.extent().clamp(MIN_RADIUS, MAX_RADIUS));
+    circle.outline_thickness = circle.outline_thickness.clamp(0.0, MAX_OUTLINE_THICKNESS);
+    circle.color = circle.color.map(|component| component.clamp(0.0, 1.0));
+    circle.outline_color = circle
+        .outline_color
+        .map(|component| component.clamp(0.0, 1.0));
+
+    let world_bounds = config.world_bounds;
+    let mut corrected = !world_bounds.contains(circle.position);
+    circle.position = world_bounds.clamp(circle.position);
+
+    let mut pushed = vec![];
+    // nobody gets shoved while paused, overlaps are sorted out by the next update after it
+    for (&other_key, other) in circles.iter_mut().filter(|_| !paused) {
+        if other_key != key && separate_circles(&mut circle, other) {
+            other.position = world_bounds.clamp(other.position);
+            pushed.push(other_key);
+        }
+    }
+    circle.position = world_bounds.clamp(circle.position);
+    corrected |= !pushed.is_empty();
+    if corrected {
+        pushed.push(key);
+    }
+    let only_moved = circles.get(&key).is_some_and(|&old| {
+        Circle {
+            position: circle.position,
+            ..old
+        } == circle
+    });
+    circles.insert(key, circle);
+
+    broadcast_player_changed(
+        key,
+        circle,
+        only_moved,
+        clients,
+        udp_socket,
+        changed_circles.as_deref_mut(),
+    );
+    for pushed_key in pushed {
+        let pushed_circle = circles[&pushed_key];
+        let (owner, id) = pushed_key;
+        if let Some(client) = clients.get(&owner) {
+            client.send(ServerToClientMessage::PositionCorrected(
+                id,
+                pushed_circle.position,
+            ));
+        }
+        if pushed_key != key {
+            broadcast_player_changed(
+                pushed_key,
+                pushed_circle,
+                true,
+                clients,
+                udp_socket,
+                changed_circles.as_deref_mut(),
+            );
+        }
+    }
+}
+
+/// Sends the change right away, or leaves it for the next snapshot when the server runs on a tick,
+/// when `only_moved` is set just the position is sent
+fn broadcast_player_changed(
+    key: CircleKey,
+    circle: Circle,
+    only_moved: bool,
+    clients: &mut HashMap<Uuid, ConnectedClient>,
+    udp_socket: &UdpSocket,
+    changed_circles: Option<&mut HashSet<CircleKey>>,
+) {
+    match changed_circles {
+        Some(changed_circles) => _ = changed_circles.insert(key),
+        None => {
+            for client in clients.values_mut() {
+                client.send_player_changed(key, circle, only_moved, udp_socket);
+            }
+        }
+    }
+}
+
+/// Pushes both circles away from each other by half of their overlap, returns whether they
+/// overlapped at all
+pub fn separate_circles(a: &mut Circle, b: &mut Circle) -> bool {
+    let offset = b.position - a.position;
+    let distance = offset.magnitude();
+    let overlap = a.shape.extent() + b.shape.extent() - distance;
+    if overlap <= COLLISION_TOLERANCE {
+        return false;
+    }
+
+    let normal = if distance > 0.0 {
+        offset / distance
+    } else {
+        cgmath::vec2(1.0, 0.0)
+    };
+    a.position -= normal * (overlap / 2.0);
+    b.position += normal * (overlap / 2.0);
+    true
+}
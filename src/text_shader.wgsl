@@ -0,0 +1,83 @@
+struct VertexInput {
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) glyph_index: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) glyph_index: u32,
+    @location(1) uv: vec2<f32>,
+};
+
+struct Camera {
+    position: vec2<f32>,
+    aspect: f32,
+    zoom: f32,
+    rotation: f32,
+    viewport_height: f32,
+    min_circle_pixels: f32,
+};
+
+@group(0)
+@binding(0)
+var<uniform> camera: Camera;
+
+// rotates offsets from the camera position into view space
+fn view_matrix() -> mat2x2<f32> {
+    let c = cos(camera.rotation);
+    let s = sin(camera.rotation);
+    return mat2x2<f32>(c, -s, s, c);
+}
+
+fn view_to_clip(view_position: vec2<f32>) -> vec4<f32> {
+    return vec4<f32>(view_position * camera.zoom / vec2<f32>(camera.aspect, 1.0), 0.0, 1.0);
+}
+
+struct Glyph {
+    anchor: vec2<f32>,
+    offset: vec2<f32>,
+    size: vec2<f32>,
+    uv_min: vec2<f32>,
+    uv_max: vec2<f32>,
+    color: vec4<f32>,
+};
+
+@group(1)
+@binding(0)
+var<storage, read> glyphs: array<Glyph>;
+
+@group(2)
+@binding(0)
+var atlas: texture_2d<f32>;
+
+@group(2)
+@binding(1)
+var atlas_sampler: sampler;
+
+@vertex
+fn vertex(input: VertexInput) -> VertexOutput {
+    var output: VertexOutput;
+    output.glyph_index = input.glyph_index;
+
+    let corner = vec2<f32>(
+        f32((input.vertex_index >> 0u) & 1u),
+        f32((input.vertex_index >> 1u) & 1u),
+    );
+
+    let glyph = glyphs[input.glyph_index];
+    // the atlas is stored top to bottom, but world space goes up
+    output.uv = mix(glyph.uv_min, glyph.uv_max, vec2<f32>(corner.x, 1.0 - corner.y));
+
+    // only the anchor follows the camera rotation, so text always stays upright
+    let view_position = view_matrix() * (glyph.anchor - camera.position) + glyph.offset + corner * glyph.size;
+    output.clip_position = view_to_clip(view_position);
+
+    return output;
+}
+
+@fragment
+fn pixel(input: VertexOutput) -> @location(0) vec4<f32> {
+    let color = glyphs[input.glyph_index].color;
+    let coverage = textureSample(atlas, atlas_sampler, input.uv).r;
+    return vec4<f32>(color.rgb, color.a * coverage);
+}
@@ -0,0 +1,77 @@
+use anyhow::{bail, Context as _};
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+
+pub use tokio_rustls::rustls;
+
+#[derive(Debug, Clone)]
+pub struct TlsClientConfig {
+    pub config: Arc<rustls::ClientConfig>,
+    /// The name the server's certificate has to be valid for, the server's ip address is used if this is `None`
+    pub server_name: Option<String>,
+}
+
+impl TlsClientConfig {
+    /// Only trusts the certificates in the PEM file at `path`, usually the server's self-signed certificate
+    pub fn trusting(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let mut roots = rustls::RootCertStore::empty();
+        let (added, _) = roots.add_parsable_certificates(&read_certificates(path)?);
+        if added == 0 {
+            bail!("no valid certificates found in {}", path.display());
+        }
+        Ok(Self {
+            config: Arc::new(
+                rustls::ClientConfig::builder()
+                    .with_safe_defaults()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth(),
+            ),
+            server_name: None,
+        })
+    }
+}
+
+/// Loads a certificate chain and private key from PEM files
+pub fn load_server_config(
+    certificate_path: impl AsRef<Path>,
+    key_path: impl AsRef<Path>,
+) -> anyhow::Result<Arc<rustls::ServerConfig>> {
+    let certificates = read_certificates(certificate_path.as_ref())?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect::<Vec<_>>();
+    if certificates.is_empty() {
+        bail!(
+            "no certificates found in {}",
+            certificate_path.as_ref().display()
+        );
+    }
+    let key = read_private_key(key_path.as_ref())?;
+    Ok(Arc::new(
+        rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certificates, key)?,
+    ))
+}
+
+fn open(path: &Path) -> anyhow::Result<BufReader<File>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    Ok(BufReader::new(file))
+}
+
+fn read_certificates(path: &Path) -> anyhow::Result<Vec<Vec<u8>>> {
+    Ok(rustls_pemfile::certs(&mut open(path)?)?)
+}
+
+fn read_private_key(path: &Path) -> anyhow::Result<rustls::PrivateKey> {
+    for item in rustls_pemfile::read_all(&mut open(path)?)? {
+        match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(rustls::PrivateKey(key)),
+            _ => {}
+        }
+    }
+    bail!("no private key found in {}", path.display())
+}
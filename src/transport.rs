@@ -0,0 +1,582 @@
+#[cfg(feature = "websocket")]
+use crate::codec::Codec as _;
+#[cfg(feature = "tls")]
+use crate::tls::{rustls, TlsClientConfig};
+use crate::{
+    client::{read_message_with, write_message_with, MAX_MESSAGE_SIZE},
+    codec::CodecKind,
+};
+use anyhow::bail;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    future::Future,
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+};
+#[cfg(feature = "test-transport")]
+use tokio::{io::DuplexStream, sync::mpsc::UnboundedSender};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _, ReadBuf},
+    net::{TcpStream, UdpSocket},
+};
+
+pub const MAX_DATAGRAM_SIZE: usize = 1200;
+const FRAME_HEADER_SIZE: usize = 1 + std::mem::size_of::<u64>();
+/// How much can be written to an in-memory connection before the writer has to wait
+#[cfg(feature = "test-transport")]
+const MEMORY_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransportKind {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+pub trait Transport {
+    fn send<T>(&mut self, message: T) -> impl Future<Output = anyhow::Result<()>> + Send
+    where
+        T: Serialize + Send;
+
+    /// Must be cancel safe so it can be used as a `select!` branch
+    fn receive<T>(&mut self) -> impl Future<Output = anyhow::Result<T>> + Send
+    where
+        T: DeserializeOwned + Send;
+
+    fn shutdown(&mut self) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+enum Stream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<tokio_rustls::TlsStream<TcpStream>>),
+    #[cfg(feature = "test-transport")]
+    Memory(DuplexStream),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "test-transport")]
+            Stream::Memory(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            // tcp only notices a peer that hung up once reading, failing the write instead would
+            // lose whatever the peer sent last
+            #[cfg(feature = "test-transport")]
+            Stream::Memory(stream) => match Pin::new(stream).poll_write(cx, buf) {
+                Poll::Ready(Err(error)) if error.kind() == io::ErrorKind::BrokenPipe => {
+                    Poll::Ready(Ok(buf.len()))
+                }
+                poll => poll,
+            },
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "test-transport")]
+            Stream::Memory(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "test-transport")]
+            Stream::Memory(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Adds up everything written through it into `count`
+struct Counted<'a> {
+    stream: &'a mut Stream,
+    count: &'a mut u64,
+}
+
+impl AsyncWrite for Counted<'_> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut *this.stream).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            *this.count = this.count.wrapping_add(written as u64);
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+pub struct TcpTransport {
+    stream: Stream,
+    buffer: Vec<u8>,
+    codec: CodecKind,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+impl TcpTransport {
+    pub fn new(stream: TcpStream) -> Self {
+        Self::from_stream(Stream::Plain(stream))
+    }
+
+    fn from_stream(stream: Stream) -> Self {
+        Self {
+            stream,
+            buffer: vec![],
+            codec: CodecKind::Cbor,
+            bytes_sent: 0,
+            bytes_received: 0,
+        }
+    }
+
+    pub fn codec(&self) -> CodecKind {
+        self.codec
+    }
+
+    /// Starts with [`CodecKind::Cbor`], both ends switch after the handshake
+    pub fn set_codec(&mut self, codec: CodecKind) {
+        self.codec = codec;
+    }
+
+    pub async fn connect(addr: SocketAddr, nodelay: bool) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(nodelay)?;
+        Ok(Self::new(stream))
+    }
+
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls(
+        addr: SocketAddr,
+        nodelay: bool,
+        tls: &TlsClientConfig,
+    ) -> io::Result<Self> {
+        let server_name = match &tls.server_name {
+            Some(name) => rustls::ServerName::try_from(name.as_str())
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?,
+            None => rustls::ServerName::IpAddress(addr.ip()),
+        };
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(nodelay)?;
+        let stream = tokio_rustls::TlsConnector::from(tls.config.clone())
+            .connect(server_name, stream)
+            .await?;
+        Ok(Self::from_stream(Stream::Tls(Box::new(
+            tokio_rustls::TlsStream::Client(stream),
+        ))))
+    }
+
+    #[cfg(feature = "tls")]
+    pub async fn accept_tls(
+        stream: TcpStream,
+        acceptor: &tokio_rustls::TlsAcceptor,
+    ) -> io::Result<Self> {
+        let stream = acceptor.accept(stream).await?;
+        Ok(Self::from_stream(Stream::Tls(Box::new(
+            tokio_rustls::TlsStream::Server(stream),
+        ))))
+    }
+
+    /// How many bytes were sent and received since the last time this was called, counting the
+    /// frame headers
+    pub fn take_byte_counts(&mut self) -> (u64, u64) {
+        (
+            std::mem::take(&mut self.bytes_sent),
+            std::mem::take(&mut self.bytes_received),
+        )
+    }
+
+    /// Receives a whole frame without decoding it, this is cancel safe like [`Transport::receive`]
+    pub(crate) async fn receive_frame(&mut self) -> anyhow::Result<Vec<u8>> {
+        // only whole frames are taken out of the buffer, so cancelling this never loses data
+        loop {
+            if let Some(length) = self.frame_length()? {
+                self.bytes_received = self.bytes_received.wrapping_add(length as u64);
+                return Ok(self.buffer.drain(..length).collect());
+            }
+            if self.stream.read_buf(&mut self.buffer).await? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+    }
+
+    #[cfg(feature = "test-transport")]
+    pub(crate) async fn send_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.stream.write_all(frame).await
+    }
+
+    fn frame_length(&self) -> anyhow::Result<Option<usize>> {
+        let Some(length_bytes) = self.buffer.get(1..FRAME_HEADER_SIZE) else {
+            return Ok(None);
+        };
+        let length = u64::from_be_bytes(length_bytes.try_into()?);
+        if length > MAX_MESSAGE_SIZE as u64 {
+            bail!("message of {length} bytes exceeds the maximum size of {MAX_MESSAGE_SIZE} bytes");
+        }
+        let length = FRAME_HEADER_SIZE + length as usize;
+        Ok((self.buffer.len() >= length).then_some(length))
+    }
+}
+
+impl Transport for TcpTransport {
+    async fn send<T>(&mut self, message: T) -> anyhow::Result<()>
+    where
+        T: Serialize + Send,
+    {
+        let stream = Counted {
+            stream: &mut self.stream,
+            count: &mut self.bytes_sent,
+        };
+        write_message_with(stream, message, self.codec).await
+    }
+
+    async fn receive<T>(&mut self) -> anyhow::Result<T>
+    where
+        T: DeserializeOwned + Send,
+    {
+        let frame = self.receive_frame().await?;
+        read_message_with(frame.as_slice(), self.codec).await
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        self.stream.flush().await?;
+        self.stream.shutdown().await
+    }
+}
+
+/// Carries every message as one binary websocket message holding nothing but the encoded message,
+/// so a browser can connect without knowing about the frame headers [`TcpTransport`] uses
+#[cfg(feature = "websocket")]
+pub struct WsTransport {
+    stream: tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>,
+    codec: CodecKind,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+#[cfg(feature = "websocket")]
+impl WsTransport {
+    fn from_stream(
+        stream: tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>,
+    ) -> Self {
+        Self {
+            stream,
+            codec: CodecKind::Cbor,
+            bytes_sent: 0,
+            bytes_received: 0,
+        }
+    }
+
+    /// See [`TcpTransport::set_codec`]
+    pub fn set_codec(&mut self, codec: CodecKind) {
+        self.codec = codec;
+    }
+
+    /// `url` looks like `ws://127.0.0.1:1235/`
+    pub async fn connect(url: &str) -> io::Result<Self> {
+        let (stream, _) =
+            tokio_tungstenite::connect_async_with_config(url, Some(websocket_config()), true)
+                .await
+                .map_err(io::Error::other)?;
+        Ok(Self::from_stream(stream))
+    }
+
+    /// Finishes the websocket upgrade, requests for anything other than `path` get a 404
+    pub async fn accept(stream: TcpStream, path: &str) -> io::Result<Self> {
+        use tokio_tungstenite::tungstenite::{
+            handshake::server::{ErrorResponse, Request, Response},
+            http::StatusCode,
+        };
+
+        let check_path = |request: &Request, response: Response| {
+            if request.uri().path() == path {
+                return Ok(response);
+            }
+            let mut error = ErrorResponse::new(Some(format!(
+                "nothing is served at `{}`",
+                request.uri().path()
+            )));
+            *error.status_mut() = StatusCode::NOT_FOUND;
+            Err(error)
+        };
+        let stream = tokio_tungstenite::accept_hdr_async_with_config(
+            tokio_tungstenite::MaybeTlsStream::Plain(stream),
+            check_path,
+            Some(websocket_config()),
+        )
+        .await
+        .map_err(io::Error::other)?;
+        Ok(Self::from_stream(stream))
+    }
+
+    /// How many encoded bytes were sent and received since the last time this was called, the
+    /// websocket framing isnt counted
+    pub fn take_byte_counts(&mut self) -> (u64, u64) {
+        (
+            std::mem::take(&mut self.bytes_sent),
+            std::mem::take(&mut self.bytes_received),
+        )
+    }
+}
+
+#[cfg(feature = "websocket")]
+fn websocket_config() -> tokio_tungstenite::tungstenite::protocol::WebSocketConfig {
+    tokio_tungstenite::tungstenite::protocol::WebSocketConfig {
+        max_message_size: Some(MAX_MESSAGE_SIZE),
+        ..Default::default()
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl Transport for WsTransport {
+    async fn send<T>(&mut self, message: T) -> anyhow::Result<()>
+    where
+        T: Serialize + Send,
+    {
+        use futures_util::SinkExt as _;
+
+        let bytes = self.codec.encode(&message)?;
+        self.bytes_sent = self.bytes_sent.wrapping_add(bytes.len() as u64);
+        self.stream
+            .send(tokio_tungstenite::tungstenite::Message::Binary(bytes))
+            .await?;
+        Ok(())
+    }
+
+    async fn receive<T>(&mut self) -> anyhow::Result<T>
+    where
+        T: DeserializeOwned + Send,
+    {
+        use futures_util::StreamExt as _;
+        use tokio_tungstenite::tungstenite::Message;
+
+        loop {
+            match self.stream.next().await {
+                Some(Ok(Message::Binary(bytes))) => {
+                    self.bytes_received = self.bytes_received.wrapping_add(bytes.len() as u64);
+                    return self.codec.decode(&bytes);
+                }
+                // pings are answered by tungstenite itself
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => {}
+                Some(Ok(Message::Text(_))) => bail!("expected a binary websocket message"),
+                Some(Ok(Message::Close(_))) | None => {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into())
+                }
+                Some(Err(error)) => return Err(error.into()),
+            }
+        }
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        self.stream.close(None).await.map_err(io::Error::other)
+    }
+}
+
+/// Either kind of connection the server accepts, they are handled the same way once accepted
+pub enum ServerTransport {
+    Tcp(TcpTransport),
+    #[cfg(feature = "websocket")]
+    WebSocket(WsTransport),
+}
+
+impl ServerTransport {
+    pub fn set_codec(&mut self, codec: CodecKind) {
+        match self {
+            Self::Tcp(transport) => transport.set_codec(codec),
+            #[cfg(feature = "websocket")]
+            Self::WebSocket(transport) => transport.set_codec(codec),
+        }
+    }
+
+    pub fn take_byte_counts(&mut self) -> (u64, u64) {
+        match self {
+            Self::Tcp(transport) => transport.take_byte_counts(),
+            #[cfg(feature = "websocket")]
+            Self::WebSocket(transport) => transport.take_byte_counts(),
+        }
+    }
+}
+
+impl From<TcpTransport> for ServerTransport {
+    fn from(transport: TcpTransport) -> Self {
+        Self::Tcp(transport)
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl From<WsTransport> for ServerTransport {
+    fn from(transport: WsTransport) -> Self {
+        Self::WebSocket(transport)
+    }
+}
+
+impl Transport for ServerTransport {
+    async fn send<T>(&mut self, message: T) -> anyhow::Result<()>
+    where
+        T: Serialize + Send,
+    {
+        match self {
+            Self::Tcp(transport) => transport.send(message).await,
+            #[cfg(feature = "websocket")]
+            Self::WebSocket(transport) => transport.send(message).await,
+        }
+    }
+
+    async fn receive<T>(&mut self) -> anyhow::Result<T>
+    where
+        T: DeserializeOwned + Send,
+    {
+        match self {
+            Self::Tcp(transport) => transport.receive().await,
+            #[cfg(feature = "websocket")]
+            Self::WebSocket(transport) => transport.receive().await,
+        }
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(transport) => transport.shutdown().await,
+            #[cfg(feature = "websocket")]
+            Self::WebSocket(transport) => transport.shutdown().await,
+        }
+    }
+}
+
+/// Opens connections to a server that never touch the network, so tests dont fight over ports,
+/// see [`crate::server::ServerHandle::memory_connector`]
+#[cfg(feature = "test-transport")]
+#[derive(Debug, Clone)]
+pub struct MemoryConnector(UnboundedSender<TcpTransport>);
+
+#[cfg(feature = "test-transport")]
+impl MemoryConnector {
+    pub(crate) fn new(connections: UnboundedSender<TcpTransport>) -> Self {
+        Self(connections)
+    }
+
+    /// The server end is handled the same way as an accepted tcp connection
+    pub fn connect(&self) -> io::Result<TcpTransport> {
+        let (client, server) = tokio::io::duplex(MEMORY_BUFFER_SIZE);
+        self.0
+            .send(TcpTransport::from_stream(Stream::Memory(server)))
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+        Ok(TcpTransport::from_stream(Stream::Memory(client)))
+    }
+}
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+    send_sequence: u64,
+    receive_sequence: u64,
+}
+
+impl UdpTransport {
+    pub async fn connect(addr: SocketAddr) -> io::Result<Self> {
+        let local_addr: SocketAddr = if addr.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local_addr).await?;
+        socket.connect(addr).await?;
+        Ok(Self {
+            socket,
+            buffer: vec![0; u16::MAX.into()],
+            send_sequence: 0,
+            receive_sequence: 0,
+        })
+    }
+}
+
+impl Transport for UdpTransport {
+    async fn send<T>(&mut self, message: T) -> anyhow::Result<()>
+    where
+        T: Serialize + Send,
+    {
+        self.send_sequence += 1;
+        let bytes = encode_datagram(self.send_sequence, message)?;
+        self.socket.send(&bytes).await?;
+        Ok(())
+    }
+
+    async fn receive<T>(&mut self) -> anyhow::Result<T>
+    where
+        T: DeserializeOwned + Send,
+    {
+        loop {
+            let length = self.socket.recv(&mut self.buffer).await?;
+            match decode_datagram(&self.buffer[..length]) {
+                Ok((sequence, message)) if sequence > self.receive_sequence => {
+                    self.receive_sequence = sequence;
+                    return Ok(message);
+                }
+                Ok(_) => {}
+                Err(error) => tracing::warn!(%error, "dropped a malformed datagram"),
+            }
+        }
+    }
+
+    async fn shutdown(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) fn encode_datagram<T>(sequence: u64, message: T) -> anyhow::Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    let mut bytes = vec![];
+    ciborium::into_writer(&(sequence, message), &mut bytes)?;
+    if bytes.len() > MAX_DATAGRAM_SIZE {
+        bail!(
+            "datagram of {} bytes exceeds the maximum size of {MAX_DATAGRAM_SIZE} bytes",
+            bytes.len()
+        );
+    }
+    Ok(bytes)
+}
+
+pub(crate) fn decode_datagram<T>(bytes: &[u8]) -> anyhow::Result<(u64, T)>
+where
+    T: DeserializeOwned,
+{
+    Ok(ciborium::from_reader(bytes)?)
+}
@@ -0,0 +1,125 @@
+use multiplayer_game::{
+    client::{
+        read_message_with, write_message_with, Circle, CircleDelta, ClientToServerMessage,
+        DisconnectReason, ServerToClientMessage, Shape, WorldBounds, PROTOCOL_VERSION,
+    },
+    codec::{CborCodec, Codec, CodecKind},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt::Debug, time::Duration};
+use uuid::Uuid;
+
+fn circle() -> Circle {
+    Circle {
+        position: cgmath::vec2(1.5, -2.0),
+        color: cgmath::vec4(0.1, 0.2, 0.3, 0.4),
+        shape: Shape::Square { size: 1.25 },
+        outline_color: cgmath::vec3(1.0, 0.5, 0.0),
+        outline_thickness: 0.05,
+    }
+}
+
+async fn round_trip<T>(codec: impl Codec + Copy, message: T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let bytes = codec.encode(&message).unwrap();
+    assert_eq!(codec.decode::<T>(&bytes).unwrap(), message);
+
+    let mut frame = vec![];
+    write_message_with(&mut frame, &message, codec)
+        .await
+        .unwrap();
+    let decoded: T = read_message_with(frame.as_slice(), codec).await.unwrap();
+    assert_eq!(decoded, message);
+}
+
+async fn round_trip_messages(codec: impl Codec + Copy) {
+    let uuid = Uuid::new_v4();
+    let client_messages = [
+        ClientToServerMessage::Disconnect,
+        ClientToServerMessage::PlayerChanged(0, circle()),
+        ClientToServerMessage::MovedTo(1, cgmath::vec2(3.0, 4.0)),
+        ClientToServerMessage::SetName("Alice".into()),
+        ClientToServerMessage::SetSprite(None),
+        ClientToServerMessage::Reliable(7, Box::new(ClientToServerMessage::Chat("hi".into()))),
+        ClientToServerMessage::Join {
+            auth_token: None,
+            reconnect_token: Some(uuid),
+            codec: CodecKind::Bincode,
+        },
+    ];
+    for message in client_messages {
+        round_trip(codec, message).await;
+    }
+
+    let server_messages = [
+        ServerToClientMessage::Handshake {
+            uuid,
+            version: PROTOCOL_VERSION,
+            world_bounds: WorldBounds::default(),
+            default_color: cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+            spawn_position: cgmath::vec2(2.0, -1.0),
+            reconnect_token: Uuid::new_v4(),
+            udp_token: Uuid::new_v4(),
+        },
+        ServerToClientMessage::Batch(vec![
+            ServerToClientMessage::ClientConnected(uuid),
+            ServerToClientMessage::InitialState(vec![((uuid, 0), circle())]),
+        ]),
+        ServerToClientMessage::ClientDisconnected(uuid, DisconnectReason::Timeout),
+        ServerToClientMessage::Latency(Duration::from_millis(12)),
+        ServerToClientMessage::PlayerDelta(
+            (uuid, 1),
+            CircleDelta {
+                position: Some(cgmath::vec2(5.0, 6.0)),
+                ..Default::default()
+            },
+        ),
+        ServerToClientMessage::TagScores(vec![(uuid, Duration::from_secs(3))]),
+        ServerToClientMessage::Rejected("full".into()),
+    ];
+    for message in server_messages {
+        round_trip(codec, message).await;
+    }
+}
+
+#[tokio::test]
+async fn cbor_round_trips() {
+    round_trip_messages(CborCodec).await;
+    round_trip_messages(CodecKind::Cbor).await;
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+async fn json_round_trips() {
+    round_trip_messages(multiplayer_game::codec::JsonCodec).await;
+    round_trip_messages(CodecKind::Json).await;
+}
+
+#[cfg(feature = "bincode")]
+#[tokio::test]
+async fn bincode_round_trips() {
+    round_trip_messages(multiplayer_game::codec::BincodeCodec).await;
+    round_trip_messages(CodecKind::Bincode).await;
+}
+
+#[test]
+fn codecs_are_only_available_with_their_feature() {
+    assert!(CodecKind::Cbor.is_available());
+    assert_eq!(CodecKind::Json.is_available(), cfg!(feature = "json"));
+    assert_eq!(CodecKind::Bincode.is_available(), cfg!(feature = "bincode"));
+    for codec in [CodecKind::Json, CodecKind::Bincode] {
+        if !codec.is_available() {
+            assert!(codec.encode(&ClientToServerMessage::Disconnect).is_err());
+        }
+    }
+}
+
+#[test]
+fn codec_names_parse() {
+    for codec in [CodecKind::Cbor, CodecKind::Json, CodecKind::Bincode] {
+        assert_eq!(codec.to_string().parse::<CodecKind>().unwrap(), codec);
+    }
+    assert!("yaml".parse::<CodecKind>().is_err());
+}
@@ -0,0 +1,43 @@
+use cgmath::InnerSpace as _;
+use multiplayer_game::{
+    client::{Circle, Shape},
+    server::{separate_circles, COLLISION_TOLERANCE},
+};
+
+fn circle(x: f32, y: f32) -> Circle {
+    Circle {
+        position: cgmath::vec2(x, y),
+        color: cgmath::vec4(1.0, 1.0, 1.0, 1.0),
+        shape: Shape::Circle { radius: 1.0 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.0,
+    }
+}
+
+#[test]
+fn overlap_within_the_tolerance_is_left_alone() {
+    let (mut a, mut b) = (
+        circle(0.0, 0.0),
+        circle(2.0 - COLLISION_TOLERANCE / 2.0, 0.0),
+    );
+    let (before_a, before_b) = (a, b);
+    assert!(!separate_circles(&mut a, &mut b));
+    assert_eq!((a, b), (before_a, before_b));
+}
+
+#[test]
+fn overlapping_circles_are_pushed_apart_by_the_overlap() {
+    let (mut a, mut b) = (circle(0.0, 0.0), circle(0.0, 1.5));
+    assert!(separate_circles(&mut a, &mut b));
+    assert_eq!(a.position, cgmath::vec2(0.0, -0.25));
+    assert_eq!(b.position, cgmath::vec2(0.0, 1.75));
+    assert!(((b.position - a.position).magnitude() - 2.0).abs() < 1e-6);
+}
+
+#[test]
+fn circles_on_top_of_each_other_are_pushed_apart_sideways() {
+    let (mut a, mut b) = (circle(3.0, 3.0), circle(3.0, 3.0));
+    assert!(separate_circles(&mut a, &mut b));
+    assert_eq!(a.position, cgmath::vec2(2.0, 3.0));
+    assert_eq!(b.position, cgmath::vec2(4.0, 3.0));
+}
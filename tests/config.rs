@@ -0,0 +1,20 @@
+use multiplayer_game::config::Config;
+
+#[test]
+fn saved_config_loads_back_the_same() {
+    let path = std::env::temp_dir().join(format!("multiplayer-{}.toml", uuid::Uuid::new_v4()));
+    for config in [
+        Config::default(),
+        Config {
+            color: Some([0.1, 0.2, 0.3, 1.0]),
+            sprite: Some(2),
+            interpolation_delay_ms: 250,
+            updates_per_second: 12,
+            ..Default::default()
+        },
+    ] {
+        config.save_to(&path).unwrap();
+        assert_eq!(Config::load_from(&path).unwrap(), config);
+    }
+    std::fs::remove_file(&path).unwrap();
+}
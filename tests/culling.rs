@@ -0,0 +1,76 @@
+use cgmath::Vector2;
+use multiplayer_game::culling::{cull, ViewRect, MAX_RENDERED_CIRCLES};
+
+fn grid(count: usize, spacing: f32) -> Vec<Vector2<f32>> {
+    let side = (count as f32).sqrt().ceil() as usize;
+    (0..count)
+        .map(|index| Vector2 {
+            x: (index % side) as f32 * spacing - side as f32 * spacing / 2.0,
+            y: (index / side) as f32 * spacing - side as f32 * spacing / 2.0,
+        })
+        .collect()
+}
+
+#[test]
+fn only_visible_circles_are_kept() {
+    let positions = grid(10_000, 1.0);
+    let view = ViewRect::from_camera(Vector2 { x: 0.0, y: 0.0 }, 1.0, 0.1, 0.0);
+
+    let visible = cull(
+        positions.iter().copied(),
+        view,
+        |&position| (position, 0.5),
+        MAX_RENDERED_CIRCLES,
+    );
+    assert!(!visible.is_empty());
+    assert!(visible.len() < positions.len());
+    assert!(visible
+        .iter()
+        .all(|&position| view.intersects(position, 0.5)));
+    let culled = positions.len() - visible.len();
+    assert_eq!(
+        culled,
+        positions
+            .iter()
+            .filter(|&&position| !view.intersects(position, 0.5))
+            .count()
+    );
+}
+
+#[test]
+fn rotated_view_covers_the_rotated_screen() {
+    let view = ViewRect::from_camera(
+        Vector2 { x: 0.0, y: 0.0 },
+        2.0,
+        1.0,
+        std::f32::consts::FRAC_PI_2,
+    );
+    assert!((view.max.x - 1.0).abs() < 1e-5);
+    assert!((view.max.y - 2.0).abs() < 1e-5);
+
+    let diagonal = ViewRect::from_camera(
+        Vector2 { x: 0.0, y: 0.0 },
+        1.0,
+        1.0,
+        std::f32::consts::FRAC_PI_4,
+    );
+    assert!(diagonal.intersects(Vector2 { x: 0.0, y: 1.4 }, 0.0));
+    assert!(!diagonal.intersects(Vector2 { x: 0.0, y: 1.5 }, 0.0));
+}
+
+#[test]
+fn large_counts_are_thinned_in_order() {
+    let positions = grid(10_000, 0.01);
+    let view = ViewRect::from_camera(Vector2 { x: 0.0, y: 0.0 }, 1.0, 1.0, 0.0);
+
+    let indices = (0..positions.len()).collect::<Vec<_>>();
+    let kept = cull(
+        indices,
+        view,
+        |&index| (positions[index], 0.005),
+        MAX_RENDERED_CIRCLES,
+    );
+    assert_eq!(kept.len(), MAX_RENDERED_CIRCLES);
+    assert!(kept.windows(2).all(|indices| indices[0] < indices[1]));
+    assert!(*kept.last().unwrap() > positions.len() * 9 / 10);
+}
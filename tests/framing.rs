@@ -0,0 +1,181 @@
+use multiplayer_game::{
+    client::{
+        read_message, read_message_with_limit, write_message, Circle, CircleDelta,
+        ClientToServerMessage, DisconnectReason, ServerToClientMessage, Shape, WorldBounds,
+        MAX_MESSAGE_SIZE, PROTOCOL_VERSION,
+    },
+    codec::CodecKind,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt::Debug, io, time::Duration};
+use uuid::Uuid;
+
+fn circle() -> Circle {
+    Circle {
+        position: cgmath::vec2(1.5, -2.0),
+        color: cgmath::vec4(0.1, 0.2, 0.3, 0.4),
+        shape: Shape::Square { size: 1.25 },
+        outline_color: cgmath::vec3(1.0, 0.5, 0.0),
+        outline_thickness: 0.05,
+    }
+}
+
+async fn encode<T: Serialize>(message: T) -> Vec<u8> {
+    let mut bytes = vec![];
+    write_message(&mut bytes, message).await.unwrap();
+    bytes
+}
+
+async fn round_trip<T>(message: T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + Debug,
+{
+    let bytes = encode(&message).await;
+    let decoded: T = read_message(bytes.as_slice()).await.unwrap();
+    assert_eq!(decoded, message);
+}
+
+#[tokio::test]
+async fn client_messages_round_trip() {
+    let messages = [
+        ClientToServerMessage::Disconnect,
+        ClientToServerMessage::Ping(42),
+        ClientToServerMessage::PlayerChanged(0, circle()),
+        ClientToServerMessage::MovedTo(1, cgmath::vec2(3.0, 4.0)),
+        ClientToServerMessage::AddCircle(2, circle()),
+        ClientToServerMessage::RemoveCircle(2),
+        ClientToServerMessage::SetName("Alice".into()),
+        ClientToServerMessage::SetSprite(Some(2)),
+        ClientToServerMessage::SetSprite(None),
+        ClientToServerMessage::Chat("hello".into()),
+        ClientToServerMessage::SetSpectator(true),
+        ClientToServerMessage::Reliable(7, Box::new(ClientToServerMessage::Chat("hi".into()))),
+        ClientToServerMessage::Kick(Uuid::new_v4()),
+        ClientToServerMessage::SetPaused(true),
+        ClientToServerMessage::SetViewport(cgmath::vec2(1.0, 2.0), 3.0),
+        ClientToServerMessage::Join {
+            auth_token: Some("secret".into()),
+            reconnect_token: Some(Uuid::new_v4()),
+            codec: CodecKind::Json,
+        },
+        ClientToServerMessage::QueryInfo,
+    ];
+    for message in messages {
+        round_trip(message).await;
+    }
+}
+
+#[tokio::test]
+async fn server_messages_round_trip() {
+    let uuid = Uuid::new_v4();
+    let messages = [
+        ServerToClientMessage::Handshake {
+            uuid,
+            version: PROTOCOL_VERSION,
+            world_bounds: WorldBounds::default(),
+            default_color: cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+            spawn_position: cgmath::vec2(-1.0, 1.0),
+            reconnect_token: Uuid::new_v4(),
+            udp_token: Uuid::new_v4(),
+        },
+        ServerToClientMessage::Rejected("full".into()),
+        ServerToClientMessage::Unauthorized,
+        ServerToClientMessage::Info {
+            player_count: 3,
+            max_players: Some(8),
+            name: "server".into(),
+        },
+        ServerToClientMessage::Ack(3),
+        ServerToClientMessage::Batch(vec![
+            ServerToClientMessage::ClientConnected(uuid),
+            ServerToClientMessage::PlayerChanged((uuid, 0), circle()),
+        ]),
+        ServerToClientMessage::ClientConnected(uuid),
+        ServerToClientMessage::ClientDisconnected(uuid, DisconnectReason::Timeout),
+        ServerToClientMessage::ClientReconnected(uuid),
+        ServerToClientMessage::Ping(9),
+        ServerToClientMessage::Latency(Duration::from_millis(25)),
+        ServerToClientMessage::PlayerChanged((uuid, 0), circle()),
+        ServerToClientMessage::MovedTo((uuid, 1), cgmath::vec2(5.0, 6.0)),
+        ServerToClientMessage::PlayerDelta(
+            (uuid, 1),
+            CircleDelta {
+                position: Some(cgmath::vec2(5.0, 6.0)),
+                outline_thickness: Some(0.1),
+                ..Default::default()
+            },
+        ),
+        ServerToClientMessage::Snapshot(vec![((uuid, 0), circle())]),
+        ServerToClientMessage::InitialState(vec![((uuid, 0), circle()), ((uuid, 1), circle())]),
+        ServerToClientMessage::PositionCorrected(0, cgmath::vec2(0.5, 0.5)),
+        ServerToClientMessage::CircleRemoved((uuid, 2)),
+        ServerToClientMessage::LeftView((uuid, 3)),
+        ServerToClientMessage::Paused(true),
+        ServerToClientMessage::NameChanged(uuid, "Bob".into()),
+        ServerToClientMessage::SpriteChanged(uuid, Some(1)),
+        ServerToClientMessage::Chat(uuid, "hey".into()),
+        ServerToClientMessage::SpectatorChanged(uuid, false),
+        ServerToClientMessage::ServerShutdown,
+        ServerToClientMessage::Kicked,
+        ServerToClientMessage::Reconnecting,
+        ServerToClientMessage::Reconnected(uuid),
+        ServerToClientMessage::ProtocolError("bad frame".into()),
+    ];
+    for message in messages {
+        round_trip(message).await;
+    }
+}
+
+#[tokio::test]
+async fn empty_payloads_round_trip() {
+    round_trip(ClientToServerMessage::Chat(String::new())).await;
+    round_trip(ClientToServerMessage::SetName(String::new())).await;
+    round_trip(ServerToClientMessage::Batch(vec![])).await;
+    round_trip(ServerToClientMessage::Snapshot(vec![])).await;
+    round_trip(ServerToClientMessage::InitialState(vec![])).await;
+}
+
+#[tokio::test]
+async fn messages_near_the_size_limit_round_trip() {
+    // leaves room for the few bytes cbor needs to describe the message
+    round_trip(ClientToServerMessage::Chat(
+        "a".repeat(MAX_MESSAGE_SIZE - 64),
+    ))
+    .await;
+}
+
+#[tokio::test]
+async fn oversized_frames_are_rejected() {
+    let max_size = 16;
+    let mut bytes = vec![0];
+    bytes.extend_from_slice(&(max_size as u64 + 1).to_be_bytes());
+    // the whole payload is there so running out of bytes cant be what fails
+    bytes.resize(bytes.len() + max_size + 1, 0);
+    let error = read_message_with_limit::<ClientToServerMessage>(
+        bytes.as_slice(),
+        max_size,
+        CodecKind::Cbor,
+    )
+    .await
+    .unwrap_err();
+    assert!(error.downcast_ref::<io::Error>().is_none());
+    assert!(error.to_string().contains("exceeds the maximum size"));
+}
+
+#[tokio::test]
+async fn truncated_frames_are_an_error() {
+    let bytes = encode(ServerToClientMessage::PlayerChanged(
+        (Uuid::new_v4(), 0),
+        circle(),
+    ))
+    .await;
+    for length in [0, 1, bytes.len() / 2, bytes.len() - 1] {
+        let error = read_message::<ServerToClientMessage>(&bytes[..length])
+            .await
+            .unwrap_err();
+        let error = error
+            .downcast::<io::Error>()
+            .expect("a truncated frame should fail while reading, not decoding");
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "test-transport")]
+
+use multiplayer_game::{
+    client::{Client, ClientConfig, ClientToServerMessage, ReliableSendError},
+    laggy::LagConfig,
+};
+use std::time::{Duration, Instant};
+
+const DELAY: Duration = Duration::from_millis(100);
+
+async fn host() -> Client {
+    Client::create_local("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap()
+}
+
+async fn laggy_client(host: &Client, lag: LagConfig) -> Client {
+    let config = ClientConfig {
+        lag: Some(lag),
+        ..Default::default()
+    };
+    Client::connect_with_config(host.server_addr().unwrap(), config)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn messages_are_delayed_both_ways() {
+    let mut host = host().await;
+    let lag = LagConfig {
+        min_delay: DELAY,
+        max_delay: DELAY,
+        ..Default::default()
+    };
+    let mut client = laggy_client(&host, lag).await;
+
+    let start = Instant::now();
+    client
+        .send_reliable(ClientToServerMessage::Chat("hello".into()))
+        .await
+        .unwrap();
+    assert!(start.elapsed() >= DELAY * 2);
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn dropped_messages_never_arrive() {
+    let mut host = host().await;
+    let lag = LagConfig {
+        min_delay: Duration::ZERO,
+        max_delay: Duration::ZERO,
+        drop_probability: 1.0,
+        ..Default::default()
+    };
+    let mut client = laggy_client(&host, lag).await;
+
+    let result = client
+        .send_reliable_with_timeout(ClientToServerMessage::Chat("hello".into()), DELAY * 3)
+        .await;
+    assert!(matches!(result, Err(ReliableSendError::Timeout)));
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
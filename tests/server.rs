@@ -0,0 +1,2370 @@
+use cgmath::InnerSpace as _;
+use multiplayer_game::{
+    client::{
+        query_server, Circle, CircleDelta, Client, ClientConfig, ClientToServerMessage,
+        ConnectError, ConnectionHealth, ConnectionState, DisconnectReason, ServerToClientMessage,
+        Shape, WorldBounds, MAX_CHAT_MESSAGE_LENGTH, MAX_NAME_LENGTH, MAX_RADIUS, PROTOCOL_VERSION,
+        SPAWN_CIRCLE_ID, SPRITE_COUNT,
+    },
+    codec::CodecKind,
+    server::{
+        default_color, load_circles, run_server, save_circles, spawn_position, GameMode, RateLimit,
+        ServerConfig, ServerEvent, ServerHandle,
+    },
+    transport::{TcpTransport, Transport as _, TransportKind},
+};
+use std::{collections::HashSet, ops::ControlFlow, time::Duration};
+use tokio::{io::AsyncWriteExt as _, net::TcpListener, sync::mpsc::UnboundedReceiver};
+
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn host() -> Client {
+    Client::create_local("127.0.0.1:0".parse().unwrap())
+        .await
+        .unwrap()
+}
+
+/// Joins over an in-memory connection when the transport is available, so the tests that arent
+/// about the network dont depend on it
+async fn connect(host: &Client) -> Client {
+    Client::connect_with_config(host.server_addr().unwrap(), client_config(host))
+        .await
+        .unwrap()
+}
+
+#[cfg(feature = "test-transport")]
+fn client_config(host: &Client) -> ClientConfig {
+    ClientConfig {
+        memory: host.memory_connector(),
+        ..Default::default()
+    }
+}
+
+#[cfg(not(feature = "test-transport"))]
+fn client_config(_host: &Client) -> ClientConfig {
+    ClientConfig::default()
+}
+
+/// Like [`connect`] for servers that no client is hosting
+async fn connect_to(server: &ServerHandle) -> Client {
+    #[cfg(feature = "test-transport")]
+    let config = ClientConfig {
+        memory: Some(server.memory_connector()),
+        ..Default::default()
+    };
+    #[cfg(not(feature = "test-transport"))]
+    let config = ClientConfig::default();
+    Client::connect_with_config(server.local_addr(), config)
+        .await
+        .unwrap()
+}
+
+async fn receive_until<T>(
+    client: &mut Client,
+    mut f: impl FnMut(ServerToClientMessage) -> Option<T>,
+) -> T {
+    tokio::time::timeout(RECEIVE_TIMEOUT, async {
+        loop {
+            for message in client.drain() {
+                if let Some(result) = f(message.unwrap()) {
+                    return result;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for a message")
+}
+
+#[tokio::test]
+async fn player_changed_is_broadcast() {
+    let mut host = host().await;
+    let mut a = connect(&host).await;
+    let mut b = connect(&host).await;
+
+    // far away from where anyone spawns so it doesnt get pushed around
+    let circle = Circle {
+        position: cgmath::vec2(20.0, 20.0),
+        color: cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+        shape: Shape::Circle { radius: 0.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.1,
+    };
+    a.send_message(ClientToServerMessage::PlayerChanged(
+        SPAWN_CIRCLE_ID,
+        circle,
+    ))
+    .unwrap();
+
+    let a_uuid = a.uuid();
+    // skips the circle the server made for `a` when it spawned
+    let received = receive_until(&mut b, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, _), received)
+            if uuid == a_uuid && received.color == circle.color =>
+        {
+            Some(received)
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(received, circle);
+
+    a.disconnect().await;
+    b.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn player_changed_over_udp_is_broadcast() {
+    let mut host = host().await;
+    let mut a = Client::connect_with_config(
+        host.server_addr().unwrap(),
+        ClientConfig {
+            transport: TransportKind::Udp,
+            ..client_config(&host)
+        },
+    )
+    .await
+    .unwrap();
+    let mut b = connect(&host).await;
+
+    // far away from where anyone spawns so it doesnt get pushed around
+    let circle = Circle {
+        position: cgmath::vec2(20.0, 20.0),
+        color: cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+        shape: Shape::Circle { radius: 0.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.1,
+    };
+    a.send_message(ClientToServerMessage::PlayerChanged(
+        SPAWN_CIRCLE_ID,
+        circle,
+    ))
+    .unwrap();
+
+    let a_uuid = a.uuid();
+    let received = receive_until(&mut b, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, _), received)
+            if uuid == a_uuid && received.color == circle.color =>
+        {
+            Some(received)
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(received, circle);
+
+    a.disconnect().await;
+    b.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn spoofed_datagrams_are_dropped() {
+    let mut host = host().await;
+    let addr = host.server_addr().unwrap();
+    let mut transport = TcpTransport::connect(addr, true).await.unwrap();
+    transport
+        .send(ClientToServerMessage::Join {
+            auth_token: None,
+            reconnect_token: None,
+            codec: CodecKind::Cbor,
+        })
+        .await
+        .unwrap();
+    let ServerToClientMessage::Handshake {
+        uuid, udp_token, ..
+    } = transport.receive().await.unwrap()
+    else {
+        panic!("the first message wasnt a handshake");
+    };
+
+    // far away from where anyone spawns so it doesnt get pushed around
+    let circle = Circle {
+        position: cgmath::vec2(20.0, 20.0),
+        color: cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+        shape: Shape::Circle { radius: 0.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.1,
+    };
+    transport
+        .send(ClientToServerMessage::PlayerChanged(
+            SPAWN_CIRCLE_ID,
+            circle,
+        ))
+        .await
+        .unwrap();
+    receive_until(&mut host, |message| match message {
+        ServerToClientMessage::PlayerChanged((sender, _), _) if sender == uuid => Some(()),
+        _ => None,
+    })
+    .await;
+
+    let datagram = |sequence: u64, token: uuid::Uuid, position| {
+        let message = ClientToServerMessage::MovedTo(SPAWN_CIRCLE_ID, position);
+        let mut bytes = vec![];
+        ciborium::into_writer(&(sequence, (uuid, token, message)), &mut bytes).unwrap();
+        bytes
+    };
+    let spoofed = cgmath::vec2(-20.0, -20.0);
+    let moved = cgmath::vec2(25.0, 20.0);
+    // the right token from another ip, then the wrong token from the right ip, both are
+    // handled before the real datagram as loopback delivers them in order
+    let other_ip = tokio::net::UdpSocket::bind("127.0.0.2:0").await.unwrap();
+    other_ip
+        .send_to(&datagram(1, udp_token, spoofed), addr)
+        .await
+        .unwrap();
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket
+        .send_to(&datagram(1, uuid::Uuid::new_v4(), spoofed), addr)
+        .await
+        .unwrap();
+    socket
+        .send_to(&datagram(2, udp_token, moved), addr)
+        .await
+        .unwrap();
+
+    receive_until(&mut host, |message| match message {
+        ServerToClientMessage::MovedTo((sender, _), position) if sender == uuid => {
+            assert_ne!(position, spoofed, "a spoofed datagram was accepted");
+            (position == moved).then_some(())
+        }
+        _ => None,
+    })
+    .await;
+
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn overlapping_circles_are_pushed_apart() {
+    let mut host = host().await;
+    let mut a = connect(&host).await;
+    let mut b = connect(&host).await;
+    let a_uuid = a.uuid();
+
+    // far away from where anyone spawns so only these two circles overlap
+    let circle = Circle {
+        position: cgmath::vec2(20.0, 20.0),
+        color: cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+        shape: Shape::Circle { radius: 1.0 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.1,
+    };
+    a.send_message(ClientToServerMessage::PlayerChanged(
+        SPAWN_CIRCLE_ID,
+        circle,
+    ))
+    .unwrap();
+    receive_until(&mut b, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, _), received)
+            if uuid == a_uuid && received.color == circle.color =>
+        {
+            Some(())
+        }
+        _ => None,
+    })
+    .await;
+
+    b.send_message(ClientToServerMessage::PlayerChanged(
+        SPAWN_CIRCLE_ID,
+        Circle {
+            position: cgmath::vec2(20.5, 20.0),
+            ..circle
+        },
+    ))
+    .unwrap();
+    let corrected = |message| match message {
+        ServerToClientMessage::PositionCorrected(SPAWN_CIRCLE_ID, position) => Some(position),
+        _ => None,
+    };
+    let a_position = receive_until(&mut a, corrected).await;
+    let b_position = receive_until(&mut b, corrected).await;
+
+    // each circle is moved half of the overlap, along the line between their centers
+    assert!((a_position.x - 19.25).abs() < 0.01, "{a_position:?}");
+    assert!((b_position.x - 21.25).abs() < 0.01, "{b_position:?}");
+    assert_eq!(a_position.y, 20.0);
+    assert_eq!(b_position.y, 20.0);
+
+    a.disconnect().await;
+    b.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn connect_and_disconnect_are_broadcast_in_order() {
+    let mut host = host().await;
+    let mut a = connect(&host).await;
+    let mut b = connect(&host).await;
+    let b_uuid = b.uuid();
+
+    receive_until(&mut a, |message| match message {
+        ServerToClientMessage::ClientConnected(uuid) if uuid == b_uuid => Some(()),
+        ServerToClientMessage::ClientDisconnected(uuid, _) if uuid == b_uuid => {
+            panic!("{uuid} disconnected before it connected")
+        }
+        _ => None,
+    })
+    .await;
+
+    b.disconnect().await;
+    receive_until(&mut a, |message| match message {
+        ServerToClientMessage::ClientDisconnected(uuid, _) if uuid == b_uuid => Some(()),
+        _ => None,
+    })
+    .await;
+
+    a.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn moves_only_send_the_position() {
+    let mut host = host().await;
+    let a = connect(&host).await;
+    let mut b = connect(&host).await;
+    let a_uuid = a.uuid();
+
+    // far away from where anyone spawns so nothing gets pushed around
+    let circle = Circle {
+        position: cgmath::vec2(20.0, 20.0),
+        color: cgmath::vec4(0.0, 1.0, 0.0, 1.0),
+        shape: Shape::Square { size: 1.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.1,
+    };
+    a.send_message(ClientToServerMessage::PlayerChanged(
+        SPAWN_CIRCLE_ID,
+        circle,
+    ))
+    .unwrap();
+    receive_until(&mut b, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, _), received)
+            if uuid == a_uuid && received == circle =>
+        {
+            Some(())
+        }
+        _ => None,
+    })
+    .await;
+
+    a.send_message(ClientToServerMessage::MovedTo(
+        SPAWN_CIRCLE_ID,
+        cgmath::vec2(-20.0, 30.0),
+    ))
+    .unwrap();
+    let position = receive_until(&mut b, |message| match message {
+        ServerToClientMessage::MovedTo((uuid, _), position) if uuid == a_uuid => Some(position),
+        ServerToClientMessage::PlayerChanged((uuid, _), _) if uuid == a_uuid => {
+            panic!("a move shouldnt resend the whole circle")
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(position, cgmath::vec2(-20.0, 30.0));
+
+    // someone joining later sees the moved circle with everything else intact
+    let mut c = connect(&host).await;
+    let received = receive_until(&mut c, |message| match message {
+        ServerToClientMessage::InitialState(circles) => circles
+            .into_iter()
+            .find(|&((uuid, _), _)| uuid == a_uuid)
+            .map(|(_, circle)| circle),
+        _ => None,
+    })
+    .await;
+    assert_eq!(
+        received,
+        Circle {
+            position: cgmath::vec2(-20.0, 30.0),
+            ..circle
+        }
+    );
+
+    c.disconnect().await;
+    b.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn joiners_get_every_circle_in_one_message() {
+    let mut host = host().await;
+    let a = connect(&host).await;
+    let b = connect(&host).await;
+    let extra = Circle::spawn(cgmath::vec2(20.0, 20.0), cgmath::vec4(0.0, 0.0, 1.0, 1.0));
+    a.send_message(ClientToServerMessage::AddCircle(1, extra))
+        .unwrap();
+    let a_uuid = a.uuid();
+    receive_until(&mut host, |message| {
+        (message == ServerToClientMessage::PlayerChanged((a_uuid, 1), extra)).then_some(())
+    })
+    .await;
+
+    let (mut transport, _, _) = join_raw(host.server_addr().unwrap(), None).await;
+    let ServerToClientMessage::Batch(existing) = transport.receive().await.unwrap() else {
+        panic!("the existing state wasnt sent");
+    };
+    // the joiner's own spawn can be sent along in the same batch, but only after the initial state
+    let Some(ServerToClientMessage::InitialState(circles)) = existing
+        .iter()
+        .find(|message| !matches!(message, ServerToClientMessage::ClientConnected(_)))
+    else {
+        panic!("the circles werent sent as one initial state");
+    };
+    assert_eq!(
+        existing
+            .iter()
+            .filter(|message| matches!(message, ServerToClientMessage::InitialState(_)))
+            .count(),
+        1
+    );
+    let mut keys = circles.iter().map(|&(key, _)| key).collect::<Vec<_>>();
+    keys.sort();
+    let mut expected = vec![
+        (host.uuid(), SPAWN_CIRCLE_ID),
+        (a_uuid, SPAWN_CIRCLE_ID),
+        (a_uuid, 1),
+        (b.uuid(), SPAWN_CIRCLE_ID),
+    ];
+    expected.sort();
+    assert_eq!(keys, expected);
+
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn peers_follow_connects_and_disconnects() {
+    let mut host = host().await;
+    let mut a = connect(&host).await;
+    let mut b = connect(&host).await;
+    let b_uuid = b.uuid();
+
+    receive_until(&mut a, |message| match message {
+        ServerToClientMessage::ClientConnected(uuid) if uuid == b_uuid => Some(()),
+        _ => None,
+    })
+    .await;
+    let mut peers = a.peers();
+    peers.sort();
+    let mut expected = vec![host.uuid(), b_uuid];
+    expected.sort();
+    assert_eq!(peers, expected);
+
+    b.disconnect().await;
+    receive_until(&mut a, |message| match message {
+        ServerToClientMessage::ClientDisconnected(uuid, _) if uuid == b_uuid => Some(()),
+        _ => None,
+    })
+    .await;
+    assert_eq!(a.peers(), vec![host.uuid()]);
+
+    a.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn host_reports_bound_address() {
+    let mut host = host().await;
+    let addr = host.server_addr().unwrap();
+    assert_ne!(addr.port(), 0);
+
+    let mut client = Client::connect(addr).await.unwrap();
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn host_shares_reachable_address() {
+    let mut host = host().await;
+    assert_eq!(host.share_addresses(), vec![host.server_addr().unwrap()]);
+    host.disconnect().await;
+
+    let mut host = Client::create_local("0.0.0.0:0".parse().unwrap())
+        .await
+        .unwrap();
+    let port = host.server_addr().unwrap().port();
+    let addresses = host.share_addresses();
+    assert!(!addresses.is_empty());
+    for addr in addresses {
+        assert_eq!(addr.port(), port);
+        assert!(!addr.ip().is_unspecified());
+        let mut client = Client::connect(addr).await.unwrap();
+        client.disconnect().await;
+    }
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn drain_reports_disconnected_once() {
+    let mut host = host().await;
+    let mut client = connect(&host).await;
+    host.disconnect().await;
+
+    let errors = tokio::time::timeout(RECEIVE_TIMEOUT, async {
+        loop {
+            let errors = client.drain().filter(|message| message.is_err()).count();
+            if errors > 0 {
+                return errors;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for the client to disconnect");
+    assert_eq!(errors, 1);
+}
+
+#[tokio::test]
+async fn client_that_stops_answering_pings_is_disconnected() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            ping_interval: Duration::from_millis(50),
+            ping_timeout: Duration::from_millis(300),
+            reconnect_grace: None,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    // nothing reads from the raw transport, so the pings sent to it are never answered
+    let (_silent, silent_uuid, _) = join_raw(host.server_addr().unwrap(), None).await;
+    receive_until(&mut host, |message| match message {
+        ServerToClientMessage::ClientConnected(uuid) if uuid == silent_uuid => Some(()),
+        _ => None,
+    })
+    .await;
+
+    let reason = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::ClientDisconnected(uuid, reason) if uuid == silent_uuid => {
+            Some(reason)
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(reason, DisconnectReason::Timeout);
+
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn stalled_client_is_disconnected() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            client_queue_capacity: 256,
+            // make sure the client isnt disconnected for not answering pings instead
+            ping_timeout: Duration::from_secs(60 * 60),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut stalled = TcpTransport::connect(host.server_addr().unwrap(), true)
+        .await
+        .unwrap();
+    stalled
+        .send(ClientToServerMessage::Join {
+            auth_token: None,
+            reconnect_token: None,
+            codec: CodecKind::Cbor,
+        })
+        .await
+        .unwrap();
+    let ServerToClientMessage::Handshake {
+        uuid: stalled_uuid, ..
+    } = stalled.receive().await.unwrap()
+    else {
+        panic!("the first message wasnt a handshake");
+    };
+
+    let text = "a".repeat(MAX_CHAT_MESSAGE_LENGTH);
+    tokio::time::timeout(Duration::from_secs(30), async {
+        loop {
+            for _ in 0..64 {
+                host.send_message(ClientToServerMessage::Chat(text.clone()))
+                    .unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+            if host.drain().any(|message| {
+                matches!(message, Ok(ServerToClientMessage::ClientDisconnected(uuid, _)) if uuid == stalled_uuid)
+            }) {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("the stalled client was never disconnected");
+
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn recording_replays_received_messages() {
+    let path = std::env::temp_dir().join(format!("multiplayer-{}.log", uuid::Uuid::new_v4()));
+    let mut host = host().await;
+    host.record(&path).unwrap();
+    let mut client = connect(&host).await;
+    let client_uuid = client.uuid();
+
+    client
+        .send_message(ClientToServerMessage::Chat("hello".into()))
+        .unwrap();
+    receive_until(&mut host, |message| match message {
+        ServerToClientMessage::Chat(uuid, text) if uuid == client_uuid => Some(text),
+        _ => None,
+    })
+    .await;
+    client.disconnect().await;
+    host.disconnect().await;
+
+    // one entry per line
+    let bytes = std::fs::read(&path).unwrap();
+    assert!(bytes.ends_with(b"\n"));
+    let logged = multiplayer_game::recording::replay_log(&path)
+        .unwrap()
+        .collect::<Vec<_>>();
+    assert!(!logged.is_empty());
+    assert!(logged
+        .windows(2)
+        .all(|entries| entries[0].0 <= entries[1].0));
+
+    let mut replay = Client::replay(&path).await.unwrap();
+    let text = receive_until(&mut replay, |message| match message {
+        ServerToClientMessage::Chat(uuid, text) if uuid == client_uuid => Some(text),
+        _ => None,
+    })
+    .await;
+    assert_eq!(text, "hello");
+
+    replay.disconnect().await;
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn host_can_kick_clients() {
+    let mut host = host().await;
+    let mut a = connect(&host).await;
+    let mut b = connect(&host).await;
+    let b_uuid = b.uuid();
+
+    // b would be removed before the chat is broadcast if the kick was accepted
+    a.send_reliable(ClientToServerMessage::Kick(b_uuid))
+        .await
+        .unwrap();
+    host.send_message(ClientToServerMessage::Chat("hello".into()))
+        .unwrap();
+    receive_until(&mut b, |message| match message {
+        ServerToClientMessage::Chat(..) => Some(()),
+        ServerToClientMessage::Kicked => panic!("a client that isnt the host kicked another"),
+        _ => None,
+    })
+    .await;
+
+    host.send_message(ClientToServerMessage::Kick(b_uuid))
+        .unwrap();
+
+    receive_until(&mut b, |message| match message {
+        ServerToClientMessage::Kicked => Some(()),
+        _ => None,
+    })
+    .await;
+    let reason = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::ClientDisconnected(uuid, reason) if uuid == b_uuid => Some(reason),
+        _ => None,
+    })
+    .await;
+    assert_eq!(reason, DisconnectReason::Kicked);
+    assert!(a.is_connected());
+    assert_eq!(b.connection_state(), ConnectionState::Kicked);
+
+    a.disconnect().await;
+    b.disconnect().await;
+    host.disconnect().await;
+}
+
+async fn host_with_token(token: &str) -> Client {
+    Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            auth_token: Some(token.into()),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn correct_token_is_accepted() {
+    let mut host = host_with_token("secret").await;
+    let mut client = Client::connect_with_token(host.server_addr().unwrap(), "secret")
+        .await
+        .unwrap();
+    let client_uuid = client.uuid();
+
+    receive_until(&mut host, |message| match message {
+        ServerToClientMessage::ClientConnected(uuid) if uuid == client_uuid => Some(()),
+        _ => None,
+    })
+    .await;
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn wrong_token_is_rejected() {
+    let mut host = host_with_token("secret").await;
+    let result = Client::connect_with_token(host.server_addr().unwrap(), "wrong").await;
+    assert!(matches!(result, Err(ConnectError::Unauthorized)));
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn circles_are_clamped_to_custom_world_bounds() {
+    let world_bounds = WorldBounds {
+        min: cgmath::vec2(-10.0, -10.0),
+        max: cgmath::vec2(10.0, 10.0),
+    };
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            world_bounds,
+            spawn_radius: 5.0,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(host.world_bounds(), world_bounds);
+    let mut client = connect(&host).await;
+    assert_eq!(client.world_bounds(), world_bounds);
+    let client_uuid = client.uuid();
+
+    let circle = Circle {
+        position: cgmath::vec2(100.0, -100.0),
+        color: cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+        shape: Shape::Circle { radius: 0.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.1,
+    };
+    client
+        .send_message(ClientToServerMessage::PlayerChanged(
+            SPAWN_CIRCLE_ID,
+            circle,
+        ))
+        .unwrap();
+    let received = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, _), received)
+            if uuid == client_uuid && received.color == circle.color =>
+        {
+            Some(received)
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(received.position, cgmath::vec2(10.0, -10.0));
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn full_server_rejects_clients() {
+    // the host takes up one of the places
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            max_players: Some(2),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut client = connect(&host).await;
+
+    let result =
+        Client::connect_with_config(host.server_addr().unwrap(), client_config(&host)).await;
+    assert!(matches!(result, Err(ConnectError::Rejected(_))));
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn mismatched_codec_is_rejected() {
+    let mut host = host().await;
+    let config = ClientConfig {
+        codec: CodecKind::Json,
+        ..client_config(&host)
+    };
+    let result = Client::connect_with_config(host.server_addr().unwrap(), config).await;
+    assert!(matches!(result, Err(ConnectError::Rejected(_))));
+    host.disconnect().await;
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+async fn clients_can_use_another_codec() {
+    let config = ServerConfig {
+        codec: CodecKind::Json,
+        ..Default::default()
+    };
+    let (server, _) = run_server("127.0.0.1:0".parse().unwrap(), config)
+        .await
+        .unwrap();
+    #[cfg(feature = "test-transport")]
+    let config = ClientConfig {
+        memory: Some(server.memory_connector()),
+        codec: CodecKind::Json,
+        ..Default::default()
+    };
+    #[cfg(not(feature = "test-transport"))]
+    let config = ClientConfig {
+        codec: CodecKind::Json,
+        ..Default::default()
+    };
+    let mut first = Client::connect_with_config(server.local_addr(), config.clone())
+        .await
+        .unwrap();
+    let mut second = Client::connect_with_config(server.local_addr(), config)
+        .await
+        .unwrap();
+
+    first
+        .send_message(ClientToServerMessage::SetName("Alice".into()))
+        .unwrap();
+    let first_uuid = first.uuid();
+    receive_until(&mut second, |message| match message {
+        ServerToClientMessage::NameChanged(uuid, name) if uuid == first_uuid => Some(name),
+        _ => None,
+    })
+    .await;
+
+    first.disconnect().await;
+    second.disconnect().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn overlong_names_are_rejected() {
+    let mut host = host().await;
+    let mut client = connect(&host).await;
+    let client_uuid = client.uuid();
+
+    client
+        .send_message(ClientToServerMessage::SetName(
+            "a".repeat(MAX_NAME_LENGTH + 1),
+        ))
+        .unwrap();
+    client
+        .send_message(ClientToServerMessage::SetName("Alice".into()))
+        .unwrap();
+    let name = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::NameChanged(uuid, name) if uuid == client_uuid => Some(name),
+        _ => None,
+    })
+    .await;
+    assert_eq!(name, "Alice");
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+async fn next_event(events: &mut UnboundedReceiver<ServerEvent>) -> ServerEvent {
+    tokio::time::timeout(RECEIVE_TIMEOUT, events.recv())
+        .await
+        .expect("timed out waiting for an event")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn server_events_follow_joins_and_leaves() {
+    let (server, mut events) = run_server("127.0.0.1:0".parse().unwrap(), ServerConfig::default())
+        .await
+        .unwrap();
+    let mut client = connect_to(&server).await;
+    let uuid = client.uuid();
+    client.disconnect().await;
+
+    assert_eq!(
+        next_event(&mut events).await,
+        ServerEvent::ClientJoined(uuid)
+    );
+    assert_eq!(
+        next_event(&mut events).await,
+        ServerEvent::ClientLeft(uuid, DisconnectReason::Quit)
+    );
+
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn message_handlers_run_until_disconnected() {
+    let mut host = host().await;
+    let mut client = connect(&host).await;
+    let host_uuid = host.uuid();
+
+    let (names, mut received_names) = tokio::sync::mpsc::unbounded_channel();
+    let handler = client.on_message(move |message| {
+        if let ServerToClientMessage::NameChanged(uuid, name) = message {
+            _ = names.send((uuid, name));
+        }
+    });
+    assert!(matches!(client.get_message(), Some(Err(_))));
+
+    host.send_message(ClientToServerMessage::SetName("Host".into()))
+        .unwrap();
+    let received = tokio::time::timeout(RECEIVE_TIMEOUT, received_names.recv())
+        .await
+        .unwrap();
+    assert_eq!(received, Some((host_uuid, "Host".into())));
+
+    host.disconnect().await;
+    tokio::time::timeout(RECEIVE_TIMEOUT, handler)
+        .await
+        .expect("the handler kept running after the server shut down")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn headless_client_runs_until_server_shuts_down() {
+    let (server, _) = run_server("127.0.0.1:0".parse().unwrap(), ServerConfig::default())
+        .await
+        .unwrap();
+    let mut client = connect_to(&server).await;
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        server.shutdown().await;
+    });
+
+    let mut ticks = 0;
+    let state = tokio::time::timeout(
+        RECEIVE_TIMEOUT,
+        client.run_until_disconnected(Duration::from_millis(10), |client, _| {
+            ticks += 1;
+            _ = client.send_message(ClientToServerMessage::PlayerChanged(
+                SPAWN_CIRCLE_ID,
+                Circle {
+                    position: cgmath::vec2(ticks as f32 * 0.01, 0.0),
+                    color: cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+                    shape: Shape::Circle { radius: 0.5 },
+                    outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+                    outline_thickness: 0.0,
+                },
+            ));
+            ControlFlow::Continue(())
+        }),
+    )
+    .await
+    .expect("the client kept running after the server shut down");
+
+    assert_eq!(state, ConnectionState::ServerShutdown);
+    assert!(ticks > 1);
+}
+
+#[tokio::test]
+async fn health_follows_the_pings_and_the_connection() {
+    let config = ServerConfig {
+        ping_interval: Duration::from_millis(20),
+        ..Default::default()
+    };
+    let (server, _) = run_server("127.0.0.1:0".parse().unwrap(), config)
+        .await
+        .unwrap();
+    let mut client = connect_to(&server).await;
+    assert_eq!(client.health(), ConnectionHealth::Healthy);
+
+    // answering the pings is what keeps it healthy
+    let mut round_trips = 0;
+    tokio::time::timeout(
+        RECEIVE_TIMEOUT,
+        client.run_until_disconnected(Duration::from_millis(10), |client, messages| {
+            for message in messages {
+                match message {
+                    ServerToClientMessage::Ping(id) => {
+                        _ = client.send_message(ClientToServerMessage::Ping(id));
+                    }
+                    ServerToClientMessage::Latency(_) => round_trips += 1,
+                    _ => {}
+                }
+            }
+            assert_eq!(client.health(), ConnectionHealth::Healthy);
+            if round_trips >= 3 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        }),
+    )
+    .await
+    .expect("no pings made the round trip");
+
+    server.shutdown().await;
+    receive_until(&mut client, |message| {
+        matches!(message, ServerToClientMessage::ServerShutdown).then_some(())
+    })
+    .await;
+    assert_eq!(client.health(), ConnectionHealth::Disconnected);
+}
+
+#[tokio::test]
+async fn clients_get_distinct_default_colors() {
+    let mut host = host().await;
+    let mut a = connect(&host).await;
+    let mut b = connect(&host).await;
+
+    assert_eq!(a.default_color(), default_color(a.uuid()));
+    assert_eq!(host.default_color(), default_color(host.uuid()));
+    assert_ne!(a.default_color(), b.default_color());
+    for color in [a.default_color(), b.default_color()] {
+        assert!((0.0..=1.0).contains(&color.x));
+        assert!((0.0..=1.0).contains(&color.y));
+        assert!((0.0..=1.0).contains(&color.z));
+        assert_eq!(color.w, 1.0);
+    }
+
+    a.disconnect().await;
+    b.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn ticking_server_sends_snapshots() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            tick_rate: Some(20),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let a = connect(&host).await;
+    let mut b = connect(&host).await;
+
+    let mut circle = Circle {
+        position: cgmath::vec2(0.0, 20.0),
+        color: cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+        shape: Shape::Circle { radius: 0.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.0,
+    };
+    for step in 1..=10 {
+        circle.position.x = step as f32 * 0.1;
+        a.send_message(ClientToServerMessage::PlayerChanged(
+            SPAWN_CIRCLE_ID,
+            circle,
+        ))
+        .unwrap();
+    }
+
+    let a_uuid = a.uuid();
+    let received = receive_until(&mut b, |message| match message {
+        // only the spawn is sent right away
+        ServerToClientMessage::PlayerChanged((uuid, _), received)
+            if uuid == a_uuid && received.color == circle.color =>
+        {
+            panic!("changes should only arrive in snapshots")
+        }
+        ServerToClientMessage::Snapshot(circles) => circles
+            .into_iter()
+            .find(|&((uuid, _), circle)| uuid == a_uuid && circle.position.x > 0.95)
+            .map(|(_, circle)| circle),
+        _ => None,
+    })
+    .await;
+    assert_eq!(received, circle);
+
+    host.disconnect().await;
+}
+
+/// Frames `payload` the same way the transport does, uncompressed
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0];
+    frame.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[tokio::test]
+async fn garbage_from_the_server_is_a_protocol_error() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let uuid = uuid::Uuid::new_v4();
+        let mut handshake = vec![];
+        ciborium::into_writer(
+            &ServerToClientMessage::Handshake {
+                uuid,
+                version: PROTOCOL_VERSION,
+                world_bounds: WorldBounds::default(),
+                default_color: default_color(uuid),
+                spawn_position: cgmath::vec2(0.0, 0.0),
+                reconnect_token: uuid::Uuid::new_v4(),
+                udp_token: uuid::Uuid::new_v4(),
+            },
+            &mut handshake,
+        )
+        .unwrap();
+        stream.write_all(&frame(&handshake)).await.unwrap();
+        stream
+            .write_all(&frame(&[0xff, 0x13, 0x37, 0x00]))
+            .await
+            .unwrap();
+        // keep the connection open so the error cant be mistaken for the server closing it
+        tokio::time::sleep(RECEIVE_TIMEOUT).await;
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    receive_until(&mut client, |message| match message {
+        ServerToClientMessage::ProtocolError(_) => Some(()),
+        _ => None,
+    })
+    .await;
+    assert_eq!(client.connection_state(), ConnectionState::ProtocolError);
+    assert!(client.drain().any(|message| message.is_err()));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn mismatched_protocol_version_is_an_error() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut transport = TcpTransport::new(stream);
+        let _: ClientToServerMessage = transport.receive().await.unwrap();
+        let uuid = uuid::Uuid::new_v4();
+        transport
+            .send(ServerToClientMessage::Handshake {
+                uuid,
+                version: PROTOCOL_VERSION + 1,
+                world_bounds: WorldBounds::default(),
+                default_color: default_color(uuid),
+                spawn_position: cgmath::vec2(0.0, 0.0),
+                reconnect_token: uuid::Uuid::new_v4(),
+                udp_token: uuid::Uuid::new_v4(),
+            })
+            .await
+            .unwrap();
+        tokio::time::sleep(RECEIVE_TIMEOUT).await;
+    });
+
+    let result = Client::connect(addr).await;
+    assert!(matches!(
+        result,
+        Err(ConnectError::VersionMismatch { server, client })
+            if server == PROTOCOL_VERSION + 1 && client == PROTOCOL_VERSION
+    ));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn nested_batches_are_unpacked() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let other = uuid::Uuid::new_v4();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut transport = TcpTransport::new(stream);
+        let _: ClientToServerMessage = transport.receive().await.unwrap();
+        let uuid = uuid::Uuid::new_v4();
+        transport
+            .send(ServerToClientMessage::Handshake {
+                uuid,
+                version: PROTOCOL_VERSION,
+                world_bounds: WorldBounds::default(),
+                default_color: default_color(uuid),
+                spawn_position: cgmath::vec2(0.0, 0.0),
+                reconnect_token: uuid::Uuid::new_v4(),
+                udp_token: uuid::Uuid::new_v4(),
+            })
+            .await
+            .unwrap();
+        transport
+            .send(ServerToClientMessage::Batch(vec![
+                ServerToClientMessage::Batch(vec![ServerToClientMessage::ClientConnected(other)]),
+            ]))
+            .await
+            .unwrap();
+        tokio::time::sleep(RECEIVE_TIMEOUT).await;
+    });
+
+    let mut client = Client::connect(addr).await.unwrap();
+    let message = receive_until(&mut client, |message| match message {
+        ServerToClientMessage::Batch(_) => panic!("a batch wasnt unpacked"),
+        ServerToClientMessage::ClientConnected(uuid) => Some(uuid),
+        _ => None,
+    })
+    .await;
+    assert_eq!(message, other);
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn silent_server_times_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        // accept the connection but never send a handshake
+        let (_stream, _) = listener.accept().await.unwrap();
+        tokio::time::sleep(RECEIVE_TIMEOUT).await;
+    });
+
+    let timeout = Duration::from_millis(200);
+    let start = std::time::Instant::now();
+    let result = Client::connect_with_timeout(addr, timeout).await;
+    assert!(matches!(result, Err(ConnectError::Timeout)));
+    let elapsed = start.elapsed();
+    assert!(elapsed >= timeout && elapsed < timeout * 5, "{elapsed:?}");
+
+    server.abort();
+}
+
+async fn host_with_rate_limit(rate_limit: RateLimit) -> Client {
+    Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            rate_limit: Some(rate_limit),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn flooded_messages_are_dropped() {
+    let mut host = host_with_rate_limit(RateLimit {
+        messages_per_second: 10.0,
+        burst: 10,
+        max_dropped: u32::MAX,
+    })
+    .await;
+    let mut spammer = connect(&host).await;
+    let mut other = connect(&host).await;
+    let spammer_uuid = spammer.uuid();
+    let other_uuid = other.uuid();
+
+    for _ in 0..100 {
+        spammer
+            .send_message(ClientToServerMessage::Chat("spam".into()))
+            .unwrap();
+    }
+    // enough time for the bucket to let the last message through
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    spammer
+        .send_message(ClientToServerMessage::Chat("done".into()))
+        .unwrap();
+
+    let mut spam = 0;
+    receive_until(&mut other, |message| match message {
+        ServerToClientMessage::Chat(uuid, text) if uuid == spammer_uuid && text == "spam" => {
+            spam += 1;
+            None
+        }
+        ServerToClientMessage::Chat(uuid, text) if uuid == spammer_uuid && text == "done" => {
+            Some(())
+        }
+        _ => None,
+    })
+    .await;
+    assert!((10..20).contains(&spam), "{spam} messages got through");
+
+    other
+        .send_message(ClientToServerMessage::Chat("hello".into()))
+        .unwrap();
+    receive_until(&mut spammer, |message| match message {
+        ServerToClientMessage::Chat(uuid, text) if uuid == other_uuid => Some(text),
+        _ => None,
+    })
+    .await;
+
+    spammer.disconnect().await;
+    other.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn persistent_flooding_gets_the_client_kicked() {
+    let mut host = host_with_rate_limit(RateLimit {
+        messages_per_second: 10.0,
+        burst: 10,
+        max_dropped: 50,
+    })
+    .await;
+    let spammer = connect(&host).await;
+    let spammer_uuid = spammer.uuid();
+
+    for _ in 0..100 {
+        spammer
+            .send_message(ClientToServerMessage::Chat("spam".into()))
+            .unwrap();
+    }
+    let reason = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::ClientDisconnected(uuid, reason) if uuid == spammer_uuid => {
+            Some(reason)
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(reason, DisconnectReason::Kicked);
+    tokio::time::timeout(RECEIVE_TIMEOUT, async {
+        while spammer.connection_state() != ConnectionState::Kicked {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn new_players_spawn_apart() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            spawn_radius: 10.0,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut clients = vec![];
+    for _ in 0..4 {
+        clients.push(connect(&host).await);
+    }
+
+    let positions = clients
+        .iter()
+        .map(|client| client.spawn_position())
+        .collect::<Vec<_>>();
+    for (index, &position) in positions.iter().enumerate() {
+        assert!(position.magnitude() <= 10.0);
+        assert!(positions[..index].iter().all(|&other| other != position));
+    }
+
+    // the spawn is broadcast so the others see the new player before it moves
+    let last = clients.last().unwrap();
+    let (last_uuid, last_position) = (last.uuid(), last.spawn_position());
+    let spawned = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, _), circle) if uuid == last_uuid => {
+            Some(circle)
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(spawned.position, last_position);
+
+    for mut client in clients {
+        client.disconnect().await;
+    }
+    host.disconnect().await;
+}
+
+#[test]
+fn spawn_positions_stay_inside_the_world() {
+    let world_bounds = WorldBounds {
+        min: cgmath::vec2(1.0, 1.0),
+        max: cgmath::vec2(2.0, 2.0),
+    };
+    for _ in 0..100 {
+        let position = spawn_position(uuid::Uuid::new_v4(), 50.0, world_bounds);
+        assert!(world_bounds.contains(position));
+    }
+}
+
+#[tokio::test]
+async fn idle_clients_are_kicked() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            ping_interval: Duration::from_millis(100),
+            idle_timeout: Some(Duration::from_millis(500)),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let idle = connect(&host).await;
+    let active = connect(&host).await;
+
+    // pings are answered automatically, so only the chat keeps the active client around
+    let keep_active = async {
+        loop {
+            active
+                .send_message(ClientToServerMessage::Chat("still here".into()))
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    };
+    let reason = tokio::select! {
+        reason = receive_until(&mut host, |message| match message {
+            ServerToClientMessage::ClientDisconnected(uuid, reason) if uuid == idle.uuid() => {
+                Some(reason)
+            }
+            _ => None,
+        }) => reason,
+        () = keep_active => unreachable!(),
+    };
+    assert_eq!(reason, DisconnectReason::Idle);
+    assert!(host.peers().contains(&active.uuid()));
+    assert_eq!(active.connection_state(), ConnectionState::Connected);
+
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn server_info_can_be_queried_without_joining() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            max_players: Some(4),
+            name: "Test Server".into(),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let addr = host.server_addr().unwrap();
+    let client = connect(&host).await;
+    receive_until(&mut host, |message| {
+        (message == ServerToClientMessage::ClientConnected(client.uuid())).then_some(())
+    })
+    .await;
+
+    let info = query_server(addr).await.unwrap();
+    assert_eq!(info.player_count, 2);
+    assert_eq!(info.max_players, Some(4));
+    assert_eq!(info.name, "Test Server");
+
+    // the query must not have shown up as a player
+    let late = connect(&host).await;
+    let joined = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::ClientConnected(uuid) => Some(uuid),
+        _ => None,
+    })
+    .await;
+    assert_eq!(joined, late.uuid());
+
+    host.disconnect().await;
+}
+
+/// Joins with a bare transport, so the connection can be dropped without saying goodbye
+async fn join_raw(
+    addr: std::net::SocketAddr,
+    reconnect_token: Option<uuid::Uuid>,
+) -> (TcpTransport, uuid::Uuid, uuid::Uuid) {
+    let mut transport = TcpTransport::connect(addr, true).await.unwrap();
+    transport
+        .send(ClientToServerMessage::Join {
+            auth_token: None,
+            reconnect_token,
+            codec: CodecKind::Cbor,
+        })
+        .await
+        .unwrap();
+    let ServerToClientMessage::Handshake {
+        uuid,
+        reconnect_token,
+        ..
+    } = transport.receive().await.unwrap()
+    else {
+        panic!("the first message wasnt a handshake");
+    };
+    (transport, uuid, reconnect_token)
+}
+
+/// The uuids a client has been told about, panics when a message mentions one before it was
+/// announced
+#[derive(Default)]
+struct Announced(HashSet<uuid::Uuid>);
+
+impl Announced {
+    fn check(&mut self, message: ServerToClientMessage) {
+        match message {
+            ServerToClientMessage::Batch(messages) => {
+                for message in messages {
+                    self.check(message);
+                }
+            }
+            ServerToClientMessage::ClientConnected(uuid)
+            | ServerToClientMessage::ClientReconnected(uuid) => {
+                self.0.insert(uuid);
+            }
+            ServerToClientMessage::ClientDisconnected(uuid, _) => {
+                assert!(self.0.remove(&uuid), "{uuid} left without being announced");
+            }
+            ServerToClientMessage::PlayerChanged((owner, _), _)
+            | ServerToClientMessage::MovedTo((owner, _), _)
+            | ServerToClientMessage::PlayerDelta((owner, _), _)
+            | ServerToClientMessage::CircleRemoved((owner, _)) => {
+                assert!(
+                    self.0.contains(&owner),
+                    "{owner} moved without being announced"
+                );
+            }
+            ServerToClientMessage::Handshake { .. } => panic!("got a second handshake"),
+            _ => {}
+        }
+    }
+}
+
+#[tokio::test]
+async fn clients_only_hear_about_announced_players() {
+    let config = ServerConfig {
+        ping_interval: Duration::from_millis(50),
+        reconnect_grace: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let (server, _events) = run_server("127.0.0.1:0".parse().unwrap(), config)
+        .await
+        .unwrap();
+    let addr = server.local_addr();
+    let circle = Circle::spawn(cgmath::vec2(20.0, 20.0), cgmath::vec4(1.0, 0.0, 0.0, 1.0));
+
+    // every round one client drops without a goodbye, one leaves properly and one stays, so later
+    // joiners see clients that are waiting to reconnect
+    let mut survivors = vec![];
+    for _ in 0..3 {
+        let mut clients = vec![];
+        for _ in 0..3 {
+            let (mut transport, _, _) = join_raw(addr, None).await;
+            transport
+                .send(ClientToServerMessage::PlayerChanged(
+                    SPAWN_CIRCLE_ID,
+                    circle,
+                ))
+                .await
+                .unwrap();
+            clients.push(transport);
+        }
+        survivors.push(clients.pop().unwrap());
+        let mut leaving = clients.pop().unwrap();
+        leaving
+            .send(ClientToServerMessage::Disconnect)
+            .await
+            .unwrap();
+        _ = leaving.shutdown().await;
+        drop(clients);
+    }
+    // long enough for the dropped clients to run out of time to reconnect
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    for survivor in &mut survivors {
+        let mut announced = Announced::default();
+        _ = tokio::time::timeout(Duration::from_millis(200), async {
+            loop {
+                announced.check(survivor.receive().await.unwrap());
+            }
+        })
+        .await;
+        assert_eq!(announced.0.len(), 3);
+    }
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn reconnecting_in_time_keeps_the_uuid_and_circle() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            reconnect_grace: Some(Duration::from_secs(30)),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let addr = host.server_addr().unwrap();
+    let (mut transport, uuid, reconnect_token) = join_raw(addr, None).await;
+    let circle = Circle {
+        position: cgmath::vec2(20.0, -20.0),
+        color: cgmath::vec4(0.1, 0.2, 0.3, 1.0),
+        shape: Shape::Circle { radius: 0.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.0,
+    };
+    transport
+        .send(ClientToServerMessage::PlayerChanged(
+            SPAWN_CIRCLE_ID,
+            circle,
+        ))
+        .await
+        .unwrap();
+    receive_until(&mut host, |message| {
+        (message == ServerToClientMessage::PlayerChanged((uuid, SPAWN_CIRCLE_ID), circle))
+            .then_some(())
+    })
+    .await;
+
+    drop(transport);
+    let (mut transport, new_uuid, _) = join_raw(addr, Some(reconnect_token)).await;
+    assert_eq!(new_uuid, uuid);
+    let ServerToClientMessage::Batch(existing) = transport.receive().await.unwrap() else {
+        panic!("the existing state wasnt sent");
+    };
+    assert!(existing.iter().any(|message| matches!(
+        message,
+        ServerToClientMessage::InitialState(circles)
+            if circles.contains(&((uuid, SPAWN_CIRCLE_ID), circle))
+    )));
+    receive_until(&mut host, |message| match message {
+        ServerToClientMessage::ClientReconnected(reconnected) if reconnected == uuid => Some(()),
+        ServerToClientMessage::ClientDisconnected(disconnected, _) if disconnected == uuid => {
+            panic!("the reconnecting client was reported as gone")
+        }
+        _ => None,
+    })
+    .await;
+    assert!(host.peers().contains(&uuid));
+
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn reconnecting_too_late_joins_as_someone_new() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            ping_interval: Duration::from_millis(50),
+            reconnect_grace: Some(Duration::from_millis(200)),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let addr = host.server_addr().unwrap();
+    let (transport, uuid, reconnect_token) = join_raw(addr, None).await;
+
+    drop(transport);
+    let reason = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::ClientDisconnected(disconnected, reason) if disconnected == uuid => {
+            Some(reason)
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(reason, DisconnectReason::Error);
+
+    let (_transport, new_uuid, _) = join_raw(addr, Some(reconnect_token)).await;
+    assert_ne!(new_uuid, uuid);
+
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn sprites_are_shared_and_unknown_ones_rejected() {
+    let mut host = host().await;
+    let mut client = connect(&host).await;
+    let client_uuid = client.uuid();
+
+    client
+        .send_message(ClientToServerMessage::SetSprite(Some(SPRITE_COUNT)))
+        .unwrap();
+    client
+        .send_message(ClientToServerMessage::SetSprite(Some(1)))
+        .unwrap();
+    let sprite = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::SpriteChanged(uuid, sprite) if uuid == client_uuid => Some(sprite),
+        _ => None,
+    })
+    .await;
+    assert_eq!(sprite, Some(1));
+
+    // players that join later are told about the sprites that were already picked
+    let mut late = connect(&host).await;
+    let sprite = receive_until(&mut late, |message| match message {
+        ServerToClientMessage::SpriteChanged(uuid, sprite) if uuid == client_uuid => Some(sprite),
+        _ => None,
+    })
+    .await;
+    assert_eq!(sprite, Some(1));
+
+    late.disconnect().await;
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn dropped_clients_flush_their_last_update_and_quit() {
+    let mut host = host().await;
+    let config = ClientConfig {
+        // long enough that only closing the connection can send the last update
+        player_update_interval: Duration::from_secs(3600),
+        ..client_config(&host)
+    };
+    let client = Client::connect_with_config(host.server_addr().unwrap(), config)
+        .await
+        .unwrap();
+    let client_uuid = client.uuid();
+
+    let mut circle = Circle {
+        position: cgmath::vec2(20.0, 20.0),
+        color: cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+        shape: Shape::Circle { radius: 0.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.0,
+    };
+    // the first update goes out straight away, after that they wait for the interval
+    client
+        .send_message(ClientToServerMessage::PlayerChanged(
+            SPAWN_CIRCLE_ID,
+            circle,
+        ))
+        .unwrap();
+    receive_until(&mut host, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, _), received)
+            if uuid == client_uuid && received == circle =>
+        {
+            Some(())
+        }
+        _ => None,
+    })
+    .await;
+
+    circle.color = cgmath::vec4(0.0, 0.0, 1.0, 1.0);
+    client
+        .send_message(ClientToServerMessage::PlayerChanged(
+            SPAWN_CIRCLE_ID,
+            circle,
+        ))
+        .unwrap();
+    drop(client);
+
+    let mut flushed = false;
+    let reason = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, _), received)
+            if uuid == client_uuid && received == circle =>
+        {
+            flushed = true;
+            None
+        }
+        ServerToClientMessage::ClientDisconnected(uuid, reason) if uuid == client_uuid => {
+            Some(reason)
+        }
+        _ => None,
+    })
+    .await;
+    assert!(flushed);
+    assert_eq!(reason, DisconnectReason::Quit);
+
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn changing_the_update_interval_applies_to_waiting_updates() {
+    let mut host = host().await;
+    let config = ClientConfig {
+        player_update_interval: Duration::from_secs(3600),
+        ..client_config(&host)
+    };
+    let mut client = Client::connect_with_config(host.server_addr().unwrap(), config)
+        .await
+        .unwrap();
+    let client_uuid = client.uuid();
+
+    let mut circle = Circle {
+        position: cgmath::vec2(20.0, 20.0),
+        color: cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+        shape: Shape::Circle { radius: 0.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.0,
+    };
+    let changed = |circle: Circle| {
+        move |message| match message {
+            ServerToClientMessage::PlayerChanged((uuid, _), received)
+                if uuid == client_uuid && received == circle =>
+            {
+                Some(())
+            }
+            _ => None,
+        }
+    };
+    client
+        .send_message(ClientToServerMessage::PlayerChanged(
+            SPAWN_CIRCLE_ID,
+            circle,
+        ))
+        .unwrap();
+    receive_until(&mut host, changed(circle)).await;
+
+    // would otherwise wait for the hour long interval
+    circle.color = cgmath::vec4(0.0, 0.0, 1.0, 1.0);
+    client
+        .send_message(ClientToServerMessage::PlayerChanged(
+            SPAWN_CIRCLE_ID,
+            circle,
+        ))
+        .unwrap();
+    client.set_player_update_interval(Duration::from_millis(10));
+    receive_until(&mut host, changed(circle)).await;
+    assert_eq!(client.player_update_interval(), Duration::from_millis(10));
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn players_can_add_circles_up_to_the_limit_and_remove_them() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            max_circles_per_player: 2,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut client = connect(&host).await;
+    let client_uuid = client.uuid();
+
+    // far away from where anyone spawns so they dont get pushed around
+    let circle = |x| Circle {
+        position: cgmath::vec2(x, 20.0),
+        color: cgmath::vec4(0.0, 1.0, 0.0, 1.0),
+        shape: Shape::Circle { radius: 0.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.0,
+    };
+    client
+        .send_message(ClientToServerMessage::AddCircle(1, circle(20.0)))
+        .unwrap();
+    // the spawn circle counts towards the limit
+    client
+        .send_message(ClientToServerMessage::AddCircle(2, circle(-20.0)))
+        .unwrap();
+    client
+        .send_message(ClientToServerMessage::RemoveCircle(1))
+        .unwrap();
+
+    let mut added = None;
+    receive_until(&mut host, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, 1), received) if uuid == client_uuid => {
+            added = Some(received);
+            None
+        }
+        ServerToClientMessage::PlayerChanged((uuid, 2), _) if uuid == client_uuid => {
+            panic!("a circle over the limit was added")
+        }
+        ServerToClientMessage::CircleRemoved((uuid, 1)) if uuid == client_uuid => Some(()),
+        _ => None,
+    })
+    .await;
+    assert_eq!(added, Some(circle(20.0)));
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn saved_circles_load_back() {
+    let path = std::env::temp_dir().join(format!("multiplayer-{}.save", uuid::Uuid::new_v4()));
+    let uuid = uuid::Uuid::new_v4();
+    let circles = [0, 3]
+        .into_iter()
+        .map(|id| {
+            let circle = Circle {
+                position: cgmath::vec2(id as f32, 0.0),
+                color: cgmath::vec4(1.0, 1.0, 1.0, 1.0),
+                shape: Shape::Square { size: 1.0 },
+                outline_color: cgmath::vec3(0.0, 0.0, 0.0),
+                outline_thickness: 0.0,
+            };
+            ((uuid, id), circle)
+        })
+        .collect();
+
+    save_circles(&path, &circles).await.unwrap();
+    assert_eq!(load_circles(&path).await.unwrap(), circles);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn touching_another_player_passes_on_the_tag() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            game_mode: GameMode::Tag,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let host_uuid = host.uuid();
+    let mut client = connect(&host).await;
+    let client_uuid = client.uuid();
+
+    // the host was the only one around to be it
+    let it = receive_until(&mut client, |message| match message {
+        ServerToClientMessage::TagChanged(uuid) => Some(uuid),
+        _ => None,
+    })
+    .await;
+    assert_eq!(it, host_uuid);
+
+    host.send_message(ClientToServerMessage::MovedTo(
+        SPAWN_CIRCLE_ID,
+        client.spawn_position(),
+    ))
+    .unwrap();
+    receive_until(&mut client, |message| match message {
+        ServerToClientMessage::TagChanged(uuid) if uuid == client_uuid => Some(()),
+        _ => None,
+    })
+    .await;
+
+    let scores = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::TagScores(scores) => Some(scores),
+        _ => None,
+    })
+    .await;
+    assert!(scores
+        .iter()
+        .any(|&(uuid, score)| uuid == host_uuid && score > Duration::ZERO));
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn delta_encoding_only_sends_what_changed() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            delta_encoding: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut client = connect(&host).await;
+    let client_uuid = client.uuid();
+    receive_until(&mut host, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, _), _) if uuid == client_uuid => Some(()),
+        _ => None,
+    })
+    .await;
+
+    // far away from where anyone spawns so nothing gets pushed around
+    let position = cgmath::vec2(20.0, 20.0);
+    client
+        .send_message(ClientToServerMessage::MovedTo(SPAWN_CIRCLE_ID, position))
+        .unwrap();
+    let delta = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::PlayerDelta(key, delta) if key == (client_uuid, SPAWN_CIRCLE_ID) => {
+            Some(delta)
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(
+        delta,
+        CircleDelta {
+            position: Some(position),
+            ..Default::default()
+        }
+    );
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn udp_clients_receive_deltas() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            delta_encoding: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut client = Client::connect_with_config(
+        host.server_addr().unwrap(),
+        ClientConfig {
+            transport: TransportKind::Udp,
+            ..client_config(&host)
+        },
+    )
+    .await
+    .unwrap();
+    let host_uuid = host.uuid();
+    let client_uuid = client.uuid();
+
+    // the server only knows where to send datagrams once the client has sent one
+    client
+        .send_message(ClientToServerMessage::MovedTo(
+            SPAWN_CIRCLE_ID,
+            cgmath::vec2(-20.0, -20.0),
+        ))
+        .unwrap();
+    receive_until(&mut host, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, _), _)
+        | ServerToClientMessage::PlayerDelta((uuid, _), _)
+            if uuid == client_uuid =>
+        {
+            Some(())
+        }
+        _ => None,
+    })
+    .await;
+
+    // far away from where anyone spawns so nothing gets pushed around
+    let position = cgmath::vec2(20.0, 20.0);
+    host.send_message(ClientToServerMessage::MovedTo(SPAWN_CIRCLE_ID, position))
+        .unwrap();
+    let delta = receive_until(&mut client, |message| match message {
+        ServerToClientMessage::PlayerDelta(key, delta) if key == (host_uuid, SPAWN_CIRCLE_ID) => {
+            Some(delta)
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(delta.position, Some(position));
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn bandwidth_is_reported_per_client() {
+    let (server, mut events) = run_server(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            bandwidth_report_interval: Some(Duration::from_millis(50)),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut client = connect_to(&server).await;
+    let uuid = client.uuid();
+    client
+        .send_message(ClientToServerMessage::SetName("Alice".into()))
+        .unwrap();
+
+    // the handshake and the name go out in different reports depending on timing
+    let (mut sent, mut received) = (0, 0);
+    while sent == 0 || received == 0 {
+        if let ServerEvent::Bandwidth(usage) = next_event(&mut events).await {
+            if let Some(&(_, usage)) = usage.iter().find(|&&(other, _)| other == uuid) {
+                sent += usage.sent;
+                received += usage.received;
+            }
+        }
+    }
+
+    client.disconnect().await;
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn clients_can_join_by_hostname() {
+    let mut host = host().await;
+    let port = host.server_addr().unwrap().port();
+    let mut client = Client::connect_host(&format!("localhost:{port}"))
+        .await
+        .unwrap();
+    assert_ne!(client.uuid(), host.uuid());
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn joining_a_bad_hostname_is_an_error() {
+    assert!(Client::connect_host("localhost").await.is_err());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    let error = Client::connect_host(&format!("localhost:{port}"))
+        .await
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("localhost"), "{error}");
+}
+
+#[tokio::test]
+async fn circles_with_nan_are_dropped() {
+    let mut host = host().await;
+    let mut client = connect(&host).await;
+    let client_uuid = client.uuid();
+
+    let circle = Circle {
+        position: cgmath::vec2(20.0, 20.0),
+        color: cgmath::vec4(0.0, 1.0, 0.0, 1.0),
+        shape: Shape::Circle { radius: 0.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.1,
+    };
+    for message in [
+        ClientToServerMessage::PlayerChanged(
+            SPAWN_CIRCLE_ID,
+            Circle {
+                position: cgmath::vec2(f32::NAN, 0.0),
+                ..circle
+            },
+        ),
+        ClientToServerMessage::MovedTo(SPAWN_CIRCLE_ID, cgmath::vec2(0.0, f32::INFINITY)),
+        ClientToServerMessage::AddCircle(
+            1,
+            Circle {
+                shape: Shape::Circle {
+                    radius: f32::INFINITY,
+                },
+                ..circle
+            },
+        ),
+        ClientToServerMessage::PlayerChanged(
+            SPAWN_CIRCLE_ID,
+            Circle {
+                color: cgmath::vec4(f32::NAN, 0.0, 0.0, 1.0),
+                ..circle
+            },
+        ),
+        // sent last so everything before it has been handled once it arrives
+        ClientToServerMessage::PlayerChanged(SPAWN_CIRCLE_ID, circle),
+    ] {
+        client.send_message(message).unwrap();
+    }
+
+    let received = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, id), received) if uuid == client_uuid => {
+            assert_eq!(id, SPAWN_CIRCLE_ID, "the invalid circle was added");
+            assert!(received.position.x.is_finite() && received.position.y.is_finite());
+            assert!(received.color.x.is_finite());
+            (received.position == circle.position).then_some(received)
+        }
+        ServerToClientMessage::MovedTo((uuid, _), position) if uuid == client_uuid => {
+            panic!("the invalid position {position:?} was sent on")
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(received, circle);
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn out_of_range_circles_are_clamped() {
+    let mut host = host().await;
+    let mut client = connect(&host).await;
+    let client_uuid = client.uuid();
+
+    let position = cgmath::vec2(20.0, 20.0);
+    client
+        .send_message(ClientToServerMessage::PlayerChanged(
+            SPAWN_CIRCLE_ID,
+            Circle {
+                position,
+                color: cgmath::vec4(2.0, -1.0, 0.5, 3.0),
+                shape: Shape::Circle { radius: 100.0 },
+                outline_color: cgmath::vec3(-1.0, 2.0, 0.5),
+                outline_thickness: -1.0,
+            },
+        ))
+        .unwrap();
+
+    let received = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::PlayerChanged((uuid, _), received)
+            if uuid == client_uuid && received.position == position =>
+        {
+            Some(received)
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(
+        received,
+        Circle {
+            position,
+            color: cgmath::vec4(1.0, 0.0, 0.5, 1.0),
+            shape: Shape::Circle { radius: MAX_RADIUS },
+            outline_color: cgmath::vec3(0.0, 1.0, 0.5),
+            outline_thickness: 0.0,
+        }
+    );
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn movement_is_suppressed_while_paused() {
+    let mut host = host().await;
+    let mut client = connect(&host).await;
+    let client_uuid = client.uuid();
+    // far away from where anyone spawns so they dont get pushed around
+    let moved = cgmath::vec2(20.0, 20.0);
+    let moved_back = cgmath::vec2(-20.0, -20.0);
+
+    // only the host gets to pause
+    client
+        .send_message(ClientToServerMessage::SetPaused(true))
+        .unwrap();
+    client
+        .send_message(ClientToServerMessage::MovedTo(SPAWN_CIRCLE_ID, moved))
+        .unwrap();
+    let position = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::Paused(_) => panic!("the client paused the world"),
+        ServerToClientMessage::MovedTo((uuid, _), position) if uuid == client_uuid => {
+            Some(position)
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(position, moved);
+
+    host.send_message(ClientToServerMessage::SetPaused(true))
+        .unwrap();
+    let paused = receive_until(&mut client, |message| match message {
+        ServerToClientMessage::Paused(paused) => Some(paused),
+        _ => None,
+    })
+    .await;
+    assert!(paused);
+
+    client
+        .send_message(ClientToServerMessage::MovedTo(SPAWN_CIRCLE_ID, moved_back))
+        .unwrap();
+    let corrected = receive_until(&mut client, |message| match message {
+        ServerToClientMessage::PositionCorrected(SPAWN_CIRCLE_ID, position) => Some(position),
+        _ => None,
+    })
+    .await;
+    assert_eq!(corrected, moved);
+
+    // everything but the position still changes, whether the circle is sent again or re-added
+    let circle = Circle {
+        position: moved_back,
+        color: cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+        shape: Shape::Circle { radius: 0.5 },
+        outline_color: cgmath::vec3(1.0, 1.0, 1.0),
+        outline_thickness: 0.1,
+    };
+    for (message, color) in [
+        (
+            ClientToServerMessage::PlayerChanged as fn(_, _) -> _,
+            cgmath::vec4(1.0, 0.0, 0.0, 1.0),
+        ),
+        (
+            ClientToServerMessage::AddCircle,
+            cgmath::vec4(0.0, 1.0, 0.0, 1.0),
+        ),
+    ] {
+        client
+            .send_message(message(SPAWN_CIRCLE_ID, Circle { color, ..circle }))
+            .unwrap();
+        let corrected = receive_until(&mut client, |message| match message {
+            ServerToClientMessage::PositionCorrected(SPAWN_CIRCLE_ID, position) => Some(position),
+            _ => None,
+        })
+        .await;
+        assert_eq!(corrected, moved);
+        let received = receive_until(&mut host, |message| match message {
+            ServerToClientMessage::PlayerChanged((uuid, _), received)
+                if uuid == client_uuid && received.color == color =>
+            {
+                Some(received)
+            }
+            _ => None,
+        })
+        .await;
+        assert_eq!(
+            received,
+            Circle {
+                position: moved,
+                color,
+                ..circle
+            }
+        );
+    }
+
+    host.send_message(ClientToServerMessage::SetPaused(false))
+        .unwrap();
+    receive_until(&mut client, |message| match message {
+        ServerToClientMessage::Paused(false) => Some(()),
+        _ => None,
+    })
+    .await;
+    client
+        .send_message(ClientToServerMessage::MovedTo(SPAWN_CIRCLE_ID, moved_back))
+        .unwrap();
+    let position = receive_until(&mut host, |message| match message {
+        ServerToClientMessage::MovedTo((uuid, _), position) if uuid == client_uuid => {
+            Some(position)
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(position, moved_back);
+
+    client.disconnect().await;
+    host.disconnect().await;
+}
+
+#[tokio::test]
+async fn far_away_updates_are_not_forwarded() {
+    let mut host = Client::create_local_with_config(
+        "127.0.0.1:0".parse().unwrap(),
+        ServerConfig {
+            interest_radius: Some(1.0),
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut watcher = connect(&host).await;
+    let mover = connect(&host).await;
+    let mover_uuid = mover.uuid();
+
+    // far away from where anyone spawns so nothing gets pushed around
+    let center = cgmath::vec2(20.0, 20.0);
+    watcher
+        .send_reliable(ClientToServerMessage::SetViewport(center, 1.0))
+        .await
+        .unwrap();
+    let far = cgmath::vec2(-45.0, -45.0);
+    let near = center + cgmath::vec2(1.5, 0.0);
+    for position in [far, far + cgmath::vec2(0.0, 1.0), near] {
+        mover
+            .send_message(ClientToServerMessage::MovedTo(SPAWN_CIRCLE_ID, position))
+            .unwrap();
+    }
+
+    let received = receive_until(&mut watcher, |message| {
+        let position = match message {
+            ServerToClientMessage::PlayerChanged((uuid, _), circle) if uuid == mover_uuid => {
+                circle.position
+            }
+            ServerToClientMessage::MovedTo((uuid, _), position) if uuid == mover_uuid => position,
+            _ => return None,
+        };
+        assert!(
+            position.x > -40.0,
+            "the far away update {position:?} was forwarded"
+        );
+        (position == near).then_some(position)
+    })
+    .await;
+    assert_eq!(received, near);
+
+    watcher.disconnect().await;
+    host.disconnect().await;
+}
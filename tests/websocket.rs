@@ -0,0 +1,54 @@
+#![cfg(feature = "websocket")]
+
+use multiplayer_game::{
+    client::{ClientToServerMessage, ServerToClientMessage},
+    codec::CodecKind,
+    server::{run_server, ServerConfig, ServerHandle, WebSocketListener},
+    transport::{Transport as _, WsTransport},
+};
+
+async fn server() -> ServerHandle {
+    let config = ServerConfig {
+        websocket: Some(WebSocketListener {
+            addr: "127.0.0.1:0".parse().unwrap(),
+            path: "/game".to_string(),
+        }),
+        ..Default::default()
+    };
+    let (server, _) = run_server("127.0.0.1:0".parse().unwrap(), config)
+        .await
+        .unwrap();
+    server
+}
+
+#[tokio::test]
+async fn browsers_can_join_over_websockets() {
+    let server = server().await;
+    let addr = server.websocket_addr().unwrap();
+    let mut transport = WsTransport::connect(&format!("ws://{addr}/game"))
+        .await
+        .unwrap();
+    transport
+        .send(ClientToServerMessage::Join {
+            auth_token: None,
+            reconnect_token: None,
+            codec: CodecKind::Cbor,
+        })
+        .await
+        .unwrap();
+    let message: ServerToClientMessage = transport.receive().await.unwrap();
+    assert!(matches!(message, ServerToClientMessage::Handshake { .. }));
+
+    transport.shutdown().await.unwrap();
+    server.shutdown().await;
+}
+
+#[tokio::test]
+async fn other_paths_are_turned_away() {
+    let server = server().await;
+    let addr = server.websocket_addr().unwrap();
+    assert!(WsTransport::connect(&format!("ws://{addr}/elsewhere"))
+        .await
+        .is_err());
+    server.shutdown().await;
+}
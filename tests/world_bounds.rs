@@ -0,0 +1,22 @@
+use multiplayer_game::client::WorldBounds;
+
+#[test]
+fn out_of_bounds_positions_are_clamped_to_the_edge() {
+    let bounds = WorldBounds {
+        min: cgmath::vec2(-10.0, -5.0),
+        max: cgmath::vec2(10.0, 5.0),
+    };
+    let position = cgmath::vec2(100.0, -20.0);
+    assert!(!bounds.contains(position));
+    let clamped = bounds.clamp(position);
+    assert_eq!(clamped, cgmath::vec2(10.0, -5.0));
+    assert!(bounds.contains(clamped));
+}
+
+#[test]
+fn positions_inside_the_bounds_are_unchanged() {
+    let bounds = WorldBounds::default();
+    let position = cgmath::vec2(1.0, -2.0);
+    assert!(bounds.contains(position));
+    assert_eq!(bounds.clamp(position), position);
+}